@@ -88,7 +88,6 @@ fn gen_types(input_file: &Path, output_file: &Path, type_name: Option<&str>) {
 fn gen_mod(gen_path: &Path) {
 	let m = std::fs::read_dir(gen_path)
 		.unwrap()
-		.into_iter()
 		.filter_map(Result::ok)
 		.map(|t| t.path())
 		.map(|path: PathBuf| {
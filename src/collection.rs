@@ -24,6 +24,7 @@ pub enum CollectionType {
 	Item,
 	Spell,
 	Monster,
+	Rule,
 }
 
 pub const COLLECTIONS: &[Collection] = &[
@@ -51,13 +52,47 @@ pub const COLLECTIONS: &[Collection] = &[
 		collections: &["monster"],
 		type_: CollectionType::Monster,
 	},
+	Collection {
+		commands: &[
+			"rule",
+			"action",
+			"sense",
+			"skill",
+			"variantrule",
+			"condition",
+		],
+		urls: &[
+			"actions",
+			"senses",
+			"skills",
+			"variantrules",
+			"conditionsdiseases",
+		],
+		collections: &[
+			"action",
+			"sense",
+			"skill",
+			"variantrule",
+			"condition",
+			"disease",
+		],
+		type_: CollectionType::Rule,
+	},
+	Collection {
+		// DMG treasure tables, they are not searchable and only used by `/loot`
+		commands: &["loot"],
+		urls: &["loot"],
+		collections: &[],
+		type_: CollectionType::Item,
+	},
 ];
 
 lazy_static! {
 		// Different view on the above meta that allows quick lookups by different parameters
 		pub static ref COMMANDS: HashMap<CommandName, &'static Collection> = {
 			let mut map = HashMap::new();
-			for item in COLLECTIONS {
+			// Commands of the collections without searchable data are handled separately, like `/loot`
+			for item in COLLECTIONS.iter().filter(|item| !item.collections.is_empty()) {
 				for command in item.commands {
 					map.insert(*command, item);
 				}
@@ -1,19 +1,102 @@
+use regex::Regex;
 use std::str::FromStr;
 use teloxide::utils::command::{BotCommands, CommandDescription, CommandDescriptions, ParseError};
 
 use crate::{
 	collection::{Collection, COMMANDS},
-	format::{roll::roll_dice, utils::HtmlEscapable},
+	format::{
+		character::{get_context_key, is_roll_context_key},
+		locale::{Lang, Msg},
+		loot::TreasureKind,
+		monster::parse_cr,
+		resources::Rest,
+		roll::roll_dice,
+		settings::Settings,
+		tracker::{CONDITIONS, MAX_SPAWN},
+		utils::HtmlEscapable,
+	},
+	storage::Filter,
 };
 
 #[derive(PartialEq, Eq, Debug, Clone)]
 pub enum RollBotCommands {
 	Help(HelpOptions),
+	// The dice expression, rolled when the reply is sent
 	Roll(String),
 	Stats,
+	Loot(LootOptions),
+	Random(RandomOptions),
+	Spawn((usize, String)),
+	Hp(HpOptions),
+	Cond(CondOptions),
+	Combat(CombatOptions),
+	Character(CharacterCommand),
+	Slots(SlotsOptions),
+	Rest(Rest),
+	// Shows the current language without an argument
+	Lang(Option<Lang>),
+	Settings(SettingsOptions),
+	// Only for the users from `ROLL_BOT_ADMINS`, not listed in the menu
+	Admin(AdminCommand),
+	// Export or delete the messages logged for the user
+	MyData,
+	ForgetMe,
+	// Several commands in one message, never nested
+	Multi(Vec<RollBotCommands>),
 	Query((&'static Collection, String)),
+	// Full-text search across spells, items and monsters
+	Find(String),
+	RollTable((&'static Collection, usize, String)),
 	Echo(String),
-	Error(String),
+	Error(Msg),
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum SettingsOptions {
+	Show,
+	// "/settings die 100", see `Settings::set` for the keys
+	Set { key: String, value: String },
+}
+
+impl FromStr for SettingsOptions {
+	type Err = Msg;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let Some((key, value)) = s.trim().split_once(' ') else {
+			return match s.trim() {
+				"" => Ok(Self::Show),
+				_ => Err(Msg::SettingsUsage),
+			};
+		};
+		let key = key.to_lowercase();
+		// Check the value right away, so the error is shown before anything is saved
+		Settings::default().set(&key, value)?;
+		Ok(Self::Set {
+			key,
+			value: value.trim().to_owned(),
+		})
+	}
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum AdminCommand {
+	// Fetches the data right away, even if it is up to date
+	Update,
+	Status,
+	Rollback,
+}
+
+impl FromStr for AdminCommand {
+	type Err = ();
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.trim().to_lowercase().as_str() {
+			"update" => Ok(Self::Update),
+			"" | "status" => Ok(Self::Status),
+			"rollback" => Ok(Self::Rollback),
+			_ => Err(()),
+		}
+	}
 }
 
 #[derive(PartialEq, Eq, Debug, Clone)]
@@ -34,6 +117,256 @@ impl FromStr for HelpOptions {
 	}
 }
 
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct LootOptions {
+	pub cr: i64,
+	pub kind: TreasureKind,
+}
+
+impl FromStr for LootOptions {
+	type Err = ();
+
+	// Accepts any order of "cr:5" (or just "5") and "hoard"/"individual"
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let mut options = Self {
+			cr: 0,
+			kind: TreasureKind::Individual,
+		};
+		for word in s.split_whitespace() {
+			let word = word.to_lowercase();
+			match word.as_str() {
+				"hoard" | "h" => options.kind = TreasureKind::Hoard,
+				"individual" | "i" => options.kind = TreasureKind::Individual,
+				_ => {
+					let cr = word.strip_prefix("cr:").unwrap_or(&word);
+					// Fractional challenge ratings are all in the lowest tier
+					options.cr = if cr.contains('/') {
+						0
+					} else {
+						cr.parse().ok().filter(|cr| *cr >= 0).ok_or(())?
+					};
+				}
+			}
+		}
+		Ok(options)
+	}
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct RandomOptions {
+	pub collection: &'static Collection,
+	pub env: Option<String>,
+	// Inclusive range in 1/8 units, see `parse_cr`
+	pub cr: Option<(u32, u32)>,
+}
+
+impl FromStr for RandomOptions {
+	type Err = ();
+
+	// "monster env:forest cr:1-3", the collection defaults to monsters
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let mut options = Self {
+			collection: COMMANDS.get("monster").copied().ok_or(())?,
+			env: None,
+			cr: None,
+		};
+		for (i, word) in s.split_whitespace().enumerate() {
+			let word = word.to_lowercase();
+			if let Some(env) = word.strip_prefix("env:") {
+				options.env = Some(env.to_owned());
+			} else if let Some(cr) = word.strip_prefix("cr:") {
+				options.cr = Some(match cr.split_once('-') {
+					Some((min, max)) => (parse_cr(min).ok_or(())?, parse_cr(max).ok_or(())?),
+					None => {
+						let cr = parse_cr(cr).ok_or(())?;
+						(cr, cr)
+					}
+				});
+			} else if let (0, Some(collection)) = (i, COMMANDS.get(word.as_str())) {
+				options.collection = *collection;
+			} else {
+				return Err(());
+			}
+		}
+		Ok(options)
+	}
+}
+
+impl RandomOptions {
+	// Environments are lowercase in the data, "cr_eighths" is added when the data is saved
+	pub fn filter(&self) -> Filter<'static> {
+		let mut filter = Filter::new();
+		if let Some(env) = &self.env {
+			filter = filter.contains("environment", env.as_str());
+		}
+		if let Some((min, max)) = self.cr {
+			filter = filter
+				.ge("cr_eighths", min as i64)
+				.lt("cr_eighths", max as i64 + 1);
+		}
+		filter
+	}
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum HpChange {
+	Delta(i64),
+	Set(i64),
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct HpOptions {
+	pub name: String,
+	pub change: HpChange,
+}
+
+impl FromStr for HpOptions {
+	type Err = ();
+
+	// "goblin2 -7" deals damage, "goblin2 +3" heals and "Bob 30" sets HP
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let (name, value) = s.trim().rsplit_once(' ').ok_or(())?;
+		let amount = value.parse::<i64>().map_err(|_| ())?;
+		let change = if value.starts_with(['+', '-']) {
+			HpChange::Delta(amount)
+		} else {
+			HpChange::Set(amount)
+		};
+		let name = name.trim();
+		if name.is_empty() {
+			return Err(());
+		}
+		Ok(Self {
+			name: name.to_owned(),
+			change,
+		})
+	}
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct CondOptions {
+	pub name: String,
+	pub condition: String,
+	pub add: bool,
+}
+
+impl FromStr for CondOptions {
+	type Err = ();
+
+	// "goblin2 +prone" adds a condition, "goblin2 -prone" removes it
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let (name, condition) = s.trim().rsplit_once(' ').ok_or(())?;
+		let (condition, add) = match condition.strip_prefix('-') {
+			Some(condition) => (condition, false),
+			None => (condition.strip_prefix('+').unwrap_or(condition), true),
+		};
+		let name = name.trim();
+		let condition = condition.to_lowercase();
+		if name.is_empty() || !CONDITIONS.contains(&condition.as_str()) {
+			return Err(());
+		}
+		Ok(Self {
+			name: name.to_owned(),
+			condition,
+			add,
+		})
+	}
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum CombatOptions {
+	Show,
+	End,
+}
+
+impl FromStr for CombatOptions {
+	type Err = ();
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.trim() {
+			"" => Ok(Self::Show),
+			"end" | "clear" => Ok(Self::End),
+			_ => Err(()),
+		}
+	}
+}
+
+// Commands after this one in a single message are ignored
+const MAX_COMMANDS: usize = 10;
+
+// Resources can't be spent or have more than this
+const MAX_RESOURCE: i64 = 100;
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum SlotsOptions {
+	Show,
+	Clear,
+	// Number of slots for each spell level, starting from the 1st
+	SpellSlots(Vec<i64>),
+	Set { name: String, max: i64, rest: Rest },
+	Use { name: String, amount: i64 },
+}
+
+impl FromStr for SlotsOptions {
+	type Err = ();
+
+	// "", "clear", "4 3 2", "ki 5 short", "use 3" or "use ki 2"
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let words = s.split_whitespace().collect::<Vec<_>>();
+		let parse_amount = |word: &str| match word.parse::<i64>() {
+			Ok(amount) if (0..=MAX_RESOURCE).contains(&amount) => Ok(amount),
+			_ => Err(()),
+		};
+		match words.as_slice() {
+			[] => Ok(Self::Show),
+			["clear"] => Ok(Self::Clear),
+			["use" | "spend", name] => Ok(Self::Use {
+				name: name.to_string(),
+				amount: 1,
+			}),
+			["use" | "spend", name @ .., amount] => Ok(Self::Use {
+				name: name.join(" "),
+				amount: parse_amount(amount)?,
+			}),
+			words if words.len() <= 9 && words.iter().all(|w| w.parse::<i64>().is_ok()) => words
+				.iter()
+				.map(|word| parse_amount(word))
+				.collect::<Result<Vec<_>, _>>()
+				.map(Self::SpellSlots),
+			[name @ .., max, rest @ ("short" | "long")] if !name.is_empty() => Ok(Self::Set {
+				name: name.join(" "),
+				max: parse_amount(max)?,
+				rest: if *rest == "short" {
+					Rest::Short
+				} else {
+					Rest::Long
+				},
+			}),
+			[name @ .., max] if !name.is_empty() => Ok(Self::Set {
+				name: name.join(" "),
+				max: parse_amount(max)?,
+				rest: Rest::Long,
+			}),
+			_ => Err(()),
+		}
+	}
+}
+
+// Commands which need the imported character of the user
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum CharacterCommand {
+	Show,
+	Check(String),
+	Save(String),
+	Attack(String),
+	// A roll with "@dex"-like modifiers
+	Roll {
+		roll: String,
+		// Rolled for users without a character, then "/r stealth" is a d20 with a comment
+		fallback: Option<String>,
+	},
+}
+
 impl BotCommands for RollBotCommands {
 	fn descriptions() -> CommandDescriptions<'static> {
 		CommandDescriptions::new(&[
@@ -57,6 +390,96 @@ impl BotCommands for RollBotCommands {
 				command: "monster",
 				description: "Search for a monster",
 			},
+			CommandDescription {
+				prefix: "/",
+				command: "rule",
+				description: "Search for a rule, action, sense or skill",
+			},
+			CommandDescription {
+				prefix: "/",
+				command: "find",
+				description: "Search the text of spells, items and monsters",
+			},
+			CommandDescription {
+				prefix: "/",
+				command: "loot",
+				description: "Roll a random treasure",
+			},
+			CommandDescription {
+				prefix: "/",
+				command: "random",
+				description: "Show a random monster",
+			},
+			CommandDescription {
+				prefix: "/",
+				command: "spawn",
+				description: "Spawn monsters with rolled HP",
+			},
+			CommandDescription {
+				prefix: "/",
+				command: "hp",
+				description: "Damage or heal a combatant",
+			},
+			CommandDescription {
+				prefix: "/",
+				command: "cond",
+				description: "Add or remove a condition",
+			},
+			CommandDescription {
+				prefix: "/",
+				command: "combat",
+				description: "Show the combat status",
+			},
+			CommandDescription {
+				prefix: "/",
+				command: "check",
+				description: "Roll a skill or ability check of your character",
+			},
+			CommandDescription {
+				prefix: "/",
+				command: "save",
+				description: "Roll a saving throw of your character",
+			},
+			CommandDescription {
+				prefix: "/",
+				command: "attack",
+				description: "Roll an attack of your character",
+			},
+			CommandDescription {
+				prefix: "/",
+				command: "character",
+				description: "Show your imported character",
+			},
+			CommandDescription {
+				prefix: "/",
+				command: "slots",
+				description: "Track spell slots and other resources",
+			},
+			CommandDescription {
+				prefix: "/",
+				command: "rest",
+				description: "Take a short or long rest",
+			},
+			CommandDescription {
+				prefix: "/",
+				command: "lang",
+				description: "Choose the bot language",
+			},
+			CommandDescription {
+				prefix: "/",
+				command: "settings",
+				description: "Change the chat settings",
+			},
+			CommandDescription {
+				prefix: "/",
+				command: "mydata",
+				description: "Export the data the bot logged about you",
+			},
+			CommandDescription {
+				prefix: "/",
+				command: "forgetme",
+				description: "Delete the data the bot logged about you",
+			},
 			CommandDescription {
 				prefix: "/",
 				command: "help",
@@ -66,7 +489,67 @@ impl BotCommands for RollBotCommands {
 	}
 
 	fn parse(s: &str, bot_name: &str) -> Result<Self, ParseError> {
-		let mut words = s.splitn(2, |c| c == ' ' || c == '\n');
+		// "/r 1d20+5 /r 2d6+3" is parsed as two separate commands
+		if s.trim_start().starts_with('/') {
+			if let Ok(commands) = command_parser::commands(s, bot_name) {
+				if commands.len() > 1 {
+					let mut commands = commands
+						.iter()
+						.take(MAX_COMMANDS)
+						.filter_map(|command| Self::parse_single(&command.to_text(), bot_name).ok())
+						.collect::<Vec<_>>();
+					return match commands.len() {
+						0 => Err(ParseError::UnknownCommand(s.to_owned())),
+						1 => Ok(commands.remove(0)),
+						_ => Ok(Self::Multi(commands)),
+					};
+				}
+			}
+		}
+		Self::parse_single(s, bot_name)
+	}
+
+	fn bot_commands() -> Vec<teloxide::types::BotCommand> {
+		vec![
+			teloxide::types::BotCommand::new("roll", "Roll a dice (d20 by default)"),
+			teloxide::types::BotCommand::new("spell", "Search for a spell"),
+			teloxide::types::BotCommand::new("item", "Search for an item"),
+			teloxide::types::BotCommand::new("monster", "Search for a monster"),
+			teloxide::types::BotCommand::new("rule", "Search for a rule, action, sense or skill"),
+			teloxide::types::BotCommand::new(
+				"find",
+				"Search the text of spells, items and monsters",
+			),
+			teloxide::types::BotCommand::new("loot", "Roll a random treasure"),
+			teloxide::types::BotCommand::new("random", "Show a random monster"),
+			teloxide::types::BotCommand::new("spawn", "Spawn monsters with rolled HP"),
+			teloxide::types::BotCommand::new("hp", "Damage or heal a combatant"),
+			teloxide::types::BotCommand::new("cond", "Add or remove a condition"),
+			teloxide::types::BotCommand::new("combat", "Show the combat status"),
+			teloxide::types::BotCommand::new(
+				"check",
+				"Roll a skill or ability check of your character",
+			),
+			teloxide::types::BotCommand::new("save", "Roll a saving throw of your character"),
+			teloxide::types::BotCommand::new("attack", "Roll an attack of your character"),
+			teloxide::types::BotCommand::new("character", "Show your imported character"),
+			teloxide::types::BotCommand::new("slots", "Track spell slots and other resources"),
+			teloxide::types::BotCommand::new("rest", "Take a short or long rest"),
+			teloxide::types::BotCommand::new("lang", "Choose the bot language"),
+			teloxide::types::BotCommand::new("settings", "Change the chat settings"),
+			teloxide::types::BotCommand::new("mydata", "Export the data the bot logged about you"),
+			teloxide::types::BotCommand::new(
+				"forgetme",
+				"Delete the data the bot logged about you",
+			),
+			teloxide::types::BotCommand::new("help", "Show help"),
+		]
+	}
+}
+
+impl RollBotCommands {
+	fn parse_single(s: &str, bot_name: &str) -> Result<Self, ParseError> {
+		let mut words = s.splitn(2, [' ', '\n']);
 		let mut splited = words
 			.next()
 			.expect("Command always starts with a slash (/)")
@@ -88,11 +571,107 @@ impl BotCommands for RollBotCommands {
 			"help" | "h" | "about" | "start" => Ok(RollBotCommands::Help(
 				HelpOptions::from_str(&args).map_err(|_| ParseError::UnknownCommand(cmd))?,
 			)),
-			"roll" | "r" => roll_dice(&args)
-				.map(Self::Roll)
-				.or_else(|err| Ok(Self::Error(err.to_string()))),
+			"roll" | "r" => {
+				let (roll, character_roll) = normalize_roll(&args);
+				match character_roll {
+					Some(character_roll) => Ok(Self::Character(CharacterCommand::Roll {
+						roll: character_roll,
+						fallback: Some(roll),
+					})),
+					None if has_roll_modifier(&roll) => {
+						Ok(Self::Character(CharacterCommand::Roll {
+							roll,
+							fallback: None,
+						}))
+					}
+					// Only checked here, the chat default die is not known yet
+					None => match roll_dice(&roll) {
+						Ok(_) => Ok(Self::Roll(roll)),
+						Err(err) => Ok(Self::Error(Msg::Roll(err))),
+					},
+				}
+			}
 			"stats" => Ok(Self::Stats),
+			"loot" => LootOptions::from_str(&args)
+				.map(Self::Loot)
+				.or_else(|_| Ok(Self::Error(Msg::LootUsage))),
 			"echo" => Ok(Self::Echo(args.escape_html())),
+			"random" | "rnd" => RandomOptions::from_str(&args)
+				.map(Self::Random)
+				.or_else(|_| Ok(Self::Error(Msg::RandomUsage))),
+			"spawn" => {
+				// "/spawn 4 goblin", the count is optional
+				let (count, name) = match args.split_once(' ') {
+					Some((count, name)) => match count.parse::<usize>() {
+						Ok(count) => (count, name.trim()),
+						Err(_) => (1, args.trim()),
+					},
+					None => (1, args.trim()),
+				};
+				if name.is_empty() || !(1..=MAX_SPAWN).contains(&count) {
+					Ok(Self::Error(Msg::SpawnUsage(MAX_SPAWN)))
+				} else {
+					Ok(Self::Spawn((count, name.to_owned())))
+				}
+			}
+			"hp" => HpOptions::from_str(&args)
+				.map(Self::Hp)
+				.or_else(|_| Ok(Self::Error(Msg::HpUsage))),
+			"cond" => CondOptions::from_str(&args)
+				.map(Self::Cond)
+				.or_else(|_| Ok(Self::Error(Msg::CondUsage))),
+			"combat" => CombatOptions::from_str(&args)
+				.map(Self::Combat)
+				.or_else(|_| Ok(Self::Error(Msg::CombatUsage))),
+			"character" | "char" => Ok(Self::Character(CharacterCommand::Show)),
+			"check" | "save" | "attack" if args.trim().is_empty() => {
+				Ok(Self::Error(Msg::CharacterUsage(match cmd.as_str() {
+					"check" => "check stealth",
+					"save" => "save dex",
+					_ => "attack longsword",
+				})))
+			}
+			"check" => Ok(Self::Character(CharacterCommand::Check(args))),
+			"save" => Ok(Self::Character(CharacterCommand::Save(args))),
+			"attack" => Ok(Self::Character(CharacterCommand::Attack(args))),
+			"slots" => SlotsOptions::from_str(&args)
+				.map(Self::Slots)
+				.or_else(|_| Ok(Self::Error(Msg::SlotsUsage))),
+			"rest" => match args.trim() {
+				"" | "long" => Ok(Self::Rest(Rest::Long)),
+				"short" => Ok(Self::Rest(Rest::Short)),
+				_ => Ok(Self::Error(Msg::RestUsage)),
+			},
+			"lang" | "language" => match args.trim() {
+				"" => Ok(Self::Lang(None)),
+				lang => Lang::from_str(lang)
+					.map(|lang| Self::Lang(Some(lang)))
+					.or_else(|_| Ok(Self::Error(Msg::UnknownLang))),
+			},
+			"settings" => SettingsOptions::from_str(&args)
+				.map(Self::Settings)
+				.or_else(|err| Ok(Self::Error(err))),
+			"admin" => AdminCommand::from_str(&args)
+				.map(Self::Admin)
+				.or_else(|_| Ok(Self::Error(Msg::AdminUsage))),
+			"find" => match args.trim() {
+				"" => Ok(Self::Error(Msg::FindUsage)),
+				query => Ok(Self::Find(query.to_owned())),
+			},
+			"mydata" => Ok(Self::MyData),
+			"forgetme" => Ok(Self::ForgetMe),
+			"rolltable" => {
+				// Comes from the table buttons: "/rolltable <command> <table index> <item name>"
+				let mut parts = args.splitn(3, ' ');
+				let collection = parts.next().and_then(|cmd| COMMANDS.get(cmd));
+				let index = parts.next().and_then(|index| index.parse().ok());
+				match (collection, index, parts.next()) {
+					(Some(collection), Some(index), Some(name)) => {
+						Ok(Self::RollTable((collection, index, name.to_owned())))
+					}
+					_ => Ok(Self::Error(Msg::TableNotFound)),
+				}
+			}
 			_ => {
 				if let Some(item) = COMMANDS.get(cmd.as_str()) {
 					Ok(Self::Query((item, args)))
@@ -102,16 +681,247 @@ impl BotCommands for RollBotCommands {
 			}
 		}
 	}
+}
 
-	fn bot_commands() -> Vec<teloxide::types::BotCommand> {
-		vec![
-			teloxide::types::BotCommand::new("roll", "Roll a dice (d20 by default)"),
-			teloxide::types::BotCommand::new("spell", "Search for a spell"),
-			teloxide::types::BotCommand::new("item", "Search for an item"),
-			teloxide::types::BotCommand::new("monster", "Search for a monster"),
-			teloxide::types::BotCommand::new("help", "Show help"),
-		]
+// Turns natural-language requests into the dice notation before parsing:
+// "roll with advantage plus five" → "+d20 + 5", "2 d 6 plus 3" → "2d6 + 3", "брось 2к6" → "2к6".
+// A leading skill or ability also gives a roll for the imported character: "roll stealth" → "d20+@stealth".
+// Only the leading phrase is rewritten, the comment after it is kept as it is
+fn normalize_roll(args: &str) -> (String, Option<String>) {
+	lazy_static! {
+		static ref DICE_REGEX: Regex = Regex::new(r"^\d*[dк]\d+$").unwrap();
+		static ref LEADING_DICE_REGEX: Regex = Regex::new(r"^[+-]?\d*[dDкКдД]\d+").unwrap();
+		static ref TOKEN_REGEX: Regex = Regex::new(r"\S+").unwrap();
+	}
+	const FILLERS: [&str; 10] = [
+		"roll",
+		"rolls",
+		"a",
+		"an",
+		"me",
+		"please",
+		"брось",
+		"бросок",
+		"кинь",
+		"пожалуйста",
+	];
+
+	// "d20 to hit with advantage" is already a roll with a comment
+	if LEADING_DICE_REGEX.is_match(args.trim_start()) && roll_dice(args).is_ok() {
+		return (args.to_owned(), None);
+	}
+
+	let matches = TOKEN_REGEX.find_iter(args).collect::<Vec<_>>();
+	let tokens = matches.iter().map(|m| m.as_str()).collect::<Vec<_>>();
+	let lower = tokens
+		.iter()
+		.map(|token| token.to_lowercase().replace('д', "d"))
+		.collect::<Vec<_>>();
+	let mut start = lower
+		.iter()
+		.take_while(|token| FILLERS.contains(&token.as_str()))
+		.count();
+
+	// Skills may have up to 3 words, e.g. "sleight of hand"
+	let skill = (1..=3).rev().find_map(|len| {
+		let words = tokens.get(start..start + len)?;
+		get_context_key(&words.join(" ")).map(|key| (key, words.join(" ")))
+	});
+	if let Some((_, name)) = &skill {
+		start += name.split_whitespace().count();
 	}
+
+	let is_number = |i: usize| {
+		lower
+			.get(i)
+			.is_some_and(|word| word.parse::<u16>().is_ok() || number_word(word).is_some())
+	};
+	let is_math = |i: Option<usize>| {
+		i.and_then(|i| lower.get(i)).is_some_and(|word| {
+			matches!(word.as_str(), "+" | "-" | "*" | "d" | "к")
+				|| math_word(word).is_some()
+				|| word.parse::<u16>().is_ok()
+				|| DICE_REGEX.is_match(word)
+		})
+	};
+	let is_advantage = |word: &str| advantage_word(word).is_some();
+
+	// The phrase ends at the first word that isn't a part of a roll, e.g. "к атаке"
+	let end = (start..tokens.len())
+		.find(|&i| {
+			let word = lower[i].as_str();
+			let is_roll_word = match word {
+				"with" | "с" | "со" => lower.get(i + 1).is_some_and(|next| is_advantage(next)),
+				"d" | "к" => is_number(i + 1),
+				"+" | "-" | "*" => true,
+				word => {
+					is_advantage(word)
+						|| math_word(word).is_some()
+						|| DICE_REGEX.is_match(word)
+						|| is_number(i)
+				}
+			};
+			!is_roll_word
+		})
+		.unwrap_or(tokens.len());
+
+	let mut result: Vec<String> = Vec::new();
+	let mut advantage = None;
+	for i in start..end {
+		let word = lower[i].as_str();
+		let token = match word {
+			"with" | "с" | "со" => continue,
+			word if is_advantage(word) => {
+				let dice = advantage_word(word).unwrap_or_default();
+				if skill.is_some() {
+					advantage = Some(dice);
+					continue;
+				}
+				dice.to_owned()
+			}
+			word if math_word(word).is_some() => math_word(word).unwrap_or_default().to_owned(),
+			"d" | "к" => "d".to_owned(),
+			word if DICE_REGEX.is_match(word) => word.to_owned(),
+			word => match number_word(word) {
+				Some(num) if is_math(i.checked_sub(1)) || is_math(Some(i + 1)) => num.to_string(),
+				_ => tokens[i].to_owned(),
+			},
+		};
+		result.push(token);
+	}
+
+	// Glue "2 d 6" together
+	let mut glued: Vec<String> = Vec::new();
+	let mut iter = result.into_iter().peekable();
+	while let Some(token) = iter.next() {
+		if token != "d" {
+			glued.push(token);
+			continue;
+		}
+		let mut dice = match glued.last() {
+			Some(prev) if prev.parse::<u16>().is_ok() => glued.pop().unwrap_or_default(),
+			_ => String::new(),
+		};
+		dice.push('d');
+		if let Some(next) = iter.next_if(|next| next.parse::<u16>().is_ok()) {
+			dice.push_str(&next);
+		}
+		glued.push(dice);
+	}
+	let comment = matches
+		.get(end)
+		.map(|m| args[m.start()..].trim_end())
+		.unwrap_or_default();
+	let rest = [glued.join(" ").as_str(), comment]
+		.into_iter()
+		.filter(|part| !part.is_empty())
+		.collect::<Vec<_>>()
+		.join(" ");
+
+	match skill {
+		Some((key, name)) => {
+			let dice = advantage.unwrap_or("d20");
+			let rest = if rest.is_empty() {
+				format!("\"{name}\"")
+			} else {
+				rest
+			};
+			(
+				format!("{dice} {rest}"),
+				Some(format!("{dice}+@{key} {rest}")),
+			)
+		}
+		None => (rest, None),
+	}
+}
+
+// "@dex", "@stealth" or "@prof", any other "@" is a part of the comment
+fn has_roll_modifier(roll: &str) -> bool {
+	lazy_static! {
+		static ref MODIFIER_REGEX: Regex = Regex::new(r"@(\w+)").unwrap();
+	}
+	MODIFIER_REGEX
+		.captures_iter(roll)
+		.any(|caps| is_roll_context_key(&caps[1]))
+}
+
+fn advantage_word(word: &str) -> Option<&'static str> {
+	match word {
+		"advantage" | "adv" | "преимуществом" | "преимущество" => {
+			Some("+d20")
+		}
+		"disadvantage" | "dis" | "помехой" | "помеха" => Some("-d20"),
+		_ => None,
+	}
+}
+
+fn math_word(word: &str) -> Option<&'static str> {
+	match word {
+		"plus" | "плюс" => Some("+"),
+		"minus" | "минус" => Some("-"),
+		"times" | "умножить" => Some("*"),
+		_ => None,
+	}
+}
+
+fn number_word(word: &str) -> Option<u16> {
+	const EN: [&str; 21] = [
+		"zero",
+		"one",
+		"two",
+		"three",
+		"four",
+		"five",
+		"six",
+		"seven",
+		"eight",
+		"nine",
+		"ten",
+		"eleven",
+		"twelve",
+		"thirteen",
+		"fourteen",
+		"fifteen",
+		"sixteen",
+		"seventeen",
+		"eighteen",
+		"nineteen",
+		"twenty",
+	];
+	const RU: [&str; 21] = [
+		"ноль",
+		"один",
+		"два",
+		"три",
+		"четыре",
+		"пять",
+		"шесть",
+		"семь",
+		"восемь",
+		"девять",
+		"десять",
+		"одиннадцать",
+		"двенадцать",
+		"тринадцать",
+		"четырнадцать",
+		"пятнадцать",
+		"шестнадцать",
+		"семнадцать",
+		"восемнадцать",
+		"девятнадцать",
+		"двадцать",
+	];
+	let num = EN
+		.iter()
+		.position(|w| *w == word)
+		.or_else(|| RU.iter().position(|w| *w == word))
+		.or(match word {
+			"одна" => Some(1),
+			"две" => Some(2),
+			"hundred" | "сто" => Some(100),
+			_ => None,
+		})?;
+	Some(num as u16)
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -120,6 +930,15 @@ pub struct Command<'a> {
 	arg: Option<&'a str>,
 }
 
+impl Command<'_> {
+	fn to_text(&self) -> String {
+		match self.arg {
+			Some(arg) => format!("/{} {arg}", self.cmd),
+			None => format!("/{}", self.cmd),
+		}
+	}
+}
+
 peg::parser! {
 	grammar command_parser() for str {
 		use peg::ParseLiteral;
@@ -130,8 +949,18 @@ peg::parser! {
 		rule __()
 		= [' ' | '\t']+ / ['\n']*<1,>
 
+		rule ws()
+		= [' ' | '\t' | '\n']
+
+		rule command_head()
+		= "/" ['a'..='z' | 'A'..='Z']
+
+		// Another command on the same or the next line, e.g. "/r 1d20+5 /r 2d6+3"
+		rule command_start()
+		= ws()+ command_head()
+
 		rule text() -> &'input str
-		= _ text:$(!"/" !"\n" [_]+) _ {
+		= _ !"/" !"\n" text:$((!command_start() [_])+) _ {
 			text
 		}
 
@@ -143,21 +972,14 @@ peg::parser! {
 			}
 		}
 
-		// rule not_command(bot_name: &str)
-		// = (!command(bot_name) [_])
+		// Any text before the first command
+		rule garbage()
+		= !command_head() (!command_start() [_])* ws()+
 
-		// pub rule commands(bot_name: &str) -> Vec<Command<'input>>
-		// = not_command(bot_name)* c:(command(bot_name) ** (not_command(bot_name)*)) not_command(bot_name)* {
-		// 	c
-		// }
-
-		rule commands_skip(bot_name: &str) -> Command<'input>
-		= &(command(bot_name)) c:command(bot_name) {
+		pub rule commands(bot_name: &str) -> Vec<Command<'input>>
+		= garbage()? c:(command(bot_name) ++ (ws()*)) ws()* {
 			c
 		}
-
-		pub rule commands(bot_name: &str) -> Vec<Command<'input>>
-		= commands_skip(bot_name)+
 	}
 }
 
@@ -229,31 +1051,352 @@ fn test_command_parser_rules() {
 		}])
 	);
 
-	// assert_eq!(
-	// 	command_parser::commands("/r /r", bot_name),
-	// 	Ok(vec![
-	// 		Command {
-	// 			cmd: "r",
-	// 			arg: None,
-	// 		},
-	// 		Command {
-	// 			cmd: "r",
-	// 			arg: None,
-	// 		}
-	// 	])
-	// );
-
-	// assert_eq!(
-	// 	command_parser::commands("garbage text /r /r", bot_name),
-	// 	Ok(vec![
-	// 		Command {
-	// 			cmd: "r",
-	// 			arg: None,
-	// 		},
-	// 		Command {
-	// 			cmd: "r",
-	// 			arg: None,
-	// 		}
-	// 	])
-	// );
+	assert_eq!(
+		command_parser::commands("/r /r", bot_name),
+		Ok(vec![
+			Command {
+				cmd: "r",
+				arg: None,
+			},
+			Command {
+				cmd: "r",
+				arg: None,
+			}
+		])
+	);
+
+	assert_eq!(
+		command_parser::commands("garbage text /r /r", bot_name),
+		Ok(vec![
+			Command {
+				cmd: "r",
+				arg: None,
+			},
+			Command {
+				cmd: "r",
+				arg: None,
+			}
+		])
+	);
+
+	assert_eq!(
+		command_parser::commands("/r 1d20+5 to hit /r 2d6+3 damage", bot_name),
+		Ok(vec![
+			Command {
+				cmd: "r",
+				arg: Some("1d20+5 to hit"),
+			},
+			Command {
+				cmd: "r",
+				arg: Some("2d6+3 damage"),
+			}
+		])
+	);
+
+	assert_eq!(
+		command_parser::commands("/loot cr:1/4\n/r@roll_bot d20", bot_name),
+		Ok(vec![
+			Command {
+				cmd: "loot",
+				arg: Some("cr:1/4"),
+			},
+			Command {
+				cmd: "r",
+				arg: Some("d20"),
+			}
+		])
+	);
+}
+
+#[test]
+fn test_loot_options() {
+	assert_eq!(
+		LootOptions::from_str("cr:5 hoard"),
+		Ok(LootOptions {
+			cr: 5,
+			kind: TreasureKind::Hoard,
+		})
+	);
+	assert_eq!(
+		LootOptions::from_str("1/4"),
+		Ok(LootOptions {
+			cr: 0,
+			kind: TreasureKind::Individual,
+		})
+	);
+	assert!(LootOptions::from_str("cr:lots").is_err());
+	assert!(LootOptions::from_str("cr:-5").is_err());
+}
+
+#[test]
+fn test_random_options() {
+	let opts = RandomOptions::from_str("monster env:forest cr:1/4-3").unwrap();
+	assert_eq!(opts.collection.get_default_command(), "monster");
+	assert_eq!(opts.env, Some("forest".to_owned()));
+	assert_eq!(opts.cr, Some((2, 24)));
+	assert!(RandomOptions::from_str("env:forest monster").is_err());
+}
+
+#[test]
+fn test_spawn_command() {
+	assert_eq!(
+		RollBotCommands::parse("/spawn 4 goblin", "roll_bot").ok(),
+		Some(RollBotCommands::Spawn((4, "goblin".to_owned())))
+	);
+	assert_eq!(
+		RollBotCommands::parse("/spawn adult red dragon", "roll_bot").ok(),
+		Some(RollBotCommands::Spawn((1, "adult red dragon".to_owned())))
+	);
+	assert!(matches!(
+		RollBotCommands::parse("/spawn 100 goblin", "roll_bot"),
+		Ok(RollBotCommands::Error(_))
+	));
+}
+
+#[test]
+fn test_combat_options() {
+	assert_eq!(
+		HpOptions::from_str("goblin2 -7"),
+		Ok(HpOptions {
+			name: "goblin2".to_owned(),
+			change: HpChange::Delta(-7),
+		})
+	);
+	assert_eq!(
+		HpOptions::from_str("Old Bob 30").map(|opts| opts.change),
+		Ok(HpChange::Set(30))
+	);
+	assert!(HpOptions::from_str("-7").is_err());
+	assert_eq!(
+		CondOptions::from_str("goblin2 +Prone"),
+		Ok(CondOptions {
+			name: "goblin2".to_owned(),
+			condition: "prone".to_owned(),
+			add: true,
+		})
+	);
+	assert_eq!(
+		CondOptions::from_str("goblin2 -prone").map(|opts| opts.add),
+		Ok(false)
+	);
+	assert!(CondOptions::from_str("goblin2 +sleepy").is_err());
+	assert_eq!(CombatOptions::from_str("end"), Ok(CombatOptions::End));
+}
+
+#[test]
+fn test_character_commands() {
+	assert_eq!(
+		RollBotCommands::parse("/check stealth", "roll_bot").ok(),
+		Some(RollBotCommands::Character(CharacterCommand::Check(
+			"stealth".to_owned()
+		)))
+	);
+	assert_eq!(
+		RollBotCommands::parse("/roll d20+@dex", "roll_bot").ok(),
+		Some(RollBotCommands::Character(CharacterCommand::Roll {
+			roll: "d20+@dex".to_owned(),
+			fallback: None
+		}))
+	);
+	assert_eq!(
+		RollBotCommands::parse("/r stealth", "roll_bot").ok(),
+		Some(RollBotCommands::Character(CharacterCommand::Roll {
+			roll: "d20+@stealth \"stealth\"".to_owned(),
+			fallback: Some("d20 \"stealth\"".to_owned())
+		}))
+	);
+	assert!(roll_dice("d20 \"stealth\"").is_ok());
+	assert!(roll_dice("+d20 \"sleight of hand\"").is_ok());
+	// Not a modifier of the character
+	assert_eq!(
+		RollBotCommands::parse("/r d20 to hit @bob", "roll_bot").ok(),
+		Some(RollBotCommands::Roll("d20 to hit @bob".to_owned()))
+	);
+	assert!(matches!(
+		RollBotCommands::parse("/save", "roll_bot"),
+		Ok(RollBotCommands::Error(_))
+	));
+}
+
+#[test]
+fn test_slots_options() {
+	assert_eq!(SlotsOptions::from_str(""), Ok(SlotsOptions::Show));
+	assert_eq!(
+		SlotsOptions::from_str("4 3 2"),
+		Ok(SlotsOptions::SpellSlots(vec![4, 3, 2]))
+	);
+	assert_eq!(
+		SlotsOptions::from_str("sorcery points 5 long"),
+		Ok(SlotsOptions::Set {
+			name: "sorcery points".to_owned(),
+			max: 5,
+			rest: Rest::Long,
+		})
+	);
+	assert_eq!(
+		SlotsOptions::from_str("ki 5 short").map(|opts| matches!(
+			opts,
+			SlotsOptions::Set {
+				rest: Rest::Short,
+				..
+			}
+		)),
+		Ok(true)
+	);
+	assert_eq!(
+		SlotsOptions::from_str("use 3"),
+		Ok(SlotsOptions::Use {
+			name: "3".to_owned(),
+			amount: 1,
+		})
+	);
+	assert_eq!(
+		SlotsOptions::from_str("use ki 2"),
+		Ok(SlotsOptions::Use {
+			name: "ki".to_owned(),
+			amount: 2,
+		})
+	);
+	assert!(SlotsOptions::from_str("ki 1000").is_err());
+}
+
+#[test]
+fn test_lang() {
+	assert_eq!(
+		RollBotCommands::parse("/lang", "roll_bot").ok(),
+		Some(RollBotCommands::Lang(None))
+	);
+	assert_eq!(
+		RollBotCommands::parse("/lang RU", "roll_bot").ok(),
+		Some(RollBotCommands::Lang(Some(Lang::Ru)))
+	);
+	assert!(matches!(
+		RollBotCommands::parse("/lang klingon", "roll_bot"),
+		Ok(RollBotCommands::Error(_))
+	));
+}
+
+#[test]
+fn test_settings_options() {
+	assert_eq!(SettingsOptions::from_str(""), Ok(SettingsOptions::Show));
+	assert_eq!(
+		SettingsOptions::from_str("die d100"),
+		Ok(SettingsOptions::Set {
+			key: "die".to_owned(),
+			value: "d100".to_owned(),
+		})
+	);
+	assert!(SettingsOptions::from_str("die").is_err());
+	assert!(SettingsOptions::from_str("crit sometimes").is_err());
+}
+
+#[test]
+fn test_admin_command() {
+	assert_eq!(
+		RollBotCommands::parse("/admin", "roll_bot").ok(),
+		Some(RollBotCommands::Admin(AdminCommand::Status))
+	);
+	assert_eq!(
+		RollBotCommands::parse("/admin Update", "roll_bot").ok(),
+		Some(RollBotCommands::Admin(AdminCommand::Update))
+	);
+	assert!(matches!(
+		RollBotCommands::parse("/admin reboot", "roll_bot"),
+		Ok(RollBotCommands::Error(_))
+	));
+}
+
+#[test]
+fn test_find_command() {
+	assert_eq!(
+		RollBotCommands::parse("/find items that grant darkvision", "roll_bot").ok(),
+		Some(RollBotCommands::Find(
+			"items that grant darkvision".to_owned()
+		))
+	);
+	assert!(matches!(
+		RollBotCommands::parse("/find", "roll_bot"),
+		Ok(RollBotCommands::Error(_))
+	));
+}
+
+#[test]
+fn test_privacy_commands() {
+	assert_eq!(
+		RollBotCommands::parse("/mydata", "roll_bot").ok(),
+		Some(RollBotCommands::MyData)
+	);
+	assert_eq!(
+		RollBotCommands::parse("/ForgetMe@roll_bot", "roll_bot").ok(),
+		Some(RollBotCommands::ForgetMe)
+	);
+}
+
+#[test]
+fn test_multiple_commands() {
+	assert_eq!(
+		RollBotCommands::parse("/stats /stats", "roll_bot").ok(),
+		Some(RollBotCommands::Multi(vec![
+			RollBotCommands::Stats,
+			RollBotCommands::Stats
+		]))
+	);
+	assert_eq!(
+		RollBotCommands::parse("/stats /unknown", "roll_bot").ok(),
+		Some(RollBotCommands::Stats)
+	);
+	assert!(matches!(
+		RollBotCommands::parse("/r 1d20+5 /r 2d6+3", "roll_bot"),
+		Ok(RollBotCommands::Multi(commands)) if commands.len() == 2
+	));
+}
+
+#[test]
+fn test_normalize_roll() {
+	let plain = |roll: &str| (roll.to_owned(), None);
+	assert_eq!(
+		normalize_roll("roll with advantage plus five"),
+		plain("+d20 + 5")
+	);
+	assert_eq!(normalize_roll("2 d 6 plus 3"), plain("2d6 + 3"));
+	assert_eq!(normalize_roll("two d six minus one"), plain("2d6 - 1"));
+	assert_eq!(normalize_roll("брось 2к6"), plain("2к6"));
+	assert_eq!(normalize_roll("брось 2 к 6 плюс три"), plain("2d6 + 3"));
+	assert_eq!(
+		normalize_roll("roll stealth"),
+		(
+			"d20 \"stealth\"".to_owned(),
+			Some("d20+@stealth \"stealth\"".to_owned())
+		)
+	);
+	assert_eq!(
+		normalize_roll("sleight of hand with advantage"),
+		(
+			"+d20 \"sleight of hand\"".to_owned(),
+			Some("+d20+@sleightofhand \"sleight of hand\"".to_owned())
+		)
+	);
+	assert_eq!(
+		normalize_roll("dex + 2"),
+		("d20 + 2".to_owned(), Some("d20+@dex + 2".to_owned()))
+	);
+	// Regular rolls and comments are left as they are
+	assert_eq!(
+		normalize_roll("1d20 + 5 longsword"),
+		plain("1d20 + 5 longsword")
+	);
+	assert_eq!(
+		normalize_roll("d20 to hit one goblin"),
+		plain("d20 to hit one goblin")
+	);
+	assert_eq!(normalize_roll("d20 к атаке"), plain("d20 к атаке"));
+	assert_eq!(
+		normalize_roll("d20 to hit with advantage"),
+		plain("d20 to hit with advantage")
+	);
+	assert_eq!(normalize_roll("roll d20 к атаке"), plain("d20 к атаке"));
+	assert_eq!(
+		normalize_roll("two d six plus one  to   hit"),
+		plain("2d6 + 1 to   hit")
+	);
 }
@@ -1,123 +1,351 @@
 use std::clone::Clone;
 use std::collections::HashMap;
 use std::convert::TryFrom;
-use std::convert::TryInto;
 use std::error::Error;
-use std::sync::RwLock;
-use std::time::Instant;
-
-use ejdb::bson;
-use ejdb::bson::Bson;
-use ejdb::query::{Q, QH};
-use ejdb::Database;
-use ejdb::Result as EjdbResult;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use bson::{doc, Bson, Document};
 use serde_json::Value as JsonValue;
 use simsearch::{SearchOptions, SimSearch};
+use thiserror::Error;
 
 use crate::{
 	collection::{CollectionName, COLLECTION_NAMES},
+	format::{
+		character::Character, monster::Monster, resources::Resource, settings::Settings,
+		tracker::Combatant,
+	},
 	get_unix_time,
 	metrics::{COLLECTION_ITEM_GAUGE, COLLECTION_TIMESTAMP_GAUGE},
+	privacy,
+	search::{entry_text, TextIndex},
+	stats::{DayStats, Summary, UsageStats},
+	storage::{
+		import_ejdb_dump, json_to_bson, CollectionInfo, Filter, SqliteStorage, Storage,
+		StorageError, EJDB_DUMP_EXTENSION,
+	},
 	telegram::BotError,
 };
 
 // System table should start with an underscore, so they will not be treated like D&D data collections
 const LOG_COLLECTION_NAME: &str = "_log";
+// Daily usage aggregates and the last message of each user, see `stats::UsageStats`
+const STATS_COLLECTION_NAME: &str = "_stats";
+const USERS_COLLECTION_NAME: &str = "_users";
 pub const VER_COLLECTION_NAME: &str = "_ver";
+// Per-chat combat state, a single document with all combatants for each chat
+const COMBAT_COLLECTION_NAME: &str = "_combat";
+// Combatants shown by each `/spawn` tracker, keyed by the chat and the message id
+const TRACKER_COLLECTION_NAME: &str = "_trackers";
+// Imported characters, one per user
+const CHARACTER_COLLECTION_NAME: &str = "_characters";
+// Spell slots and other resources, one document per user in each chat
+const RESOURCE_COLLECTION_NAME: &str = "_resources";
+// Per-chat preferences, see `/settings`
+const SETTINGS_COLLECTION_NAME: &str = "_settings";
+// Homebrew packs loaded during the last update, see `fetch::homebrew_from_env`
+pub const HOMEBREW_COLLECTION_NAME: &str = "_homebrew";
+// Which generation of each collection is visible, see `DndDatabase::commit`
+const ACTIVE_COLLECTION_NAME: &str = "_active";
+// Generations written by `DndDatabase::stage_collection`, the ones that were never committed are dropped on start
+const STAGED_COLLECTION_NAME: &str = "_staged";
+
+#[derive(Error, Debug)]
+pub enum SaveError {
+	#[error("Database Error {0}")]
+	Db(#[from] StorageError),
+	#[error("{collection} would shrink from {old} to {new} items, keeping the old data")]
+	Shrink {
+		collection: String,
+		old: usize,
+		new: usize,
+	},
+}
 
 pub struct DndDatabase {
 	pub cache: RwLock<HashMap<CollectionName, SimSearch<String>>>,
+	// Entry bodies for `/find`, swapped together with `cache`
+	pub text_index: RwLock<TextIndex>,
 	inner: RwLock<Inner>,
+	usage: Mutex<UsageStats>,
+	// Held by `update_combatants`, so the concurrent `/hp` and `/cond` don't overwrite each other
+	combat_lock: Mutex<()>,
 }
-// EJDB raw pointer won't change during our mutations
-unsafe impl Sync for Inner {}
 
 struct Inner {
-	db: Database,
+	storage: Box<dyn Storage>,
 	timestamp: Instant,
+	// Collection name → the name it is stored under. Collections saved before the staging was introduced are missing
+	active: HashMap<String, String>,
+	// Collection name → the version it replaced, kept for `DndDatabase::rollback`
+	previous: HashMap<String, String>,
+	// Collections replaced by the last commit, only they are rolled back
+	last_commit: Vec<String>,
 }
 
-impl DndDatabase {
-	pub fn new(path: &str) -> Result<DndDatabase, ejdb::Error> {
-		let ejdb = Database::open(path)?;
-		// Create log index
-		{
-			let coll = ejdb.collection(LOG_COLLECTION_NAME)?;
-			coll.index("timestamp").number().set()?;
-			coll.index("user_id").number().set()?;
+static LAST_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+// Collections written aside during an update, invisible until `DndDatabase::commit`
+pub struct Staging {
+	generation: u64,
+	collections: Vec<StagedCollection>,
+	// Skips the check for collections that lost most of their items
+	allow_shrink: bool,
+}
+
+struct StagedCollection {
+	name: String,
+	staged_name: String,
+	items: usize,
+}
+
+impl Default for Staging {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Staging {
+	pub fn new() -> Self {
+		let now = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.unwrap()
+			.as_millis() as u64;
+		// Two updates within a millisecond must not share the staged collections
+		LAST_GENERATION.fetch_max(now - 1, Ordering::SeqCst);
+		let generation = LAST_GENERATION.fetch_add(1, Ordering::SeqCst) + 1;
+		Self {
+			generation,
+			collections: Vec::new(),
+			allow_shrink: false,
 		}
-		{
-			let coll = ejdb.collection(VER_COLLECTION_NAME)?;
-			coll.index("ver").string(true).set()?;
-			coll.index("date").string(true).set()?;
+	}
+
+	pub fn allow_shrink(mut self, allow: bool) -> Self {
+		self.allow_shrink = allow;
+		self
+	}
+}
+
+impl DndDatabase {
+	pub fn new(path: &str) -> Result<DndDatabase, StorageError> {
+		let storage = SqliteStorage::open(path)?;
+		import_ejdb_dump(
+			&storage,
+			&Path::new(path).with_extension(EJDB_DUMP_EXTENSION),
+		)?;
+		Self::with_storage(Box::new(storage))
+	}
+
+	pub fn with_storage(storage: Box<dyn Storage>) -> Result<DndDatabase, StorageError> {
+		let mut active = HashMap::new();
+		let mut previous = HashMap::new();
+		let mut last_commit = Vec::new();
+		for doc in storage.find(ACTIVE_COLLECTION_NAME, &Filter::new())? {
+			let (Ok(name), Ok(stored_as)) = (doc.get_str("name"), doc.get_str("stored_as")) else {
+				continue;
+			};
+			active.insert(name.to_owned(), stored_as.to_owned());
+			if let Ok(prev) = doc.get_str("previous") {
+				previous.insert(name.to_owned(), prev.to_owned());
+			}
+			if doc.get_bool("last_commit").unwrap_or(false) {
+				last_commit.push(name.to_owned());
+			}
 		}
 
 		let inner = Inner {
-			db: ejdb,
+			storage,
 			timestamp: Instant::now(),
+			active,
+			previous,
+			last_commit,
 		};
+		inner.drop_abandoned();
+		let usage = inner.load_usage()?;
 
+		let (cache, text_index) = inner.get_cache(&inner.active);
 		Ok(Self {
-			cache: RwLock::new(inner.get_cache()),
+			cache: RwLock::new(cache),
+			text_index: RwLock::new(text_index),
 			inner: RwLock::new(inner),
+			usage: Mutex::new(usage),
+			combat_lock: Mutex::new(()),
 		})
 	}
 
-	pub fn save_collection(
+	// Replaces a single collection, use `Staging` to replace several at once
+	pub fn save_collection(&self, json: Vec<JsonValue>, collection: &str) -> Result<(), SaveError> {
+		let mut staging = Staging::new();
+		self.stage_collection(&mut staging, json, collection)?;
+		self.commit(staging)?;
+		Ok(())
+	}
+
+	// Writes the new data next to the current one, lookups don't see it until `commit`
+	pub fn stage_collection(
 		&self,
+		staging: &mut Staging,
 		json: Vec<JsonValue>,
 		collection: &str,
-	) -> Result<(), ejdb::Error> {
-		info!("Saving {}, {}", collection, json.len());
-		let bs: Bson = serde_json::Value::Array(json).into();
-		let arr = bs.as_array().ok_or_else(|| {
-			bson::DecoderError::Unknown(format!("{collection} field is not an array"))
-		})?;
-		let mut inner = self.inner.write().unwrap();
-		inner.timestamp = Instant::now();
-		inner.db.drop_collection(collection, true)?;
-		let coll = inner.db.collection(collection)?;
-		arr.iter()
-			.filter_map(|elem| elem.as_document())
-			.for_each(|elem| {
-				let name_source = elem.get_str("name").map(|name| {
-					elem.get_str("source")
-						.map(|source| format!("{name} ({source})"))
-						.unwrap_or_else(|_| name.to_owned())
-				});
-				let res = match name_source {
-					Ok(name_source) => {
-						let mut elem = elem.clone();
-						elem.insert("name_source", name_source);
-						coll.save(elem)
-					}
-					Err(_) => coll.save(elem),
-				};
-				if let Err(e) = res {
-					error!("Failed to save document: {}", e)
-				}
+	) -> Result<(), SaveError> {
+		info!("Staging {}, {}", collection, json.len());
+		let docs = json
+			.into_iter()
+			.filter_map(|value| match json_to_bson(value) {
+				Bson::Document(doc) => Some(doc),
+				_ => None,
+			})
+			.collect::<Vec<_>>();
+		let items = docs.len();
+		let inner = self.inner.read().unwrap();
+		// A broken mirror or a half-downloaded directory shouldn't wipe out the data.
+		// System collections like the homebrew list are replaced as is
+		let old = inner.storage.count(inner.stored_name(collection))?;
+		if !staging.allow_shrink && !collection.starts_with('_') && is_sharp_drop(old, items) {
+			return Err(SaveError::Shrink {
+				collection: collection.to_owned(),
+				old,
+				new: items,
 			});
-		coll.index("name").string(false).set()?;
-		coll.index("name_source").string(false).set()?;
-		coll.index("abbreviation").string(true).set()?;
-		coll.index("appliesTo").string(true).set()?;
+		}
 
-		COLLECTION_TIMESTAMP_GAUGE
-			.with_label_values(&[collection])
-			.set(get_unix_time() as i64);
+		let staged_name = format!("{collection}_{}", staging.generation);
+		// Recorded first, so the data is found even if the bot stops in the middle of writing it
+		inner
+			.storage
+			.insert(STAGED_COLLECTION_NAME, doc! {"name": staged_name.as_str()})?;
+		if let Err(err) = inner.write_collection(&staged_name, docs) {
+			let _ = inner.storage.drop_collection(&staged_name);
+			return Err(err.into());
+		}
+		staging.collections.push(StagedCollection {
+			name: collection.to_owned(),
+			staged_name,
+			items,
+		});
+		Ok(())
+	}
 
-		COLLECTION_ITEM_GAUGE
-			.with_label_values(&[collection])
-			.set(arr.len() as i64);
+	// Makes the staged collections visible, the replaced ones are kept for `rollback`
+	pub fn commit(&self, staging: Staging) -> Result<(), StorageError> {
+		if staging.collections.is_empty() {
+			return Ok(());
+		}
+		let (active, previous, outdated) = {
+			let inner = self.inner.read().unwrap();
+			let mut active = inner.active.clone();
+			let mut previous = inner.previous.clone();
+			let mut outdated = Vec::new();
+			for staged in &staging.collections {
+				let current = inner.stored_name(&staged.name).to_owned();
+				if let Some(prev) = previous.insert(staged.name.clone(), current) {
+					outdated.push(prev);
+				}
+				active.insert(staged.name.clone(), staged.staged_name.clone());
+			}
+			(active, previous, outdated)
+		};
+		let last_commit = staging
+			.collections
+			.iter()
+			.map(|staged| staged.name.clone())
+			.collect();
+		self.activate(active, previous, last_commit)?;
 
+		// The versions before the previous ones are not needed anymore
+		let inner = self.inner.read().unwrap();
+		for staged in &staging.collections {
+			inner.storage.delete(
+				STAGED_COLLECTION_NAME,
+				&Filter::new().eq("name", staged.staged_name.as_str()),
+			)?;
+		}
+		for name in outdated {
+			if !inner.is_in_use(&name) {
+				if let Err(err) = inner.storage.drop_collection(&name) {
+					error!("Failed to drop {}: {}", name, err);
+				}
+			}
+		}
+		for staged in &staging.collections {
+			COLLECTION_TIMESTAMP_GAUGE
+				.with_label_values(&[&staged.name])
+				.set(get_unix_time() as i64);
+			COLLECTION_ITEM_GAUGE
+				.with_label_values(&[&staged.name])
+				.set(staged.items as i64);
+		}
 		Ok(())
 	}
 
-	pub fn update_cache(&self) {
-		let inner = self.inner.read().unwrap();
-		let new_cache = inner.get_cache();
+	// Swaps the collections replaced by the last commit with their previous versions,
+	// returns how many were swapped. Rolling back twice restores the newer data
+	pub fn rollback(&self) -> Result<usize, StorageError> {
+		let (active, previous, last_commit, swapped) = {
+			let inner = self.inner.read().unwrap();
+			let mut active = inner.active.clone();
+			let mut previous = inner.previous.clone();
+			let mut swapped = 0;
+			for name in &inner.last_commit {
+				let Some(prev) = inner.previous.get(name) else {
+					continue;
+				};
+				previous.insert(name.clone(), inner.stored_name(name).to_owned());
+				active.insert(name.clone(), prev.clone());
+				swapped += 1;
+			}
+			(active, previous, inner.last_commit.clone(), swapped)
+		};
+		if swapped > 0 {
+			self.activate(active, previous, last_commit)?;
+		}
+		Ok(swapped)
+	}
+
+	// Switches lookups and the search indexes to `active` at once
+	fn activate(
+		&self,
+		active: HashMap<String, String>,
+		previous: HashMap<String, String>,
+		last_commit: Vec<String>,
+	) -> Result<(), StorageError> {
+		// The new cache is built while lookups still use the old data
+		let (new_cache, new_text_index) = {
+			let inner = self.inner.read().unwrap();
+			inner.get_cache(&active)
+		};
+
+		// Same lock order as the lookups: cache first
 		let mut cache = self.cache.write().unwrap();
+		let mut text_index = self.text_index.write().unwrap();
+		let mut inner = self.inner.write().unwrap();
+		// A restart must not see a half of the generations switched
+		let docs = active
+			.iter()
+			.map(|(name, stored_as)| {
+				let mut doc = doc! {
+					"name": name.as_str(),
+					"stored_as": stored_as.as_str(),
+					"last_commit": last_commit.contains(name)
+				};
+				if let Some(prev) = previous.get(name) {
+					doc.insert("previous", prev.as_str());
+				}
+				(Filter::new().eq("name", name.as_str()), doc)
+			})
+			.collect();
+		inner.storage.upsert_many(ACTIVE_COLLECTION_NAME, docs)?;
+		inner.active = active;
+		inner.previous = previous;
+		inner.last_commit = last_commit;
+		inner.timestamp = Instant::now();
 		*cache = new_cache;
+		*text_index = new_text_index;
+		Ok(())
 	}
 
 	pub fn get_update_timestamp(&self) -> Instant {
@@ -125,59 +353,61 @@ impl DndDatabase {
 		inner.timestamp
 	}
 
-	pub fn get_metadata(&self) -> Result<ejdb::meta::DatabaseMetadata, ejdb::Error> {
+	// Only the visible generation of each collection is listed, under the collection name
+	pub fn get_metadata(&self) -> Result<Vec<CollectionInfo>, StorageError> {
 		let inner = self.inner.read().unwrap();
-		inner.db.get_metadata()
-	}
-
-	// This is terribly inefficient, but upstream EJDB bindings does not implement distinct queries :(
-	pub fn get_all_massages(&self) -> Result<Vec<LogMessage>, ejdb::Error> {
-		let inner = self.inner.read().unwrap();
-		let coll = inner.db.collection(LOG_COLLECTION_NAME)?;
-		Ok(coll
-			.query(Q.empty(), QH.empty())
-			.find()?
-			.filter_map(Result::ok)
-			.map(|doc| doc.try_into())
-			.filter_map(Result::ok)
-			.collect())
+		let staged = inner.staged_names()?;
+		let mut collections = inner
+			.storage
+			.collections()?
+			.into_iter()
+			.filter_map(|mut info| {
+				if let Some((name, _)) = inner
+					.active
+					.iter()
+					.find(|(_, stored)| **stored == info.name)
+				{
+					info.name = name.clone();
+				} else if inner.is_in_use(&info.name)
+					|| inner.active.contains_key(&info.name)
+					|| staged.contains(&info.name)
+				{
+					return None;
+				}
+				Some(info)
+			})
+			.collect::<Vec<_>>();
+		collections.sort_by(|a, b| a.name.cmp(&b.name));
+		Ok(collections)
 	}
 
-	fn list_collections(db: &Database) -> Vec<String> {
-		db.get_metadata()
-			.unwrap()
-			.collections()
-			.map(|coll| coll.name().to_owned())
-			.filter(|coll| !coll.starts_with('_'))
-			.collect::<Vec<_>>()
-	}
-
-	fn list_items(db: &Database, collection: &str) -> Result<Vec<String>, ejdb::Error> {
-		let coll = db.collection(collection)?;
-		let res = coll
-			.query(Q.empty(), QH.field("name_source").include())
-			.find()?
-			.collect::<EjdbResult<Vec<bson::Document>>>()?;
-		Ok(res
-			.iter()
-			.filter_map(|doc| doc.get("name_source"))
-			.filter_map(|name| name.as_str())
-			.map(|name| name.to_owned())
-			.collect::<Vec<String>>())
+	pub fn get_usage_summary(&self, since: u64) -> Summary {
+		self.usage.lock().unwrap().since(since)
 	}
 
 	pub fn get_item(
 		&self,
 		collection: &str,
 		item_name: &str,
-	) -> Result<Option<bson::Document>, ejdb::Error> {
+	) -> Result<Option<Document>, StorageError> {
 		let inner = self.inner.read().unwrap();
-		let coll = inner.db.collection(collection)?;
-		coll.query(
-			Q.field("name_source").case_insensitive().eq(item_name),
-			QH.empty(),
+		inner.storage.find_one(
+			inner.stored_name(collection),
+			&Filter::new().eq_ignore_case("name_source", item_name),
 		)
-		.find_one()
+	}
+
+	pub fn get_all(&self, collection: &str) -> Result<Vec<Document>, StorageError> {
+		self.find_all(collection, &Filter::new())
+	}
+
+	pub fn find_all(
+		&self,
+		collection: &str,
+		filter: &Filter,
+	) -> Result<Vec<Document>, StorageError> {
+		let inner = self.inner.read().unwrap();
+		inner.storage.find(inner.stored_name(collection), filter)
 	}
 
 	pub fn find_one_by(
@@ -185,10 +415,207 @@ impl DndDatabase {
 		collection: &str,
 		field: &str,
 		value: &str,
-	) -> Result<Option<bson::Document>, ejdb::Error> {
+	) -> Result<Option<Document>, StorageError> {
+		let inner = self.inner.read().unwrap();
+		inner.storage.find_one(
+			inner.stored_name(collection),
+			&Filter::new().eq(field, value),
+		)
+	}
+
+	pub fn get_combatants(&self, chat_id: i64) -> Result<Vec<Combatant>, StorageError> {
+		let inner = self.inner.read().unwrap();
+		let combat = inner.storage.find_one(
+			COMBAT_COLLECTION_NAME,
+			&Filter::new().eq("chat_id", chat_id),
+		)?;
+		Ok(combat
+			.as_ref()
+			.and_then(|combat| combat.get_array("combatants").ok())
+			.map(|combatants| {
+				combatants
+					.iter()
+					.filter_map(Bson::as_document)
+					.map(Combatant::try_from)
+					.filter_map(Result::ok)
+					.collect()
+			})
+			.unwrap_or_default())
+	}
+
+	// Changes the chat combat and saves it, the result of `update` is returned
+	pub fn update_combatants<T>(
+		&self,
+		chat_id: i64,
+		update: impl FnOnce(&mut Vec<Combatant>) -> T,
+	) -> Result<T, StorageError> {
+		let _lock = self.combat_lock.lock().unwrap();
+		let mut combatants = self.get_combatants(chat_id)?;
+		let result = update(&mut combatants);
+		self.save_combatants(chat_id, &combatants)?;
+		Ok(result)
+	}
+
+	// The combat and its trackers are gone, the buttons of old trackers stop working
+	pub fn end_combat(&self, chat_id: i64) -> Result<(), StorageError> {
+		self.update_combatants(chat_id, Vec::clear)?;
 		let inner = self.inner.read().unwrap();
-		let coll = inner.db.collection(collection)?;
-		coll.query(Q.field(field).eq(value), QH.empty()).find_one()
+		inner.storage.delete(
+			TRACKER_COLLECTION_NAME,
+			&Filter::new().eq("chat_id", chat_id),
+		)?;
+		Ok(())
+	}
+
+	pub fn save_tracker(
+		&self,
+		chat_id: i64,
+		message_id: i32,
+		title: &str,
+		names: &[String],
+	) -> Result<(), StorageError> {
+		let inner = self.inner.read().unwrap();
+		let names = names.iter().cloned().map(Bson::String).collect::<Vec<_>>();
+		inner.storage.insert(
+			TRACKER_COLLECTION_NAME,
+			doc! {
+				"chat_id": chat_id,
+				"message_id": message_id,
+				"title": title,
+				"names": names
+			},
+		)
+	}
+
+	// The title and the combatant names of the tracker
+	pub fn get_tracker(
+		&self,
+		chat_id: i64,
+		message_id: i32,
+	) -> Result<Option<(String, Vec<String>)>, StorageError> {
+		let inner = self.inner.read().unwrap();
+		let tracker = inner.storage.find_one(
+			TRACKER_COLLECTION_NAME,
+			&Filter::new()
+				.eq("chat_id", chat_id)
+				.eq("message_id", message_id),
+		)?;
+		Ok(tracker.and_then(|tracker| {
+			let title = tracker.get_str("title").ok()?.to_owned();
+			let names = tracker
+				.get_array("names")
+				.ok()?
+				.iter()
+				.filter_map(Bson::as_str)
+				.map(str::to_owned)
+				.collect();
+			Some((title, names))
+		}))
+	}
+
+	fn save_combatants(&self, chat_id: i64, combatants: &[Combatant]) -> Result<(), StorageError> {
+		let inner = self.inner.read().unwrap();
+		let combatants = combatants
+			.iter()
+			.map(|combatant| Bson::Document(combatant.into()))
+			.collect::<Vec<_>>();
+		let doc = doc! {
+			"chat_id": chat_id,
+			"combatants": combatants
+		};
+		inner.storage.upsert(
+			COMBAT_COLLECTION_NAME,
+			&Filter::new().eq("chat_id", chat_id),
+			doc,
+		)
+	}
+
+	pub fn get_character(&self, user_id: i64) -> Result<Option<Character>, StorageError> {
+		let inner = self.inner.read().unwrap();
+		let doc = inner.storage.find_one(
+			CHARACTER_COLLECTION_NAME,
+			&Filter::new().eq("user_id", user_id),
+		)?;
+		Ok(doc
+			.as_ref()
+			.and_then(|doc| doc.get_document("character").ok())
+			.and_then(|character| Character::try_from(character).ok()))
+	}
+
+	pub fn save_character(&self, user_id: i64, character: &Character) -> Result<(), StorageError> {
+		let inner = self.inner.read().unwrap();
+		let doc = doc! {
+			"user_id": user_id,
+			"character": Document::from(character)
+		};
+		inner.storage.upsert(
+			CHARACTER_COLLECTION_NAME,
+			&Filter::new().eq("user_id", user_id),
+			doc,
+		)
+	}
+
+	pub fn get_settings(&self, chat_id: i64) -> Result<Settings, StorageError> {
+		let inner = self.inner.read().unwrap();
+		let doc = inner.storage.find_one(
+			SETTINGS_COLLECTION_NAME,
+			&Filter::new().eq("chat_id", chat_id),
+		)?;
+		Ok(doc.as_ref().map(Settings::from).unwrap_or_default())
+	}
+
+	pub fn save_settings(&self, chat_id: i64, settings: &Settings) -> Result<(), StorageError> {
+		let inner = self.inner.read().unwrap();
+		let mut doc = Document::from(settings);
+		doc.insert("chat_id", chat_id);
+		inner.storage.upsert(
+			SETTINGS_COLLECTION_NAME,
+			&Filter::new().eq("chat_id", chat_id),
+			doc,
+		)
+	}
+
+	pub fn get_resources(&self, chat_id: i64, user_id: i64) -> Result<Vec<Resource>, StorageError> {
+		let inner = self.inner.read().unwrap();
+		let doc = inner.storage.find_one(
+			RESOURCE_COLLECTION_NAME,
+			&Filter::new().eq("chat_id", chat_id).eq("user_id", user_id),
+		)?;
+		Ok(doc
+			.as_ref()
+			.and_then(|doc| doc.get_array("resources").ok())
+			.map(|resources| {
+				resources
+					.iter()
+					.filter_map(Bson::as_document)
+					.map(Resource::try_from)
+					.filter_map(Result::ok)
+					.collect()
+			})
+			.unwrap_or_default())
+	}
+
+	pub fn save_resources(
+		&self,
+		chat_id: i64,
+		user_id: i64,
+		resources: &[Resource],
+	) -> Result<(), StorageError> {
+		let inner = self.inner.read().unwrap();
+		let resources = resources
+			.iter()
+			.map(|resource| Bson::Document(resource.into()))
+			.collect::<Vec<_>>();
+		let doc = doc! {
+			"chat_id": chat_id,
+			"user_id": user_id,
+			"resources": resources
+		};
+		inner.storage.upsert(
+			RESOURCE_COLLECTION_NAME,
+			&Filter::new().eq("chat_id", chat_id).eq("user_id", user_id),
+			doc,
+		)
 	}
 
 	pub fn log_message(
@@ -199,19 +626,65 @@ impl DndDatabase {
 		response: &Result<Option<String>, BotError>,
 		latency: u64,
 	) {
+		let user_id = privacy::log_user_id(user_id);
 		match self.try_log_message(user_id, chat_type, request, response, latency) {
 			Ok(_) => {}
 			Err(err) => error!("Failed to save message to db: {}", err),
 		}
 	}
 
+	// Everything logged about the user, see `/mydata`
+	pub fn get_user_log(&self, user_id: i64) -> Result<Vec<Document>, StorageError> {
+		let inner = self.inner.read().unwrap();
+		let mut messages = Vec::new();
+		for user_id in privacy::logged_user_ids(user_id) {
+			messages.extend(
+				inner
+					.storage
+					.find(LOG_COLLECTION_NAME, &Filter::new().eq("user_id", user_id))?,
+			);
+		}
+		Ok(messages)
+	}
+
+	// Deletes the user's log entries, see `/forgetme`. Returns how many were deleted
+	pub fn forget_user(&self, user_id: i64) -> Result<usize, StorageError> {
+		let inner = self.inner.read().unwrap();
+		let mut usage = self.usage.lock().unwrap();
+		let mut deleted = 0;
+		for user_id in privacy::logged_user_ids(user_id) {
+			let filter = Filter::new().eq("user_id", user_id);
+			deleted += inner.storage.delete(LOG_COLLECTION_NAME, &filter)?;
+			inner.storage.delete(USERS_COLLECTION_NAME, &filter)?;
+			usage.forget(user_id);
+		}
+		Ok(deleted)
+	}
+
+	// Deletes the log entries older than `timestamp`, the daily usage aggregates are kept.
+	// Users not seen since then are forgotten too, so the total unique users only cover the retention period
+	pub fn purge_log(&self, timestamp: u64) -> Result<usize, StorageError> {
+		let inner = self.inner.read().unwrap();
+		let mut usage = self.usage.lock().unwrap();
+		let deleted = inner.storage.delete(
+			LOG_COLLECTION_NAME,
+			&Filter::new().lt("timestamp", timestamp as i64),
+		)?;
+		inner.storage.delete(
+			USERS_COLLECTION_NAME,
+			&Filter::new().lt("last_seen", timestamp as i64),
+		)?;
+		usage.forget_before(timestamp);
+		Ok(deleted)
+	}
+
 	pub fn get_version(&self) -> Result<Option<String>, Box<dyn Error>> {
 		let inner = self.inner.read().unwrap();
-		let coll = inner.db.collection(VER_COLLECTION_NAME)?;
-		let results = coll.query(Q.empty(), QH.empty()).find()?;
+		let results = inner
+			.storage
+			.find(inner.stored_name(VER_COLLECTION_NAME), &Filter::new())?;
 		match results.last() {
 			Some(result) => {
-				let result = result?;
 				let ver = result.get_str("ver")?;
 				Ok(Some(ver.to_string()))
 			}
@@ -226,9 +699,8 @@ impl DndDatabase {
 		request: String,
 		response: &Result<Option<String>, BotError>,
 		latency: u64,
-	) -> Result<(), ejdb::Error> {
+	) -> Result<(), StorageError> {
 		let inner = self.inner.read().unwrap();
-		let coll = inner.db.collection(LOG_COLLECTION_NAME)?;
 
 		let mut default_response = String::new();
 		let response = match response {
@@ -245,40 +717,194 @@ impl DndDatabase {
 
 		let timestamp = get_unix_time();
 
-		let bson = bson! {
-			"timestamp" => timestamp,
-			"user_id" => user_id,
-			"chat_type" => chat_type,
-			"request" => request,
-			"response" => response,
-			"latency" => latency
+		let doc = doc! {
+			"timestamp": timestamp as i64,
+			"user_id": user_id,
+			"chat_type": chat_type,
+			"request": request,
+			"response": response.as_str(),
+			"latency": latency as i64
 		};
 
-		coll.save(bson)?;
+		inner.storage.insert(LOG_COLLECTION_NAME, doc)?;
 
-		Ok(())
+		// The lock keeps the saved aggregates in the same order as the in-memory ones
+		let mut usage = self.usage.lock().unwrap();
+		let day = usage.record(user_id, timestamp, latency);
+		inner.storage.upsert(
+			STATS_COLLECTION_NAME,
+			&Filter::new().eq("day", day.day as i64),
+			day.into(),
+		)?;
+		inner.storage.upsert(
+			USERS_COLLECTION_NAME,
+			&Filter::new().eq("user_id", user_id),
+			doc! {
+				"user_id": user_id,
+				"last_seen": timestamp as i64
+			},
+		)
 	}
 }
 
+// Losing more than a half of the items is most likely an error, not an errata
+fn is_sharp_drop(old: usize, new: usize) -> bool {
+	new * 2 < old
+}
+
 impl Inner {
-	fn get_cache(&self) -> HashMap<CollectionName, SimSearch<String>> {
+	fn stored_name<'a>(&'a self, collection: &'a str) -> &'a str {
+		self.active
+			.get(collection)
+			.map(String::as_str)
+			.unwrap_or(collection)
+	}
+
+	fn is_in_use(&self, stored_name: &str) -> bool {
+		self.active
+			.values()
+			.chain(self.previous.values())
+			.any(|name| name == stored_name)
+	}
+
+	fn write_collection(&self, collection: &str, docs: Vec<Document>) -> Result<(), StorageError> {
+		let docs = docs
+			.into_iter()
+			.map(|mut doc| {
+				let name_source = doc.get_str("name").map(|name| {
+					doc.get_str("source")
+						.map(|source| format!("{name} ({source})"))
+						.unwrap_or_else(|_| name.to_owned())
+				});
+				if let Ok(name_source) = name_source {
+					doc.insert("name_source", name_source);
+				}
+				// Fractions and the lair CR are not comparable in storage, see `RandomOptions::filter`
+				if let Some(cr) = doc.get_cr_eighths() {
+					doc.insert("cr_eighths", cr as i64);
+				}
+				doc
+			})
+			.collect();
+		self.storage.insert_many(collection, docs)
+	}
+
+	fn staged_names(&self) -> Result<Vec<String>, StorageError> {
+		Ok(self
+			.storage
+			.find(STAGED_COLLECTION_NAME, &Filter::new())?
+			.iter()
+			.filter_map(|doc| doc.get_str("name").ok().map(str::to_owned))
+			.collect())
+	}
+
+	// Staged collections of an update that was interrupted before `commit`
+	fn drop_abandoned(&self) {
+		let Ok(staged) = self.staged_names() else {
+			return;
+		};
+		for name in staged {
+			if !self.is_in_use(&name) {
+				info!("Dropping abandoned collection {}", name);
+				if let Err(err) = self.storage.drop_collection(&name) {
+					error!("Failed to drop {}: {}", name, err);
+					continue;
+				}
+			}
+			let _ = self.storage.delete(
+				STAGED_COLLECTION_NAME,
+				&Filter::new().eq("name", name.as_str()),
+			);
+		}
+	}
+
+	// The aggregates are built from the log once, when they are missing
+	fn load_usage(&self) -> Result<UsageStats, StorageError> {
+		let days = self
+			.storage
+			.find(STATS_COLLECTION_NAME, &Filter::new())?
+			.iter()
+			.map(DayStats::try_from)
+			.filter_map(Result::ok)
+			.collect::<Vec<_>>();
+		let last_seen = self
+			.storage
+			.find(USERS_COLLECTION_NAME, &Filter::new())?
+			.iter()
+			.filter_map(|doc| Some((doc.get_i64("user_id").ok()?, doc.get_i64("last_seen").ok()?)))
+			.map(|(user_id, last_seen)| (user_id, last_seen as u64))
+			.collect();
+		let mut usage = UsageStats::new(days, last_seen);
+		if !usage.is_empty() {
+			return Ok(usage);
+		}
+
+		let messages = self.storage.find(LOG_COLLECTION_NAME, &Filter::new())?;
+		if messages.is_empty() {
+			return Ok(usage);
+		}
+		info!(
+			"Building usage stats from {} logged messages",
+			messages.len()
+		);
+		for msg in messages
+			.into_iter()
+			.map(LogMessage::try_from)
+			.filter_map(Result::ok)
+		{
+			usage.record(msg.user_id, msg.timestamp, msg.latency);
+		}
+		self.storage.insert_many(
+			STATS_COLLECTION_NAME,
+			usage.days().map(Document::from).collect(),
+		)?;
+		self.storage.insert_many(
+			USERS_COLLECTION_NAME,
+			usage
+				.users()
+				.map(|(user_id, last_seen)| {
+					doc! {
+						"user_id": *user_id,
+						"last_seen": *last_seen as i64
+					}
+				})
+				.collect(),
+		)?;
+		Ok(usage)
+	}
+
+	// Name search and the full-text index are built from the same documents
+	fn get_cache(
+		&self,
+		active: &HashMap<String, String>,
+	) -> (HashMap<CollectionName, SimSearch<String>>, TextIndex) {
 		let mut result: HashMap<CollectionName, SimSearch<String>> =
 			HashMap::with_capacity(COLLECTION_NAMES.len());
+		let mut text_index = TextIndex::default();
 		COLLECTION_NAMES
 			.iter()
 			.for_each(|collection: &CollectionName| {
 				let collection = *collection;
 				let mut engine = SimSearch::new_with(get_search_options());
-				DndDatabase::list_items(&self.db, collection)
+				let stored_name = active
+					.get(collection)
+					.map(String::as_str)
+					.unwrap_or(collection);
+				self.storage
+					.find(stored_name, &Filter::new())
 					.unwrap_or_default()
-					.into_iter()
-					.for_each(|item| {
-						engine.insert(item.clone(), &item);
+					.iter()
+					.for_each(|doc| {
+						let Ok(item) = doc.get_str("name_source") else {
+							return;
+						};
+						engine.insert(item.to_owned(), item);
+						text_index.insert(collection, item.to_owned(), &entry_text(doc));
 					});
 
 				result.insert(collection, engine);
 			});
-		result
+		(result, text_index)
 	}
 }
 
@@ -297,9 +923,9 @@ pub struct LogMessage {
 	pub latency: u64,
 }
 
-impl TryFrom<bson::ordered::OrderedDocument> for LogMessage {
-	type Error = bson::ValueAccessError;
-	fn try_from(value: bson::ordered::OrderedDocument) -> Result<Self, Self::Error> {
+impl TryFrom<Document> for LogMessage {
+	type Error = bson::document::ValueAccessError;
+	fn try_from(value: Document) -> Result<Self, Self::Error> {
 		let timestamp = value.get_i64("timestamp")?;
 		let latency = value.get_i64("latency")?;
 		Ok(LogMessage {
@@ -314,39 +940,46 @@ impl TryFrom<bson::ordered::OrderedDocument> for LogMessage {
 
 #[cfg(test)]
 mod test {
-	use std::{fs::create_dir_all, path::Path};
+	use bson::doc;
+	use serde_json::json;
 
-	use super::DndDatabase;
-	use crate::fetch::fetch;
-	use crate::format::Entry;
+	use super::{DndDatabase, Staging, VER_COLLECTION_NAME};
+	use crate::collection::COLLECTIONS;
+	use crate::commands::RandomOptions;
+	use crate::fetch::{check_mirrors, fetch, DataSource};
+	use crate::format::{settings::Settings, tracker::Combatant, Entry};
+	use crate::get_unix_time;
+	use crate::storage::{MemoryStorage, Storage};
 
 	use simplelog::*;
 	use tokio_test::block_on;
 
 	fn init_db() -> DndDatabase {
 		let _ = TestLogger::init(LevelFilter::Trace, Config::default());
-		const DB_PATH: &str = "./test_data/roll_bot.ejdb";
-		let path = Path::new(DB_PATH);
-		create_dir_all(path.parent().unwrap()).unwrap();
-		DndDatabase::new(DB_PATH).unwrap()
+		DndDatabase::with_storage(Box::<MemoryStorage>::default()).unwrap()
 	}
 
 	fn init_with_data() -> DndDatabase {
 		let db = init_db();
 		log::set_max_level(LevelFilter::Warn);
-		let data = block_on(fetch()).unwrap();
+		let mirrors = block_on(check_mirrors(DataSource::from_env(), None));
+		let collections = COLLECTIONS.iter().collect::<Vec<_>>();
+		let (data, _) = block_on(fetch(&mirrors[0].source, &collections)).unwrap();
 		log::set_max_level(LevelFilter::Trace);
+		let mut staging = Staging::new();
 		for (collection, items) in data {
-			db.save_collection(items, &collection).unwrap();
+			db.stage_collection(&mut staging, items, &collection)
+				.unwrap();
 		}
-		db.update_cache();
+		db.commit(staging).unwrap();
 		db
 	}
 
 	#[test]
 	fn test_get_cache() {
 		let db = init_with_data();
-		assert!(db.cache.read().unwrap().len() > 0);
+		assert!(!db.cache.read().unwrap().is_empty());
+		assert!(!db.text_index.read().unwrap().search("fireball").is_empty());
 	}
 
 	#[test]
@@ -372,4 +1005,194 @@ mod test {
 		let engine = cache.get("item").unwrap();
 		assert!(!engine.search("bag of").is_empty());
 	}
+
+	#[test]
+	fn test_save_and_rollback() {
+		let db = init_db();
+		let spells = |names: &[&str]| {
+			names
+				.iter()
+				.map(|name| json!({"name": name, "source": "PHB"}))
+				.collect::<Vec<_>>()
+		};
+		db.save_collection(spells(&["Fireball", "Shield"]), "spell")
+			.unwrap();
+		assert!(db.get_item("spell", "fireball (phb)").unwrap().is_some());
+
+		db.save_collection(spells(&["Fireball", "Shield", "Wish"]), "spell")
+			.unwrap();
+		assert!(db.get_item("spell", "Wish (PHB)").unwrap().is_some());
+		assert!(db.save_collection(spells(&["Wish"]), "spell").is_err());
+		// Unless it is forced, the staged data is not committed here
+		let mut staging = Staging::new().allow_shrink(true);
+		assert!(db
+			.stage_collection(&mut staging, spells(&["Wish"]), "spell")
+			.is_ok());
+
+		assert_eq!(db.rollback().unwrap(), 1);
+		assert!(db.get_item("spell", "Wish (PHB)").unwrap().is_none());
+		assert!(!db.cache.read().unwrap()["spell"]
+			.search("shield")
+			.is_empty());
+		assert!(db.text_index.read().unwrap().search("wish").is_empty());
+
+		// Only the collections of the last commit are rolled back
+		db.save_collection(vec![json!({"ver": "1"})], VER_COLLECTION_NAME)
+			.unwrap();
+		db.save_collection(vec![json!({"ver": "2"})], VER_COLLECTION_NAME)
+			.unwrap();
+		assert_eq!(db.rollback().unwrap(), 1);
+		assert_eq!(db.get_version().unwrap().as_deref(), Some("1"));
+		assert!(db.get_item("spell", "Wish (PHB)").unwrap().is_none());
+
+		// The previous and the uncommitted generations are not listed
+		let metadata = db.get_metadata().unwrap();
+		let spells = metadata
+			.iter()
+			.filter(|info| info.name.starts_with("spell"))
+			.collect::<Vec<_>>();
+		assert_eq!(spells.len(), 1);
+		assert_eq!((spells[0].name.as_str(), spells[0].records), ("spell", 2));
+	}
+
+	#[test]
+	fn test_drop_abandoned() {
+		let storage = MemoryStorage::default();
+		storage
+			.insert(super::STAGED_COLLECTION_NAME, doc! {"name": "spell_1"})
+			.unwrap();
+		storage.insert("spell_1", doc! {"name": "Wish"}).unwrap();
+		// Not staged by the bot, even though the name looks like it
+		storage
+			.insert("monster_2", doc! {"name": "Goblin"})
+			.unwrap();
+
+		let db = DndDatabase::with_storage(Box::new(storage)).unwrap();
+		let names = db
+			.get_metadata()
+			.unwrap()
+			.into_iter()
+			.map(|info| info.name)
+			.collect::<Vec<_>>();
+		assert_eq!(names, vec!["monster_2"]);
+	}
+
+	#[test]
+	fn test_usage_stats() {
+		let storage = MemoryStorage::default();
+		storage
+			.insert(
+				super::LOG_COLLECTION_NAME,
+				doc! {
+					"timestamp": get_unix_time() as i64 - 40 * 24 * 60 * 60,
+					"user_id": 1i64,
+					"chat_type": "private",
+					"request": "/roll",
+					"response": "4",
+					"latency": 30i64
+				},
+			)
+			.unwrap();
+		let db = DndDatabase::with_storage(Box::new(storage)).unwrap();
+		db.log_message(2, "private", "/roll".to_owned(), &Ok(None), 10);
+
+		let month = db.get_usage_summary(get_unix_time() - 30 * 24 * 60 * 60);
+		assert_eq!((month.messages, month.users), (1, 1));
+		let total = db.get_usage_summary(0);
+		assert_eq!((total.messages, total.users), (2, 2));
+		assert_eq!(total.latency.max(), 30);
+	}
+
+	#[test]
+	fn test_forget_user() {
+		let db = init_db();
+		db.log_message(1, "private", "/roll".to_owned(), &Ok(None), 10);
+		db.log_message(2, "private", "/roll".to_owned(), &Ok(None), 10);
+		db.log_message(1, "private", "/spell fireball".to_owned(), &Ok(None), 10);
+		assert_eq!(db.get_user_log(1).unwrap().len(), 2);
+
+		assert_eq!(db.forget_user(1).unwrap(), 2);
+		assert!(db.get_user_log(1).unwrap().is_empty());
+		assert_eq!(db.get_user_log(2).unwrap().len(), 1);
+		let total = db.get_usage_summary(0);
+		assert_eq!((total.messages, total.users), (3, 1));
+
+		assert_eq!(db.purge_log(get_unix_time() + 1).unwrap(), 1);
+		assert!(db.get_user_log(2).unwrap().is_empty());
+		assert_eq!(db.get_usage_summary(0).messages, 3);
+	}
+
+	#[test]
+	fn test_settings() {
+		let db = init_db();
+		let mut settings = Settings::default();
+		settings.set("homebrew", "ToB").unwrap();
+		db.save_settings(1, &settings).unwrap();
+		db.save_settings(1, &settings).unwrap();
+		db.save_settings(2, &Settings::default()).unwrap();
+		assert_eq!(db.get_settings(1).unwrap().homebrew, vec!["ToB"]);
+		assert!(db.get_settings(2).unwrap().homebrew.is_empty());
+	}
+
+	#[test]
+	fn test_find_all() {
+		let db = init_db();
+		db.save_collection(
+			vec![
+				serde_json::json!({"name": "Goblin", "cr": "1/4", "environment": ["forest", "hill"]}),
+				serde_json::json!({"name": "Ogre", "cr": "2", "environment": ["forest"]}),
+				serde_json::json!({"name": "Dragon", "cr": {"cr": "17", "lair": "18"}}),
+			],
+			"monster",
+		)
+		.unwrap();
+		let names = |opts: &str| {
+			let opts = opts.parse::<RandomOptions>().unwrap();
+			db.find_all("monster", &opts.filter())
+				.unwrap()
+				.iter()
+				.map(|monster| monster.get_str("name").unwrap().to_owned())
+				.collect::<Vec<_>>()
+		};
+		assert_eq!(names("env:forest cr:0-1"), vec!["Goblin"]);
+		assert_eq!(names("env:forest"), vec!["Goblin", "Ogre"]);
+		assert_eq!(names("cr:17"), vec!["Dragon"]);
+		assert!(names("env:hill cr:2").is_empty());
+	}
+
+	#[test]
+	fn test_update_combatants() {
+		let db = init_db();
+		let goblin = Combatant {
+			name: "Goblin".to_owned(),
+			hp: 100,
+			max_hp: 100,
+			conditions: Vec::new(),
+		};
+		db.update_combatants(1, |combatants| combatants.push(goblin))
+			.unwrap();
+		std::thread::scope(|scope| {
+			for _ in 0..4 {
+				scope.spawn(|| {
+					for _ in 0..10 {
+						db.update_combatants(1, |combatants| combatants[0].apply(-1))
+							.unwrap();
+					}
+				});
+			}
+		});
+		assert_eq!(db.get_combatants(1).unwrap()[0].hp, 60);
+		assert!(db.get_combatants(2).unwrap().is_empty());
+
+		let names = vec!["Goblin".to_owned()];
+		db.save_tracker(1, 10, "Goblin", &names).unwrap();
+		assert_eq!(
+			db.get_tracker(1, 10).unwrap(),
+			Some(("Goblin".to_owned(), names))
+		);
+		assert_eq!(db.get_tracker(2, 10).unwrap(), None);
+		db.end_combat(1).unwrap();
+		assert_eq!(db.get_tracker(1, 10).unwrap(), None);
+		assert!(db.get_combatants(1).unwrap().is_empty());
+	}
 }
@@ -1,17 +1,49 @@
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::env;
 use std::error::Error;
 use std::fmt::Display;
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Read};
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
 
+use flate2::read::GzDecoder;
 use futures::future::{join_all, try_join_all, BoxFuture, FutureExt};
 
 use serde_json::{Map, Value as JsonValue};
+use tempfile::TempDir;
+use tokio::time;
 
 use crate::collection::*;
+use crate::db::HOMEBREW_COLLECTION_NAME;
 
-const BASE_URL: &str = "https://5etools-mirror-2.github.io/data";
-const CHANGELOG_URL: &str = "/changelog.json";
+// Tried in order, the first healthy one is used
+const MIRRORS: &[&str] = &[
+	"https://5etools-mirror-2.github.io/data",
+	"https://5etools-mirror-3.github.io/data",
+];
+const CHANGELOG: &str = "changelog.json";
 const INDEX: &str = "/index.json";
 const EXTENSION: &str = ".json";
+// Comma-separated list of urls, paths to the `data` directory of a 5etools checkout or paths to its tarball
+const DATA_SOURCE_VAR: &str = "ROLL_BOT_DATA_SOURCE";
+// Comma-separated list of urls or paths to homebrew files in the 5etools format
+const HOMEBREW_VAR: &str = "ROLL_BOT_HOMEBREW";
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+// Some files are several megabytes, slow mirrors need time
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+const ATTEMPTS: u32 = 4;
+// Doubled after each failed attempt
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+lazy_static! {
+	static ref CLIENT: reqwest::Client = reqwest::Client::builder()
+		.connect_timeout(CONNECT_TIMEOUT)
+		.timeout(REQUEST_TIMEOUT)
+		.build()
+		.unwrap();
+}
 
 #[derive(Debug)]
 pub struct FetchError {
@@ -27,12 +59,195 @@ impl Display for FetchError {
 
 impl Error for FetchError {}
 
+// Where the 5etools data is taken from. All paths are relative to the `data` directory
+#[derive(Debug)]
+pub enum DataSource {
+	Http(String),
+	Dir(PathBuf),
+	// Tarballs are extracted once, only json files are kept. Read like a `Dir`,
+	// the directory is removed with the source
+	Tarball(PathBuf, TempDir),
+}
+
+impl Display for DataSource {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::Http(base_url) => write!(f, "{base_url}"),
+			Self::Dir(path) | Self::Tarball(path, _) => write!(f, "{}", path.display()),
+		}
+	}
+}
+
+impl DataSource {
+	// All configured mirrors, in the order they should be tried
+	pub fn from_env() -> impl Iterator<Item = Self> {
+		Self::from_list(&env::var(DATA_SOURCE_VAR).unwrap_or_default())
+	}
+
+	// Each source is opened only when the iterator gets to it.
+	// The ones that can't be opened are skipped, so a bad path doesn't stop the other mirrors
+	fn from_list(sources: &str) -> impl Iterator<Item = Self> {
+		let mut sources = sources
+			.split(',')
+			.map(str::trim)
+			.filter(|source| !source.is_empty())
+			.map(str::to_owned)
+			.collect::<Vec<_>>();
+		if sources.is_empty() {
+			sources = MIRRORS.iter().map(|mirror| mirror.to_string()).collect();
+		}
+		sources
+			.into_iter()
+			.filter_map(|source| match Self::new(&source) {
+				Ok(source) => Some(source),
+				Err(err) => {
+					warn!("Skipping data source {source}: {err}");
+					None
+				}
+			})
+	}
+
+	pub fn new(source: &str) -> Result<Self, Box<dyn Error + Send + Sync>> {
+		if source.starts_with("http://") || source.starts_with("https://") {
+			return Ok(Self::Http(source.trim_end_matches('/').to_owned()));
+		}
+		let path = Path::new(source);
+		if path.is_dir() {
+			Ok(Self::Dir(path.to_owned()))
+		} else {
+			Self::from_tarball(path)
+		}
+	}
+
+	pub fn from_tarball(path: &Path) -> Result<Self, Box<dyn Error + Send + Sync>> {
+		info!("Unpacking {}", path.display());
+		let file = File::open(path)?;
+		let is_gzip = matches!(
+			path.extension().and_then(|ext| ext.to_str()),
+			Some("gz" | "tgz")
+		);
+		if is_gzip {
+			Self::read_tarball(path, GzDecoder::new(file))
+		} else {
+			Self::read_tarball(path, file)
+		}
+	}
+
+	fn read_tarball(path: &Path, reader: impl Read) -> Result<Self, Box<dyn Error + Send + Sync>> {
+		let mut archive = tar::Archive::new(reader);
+		let dir = tempfile::Builder::new().prefix("roll_bot_data").tempdir()?;
+		let mut files = 0;
+		for entry in archive.entries()? {
+			let mut entry = entry?;
+			if !entry.header().entry_type().is_file() {
+				continue;
+			}
+			// Only plain components are kept, so nothing is written outside of the directory
+			let Some(file_path) = tarball_path(&entry.path()?) else {
+				continue;
+			};
+			let file_path = dir.path().join(file_path);
+			if let Some(parent) = file_path.parent() {
+				fs::create_dir_all(parent)?;
+			}
+			io::copy(&mut entry, &mut File::create(file_path)?)?;
+			files += 1;
+		}
+		if files == 0 {
+			return Err(FetchError {
+				url: path.display().to_string(),
+				desc: "No json files found".to_owned(),
+			}
+			.into());
+		}
+		Ok(Self::Tarball(path.to_owned(), dir))
+	}
+
+	// Human-readable location of the file for logs and errors
+	fn location(&self, path: &str) -> String {
+		match self {
+			Self::Http(base_url) => format!("{base_url}/{path}"),
+			Self::Dir(dir) => dir.join(path).display().to_string(),
+			Self::Tarball(tarball, _) => format!("{}:{path}", tarball.display()),
+		}
+	}
+
+	// Returns `None` if there is no such file
+	async fn read(&self, path: &str) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
+		match self {
+			Self::Http(_) => http_get_with_retries(&self.location(path)).await,
+			Self::Dir(dir) => read_file(dir, path),
+			Self::Tarball(_, dir) => read_file(dir.path(), path),
+		}
+	}
+}
+
+fn read_file(dir: &Path, path: &str) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
+	match fs::read_to_string(dir.join(path)) {
+		Ok(text) => Ok(Some(text)),
+		Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+		Err(err) => Err(err.into()),
+	}
+}
+
+async fn http_get_with_retries(url: &str) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
+	let mut delay = RETRY_DELAY;
+	let mut attempt = 1;
+	loop {
+		match http_get(url).await {
+			Err(err) if attempt < ATTEMPTS => {
+				warn!("Attempt {attempt} to get {url} failed: {err}, retrying in {delay:?}");
+				time::sleep(delay).await;
+				delay *= 2;
+				attempt += 1;
+			}
+			result => return result,
+		}
+	}
+}
+
+async fn http_get(url: &str) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
+	let response = CLIENT.get(url).send().await?;
+	match response.status() {
+		reqwest::StatusCode::OK => Ok(Some(response.text().await?)),
+		reqwest::StatusCode::NOT_FOUND => Ok(None),
+		status => Err(FetchError {
+			url: url.to_owned(),
+			desc: format!("Unexpected status code: {status}"),
+		}
+		.into()),
+	}
+}
+
+// "5etools-v1.0/data/bestiary/index.json" -> "bestiary/index.json"
+fn tarball_path(path: &Path) -> Option<String> {
+	if path.extension()? != "json" {
+		return None;
+	}
+	let components = path
+		.components()
+		.filter_map(|c| match c {
+			Component::Normal(c) => c.to_str(),
+			_ => None,
+		})
+		.collect::<Vec<_>>();
+	let start = components
+		.iter()
+		.position(|c| *c == "data")
+		.map(|i| i + 1)
+		.unwrap_or_default();
+	Some(components[start..].join("/"))
+}
+
 impl Collection {
-	pub async fn fetch(&self) -> Result<Vec<JsonValue>, Box<dyn Error + Send + Sync>> {
+	pub async fn fetch(
+		&self,
+		source: &DataSource,
+	) -> Result<Vec<JsonValue>, Box<dyn Error + Send + Sync>> {
 		let work = self
 			.urls
 			.iter()
-			.map(|url| download(format!("{BASE_URL}/{url}")))
+			.map(|url| download(source, url.to_string()))
 			.collect::<Vec<_>>();
 
 		let results = try_join_all(work).await?;
@@ -41,11 +256,15 @@ impl Collection {
 	}
 }
 
-pub async fn fetch_changelog() -> Result<Vec<JsonValue>, Box<dyn Error + Send + Sync>> {
-	let changelog_url = format!("{BASE_URL}{CHANGELOG_URL}");
+pub async fn fetch_changelog(
+	source: &DataSource,
+) -> Result<Vec<JsonValue>, Box<dyn Error + Send + Sync>> {
+	let changelog_url = source.location(CHANGELOG);
 	info!("Fetching changelog {}", changelog_url);
-	let changelog = reqwest::get(&changelog_url).await?;
-	let changelog = changelog.text().await?;
+	let changelog = source.read(CHANGELOG).await?.ok_or_else(|| FetchError {
+		url: changelog_url.clone(),
+		desc: "Not found".to_string(),
+	})?;
 	let changelog: JsonValue = serde_json::from_str(&changelog)?;
 	match changelog {
 		JsonValue::Array(arr) => Ok(arr),
@@ -57,10 +276,140 @@ pub async fn fetch_changelog() -> Result<Vec<JsonValue>, Box<dyn Error + Send +
 	}
 }
 
-pub async fn fetch() -> Result<HashMap<String, Vec<JsonValue>>, Box<dyn Error + Send + Sync>> {
-	let work = COLLECTIONS.iter().map(|item| item.fetch());
-	let fetch_results = try_join_all(work).await?;
+// A mirror that passed the health check
+pub struct Mirror {
+	pub source: DataSource,
+	pub changelog: Vec<JsonValue>,
+}
+
+impl Mirror {
+	pub fn ver(&self) -> Option<&str> {
+		get_latest_ver(&self.changelog)
+	}
+}
+
+// Drops mirrors that are down or lag behind the others or `cur_ver`, keeping the order
+pub async fn check_mirrors(
+	sources: impl IntoIterator<Item = DataSource>,
+	cur_ver: Option<&str>,
+) -> Vec<Mirror> {
+	let sources = sources.into_iter().collect::<Vec<_>>();
+	let changelogs = join_all(sources.iter().map(fetch_changelog)).await;
+	let mirrors = sources
+		.into_iter()
+		.zip(changelogs)
+		.filter_map(|(source, changelog)| match changelog {
+			Ok(changelog) if get_latest_ver(&changelog).is_some() => {
+				Some(Mirror { source, changelog })
+			}
+			Ok(_) => {
+				warn!("Mirror {source} is unhealthy: changelog has no versions");
+				None
+			}
+			Err(err) => {
+				warn!("Mirror {source} is unhealthy: {err}");
+				None
+			}
+		})
+		.collect::<Vec<_>>();
+
+	let newest = mirrors
+		.iter()
+		.filter_map(Mirror::ver)
+		.chain(cur_ver)
+		.max_by(|a, b| compare_versions(a, b))
+		.map(str::to_owned);
+	mirrors
+		.into_iter()
+		.filter(|mirror| {
+			let ver = mirror.ver().unwrap_or_default();
+			let is_stale = newest
+				.as_deref()
+				.is_some_and(|newest| compare_versions(ver, newest) == Ordering::Less);
+			if is_stale {
+				warn!(
+					"Mirror {} is stale: it has {ver}, but {} is available",
+					mirror.source,
+					newest.as_deref().unwrap_or_default()
+				);
+			}
+			!is_stale
+		})
+		.collect()
+}
+
+pub fn get_latest_ver(changelog: &[JsonValue]) -> Option<&str> {
+	let last = changelog.last()?;
+	let doc = last.as_object()?;
+	let ver = doc.get("ver")?;
+	ver.as_str()
+}
+
+// "1.9.2" < "1.10.0"
+fn compare_versions(a: &str, b: &str) -> Ordering {
+	let parse = |ver: &str| {
+		ver.split(['.', '-'])
+			.map(|part| part.parse::<u64>().unwrap_or_default())
+			.collect::<Vec<_>>()
+	};
+	parse(a).cmp(&parse(b))
+}
+
+// What an update has downloaded, collections that failed are not updated at all
+#[derive(Debug, Default, Clone)]
+pub struct FetchReport {
+	// Item counts of the downloaded collections
+	pub loaded: Vec<(String, usize)>,
+	// Default command of the failed collection or the homebrew location, and the error
+	pub failed: Vec<(String, String)>,
+}
+
+impl Display for FetchReport {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "Fetched {} collections", self.loaded.len())?;
+		for (collection, count) in &self.loaded {
+			write!(f, "\n  {collection}: {count} items")?;
+		}
+		if !self.failed.is_empty() {
+			write!(f, "\nFailed {} collections", self.failed.len())?;
+			for (command, err) in &self.failed {
+				write!(f, "\n  {command}: {err}")?;
+			}
+		}
+		Ok(())
+	}
+}
+
+// One failed collection doesn't stop the others, see the report for what was downloaded
+pub async fn fetch(
+	source: &DataSource,
+	collections: &[&'static Collection],
+) -> Result<(HashMap<String, Vec<JsonValue>>, FetchReport), Box<dyn Error + Send + Sync>> {
+	let work = collections.iter().map(|item| item.fetch(source));
+	let fetch_results = join_all(work).await;
 	info!("Fetch complete!");
+	let mut report = FetchReport::default();
+	let fetch_results = collections
+		.iter()
+		.zip(fetch_results)
+		.filter_map(|(item, result)| match result {
+			Ok(values) => Some(values),
+			Err(err) => {
+				report
+					.failed
+					.push((item.get_default_command().to_owned(), err.to_string()));
+				None
+			}
+		})
+		.collect::<Vec<_>>();
+	if fetch_results.is_empty() {
+		return Err(FetchError {
+			url: source.to_string(),
+			desc: "Every collection failed".to_string(),
+		}
+		.into());
+	}
+
 	let mut result: HashMap<String, Vec<JsonValue>> = HashMap::new();
 	fetch_results.into_iter().flatten().for_each(|value| {
 		if let Some(doc) = value.as_object() {
@@ -76,26 +425,207 @@ pub async fn fetch() -> Result<HashMap<String, Vec<JsonValue>>, Box<dyn Error +
 			}
 		}
 	});
-	Ok(result)
+
+	report.loaded = result
+		.iter()
+		.map(|(collection, items)| (collection.clone(), items.len()))
+		.collect();
+	report.loaded.sort();
+	Ok((result, report))
 }
 
-async fn download(url: String) -> Result<Vec<JsonValue>, Box<dyn Error + Send + Sync>> {
-	let is_file = url.ends_with(EXTENSION);
-	let file_url = if !is_file {
-		url.clone() + EXTENSION
+// Items of every homebrew pack by collection, they are added to the data of the mirror.
+// Packs that failed are added to the report
+pub async fn fetch_homebrew(
+	homebrew: &[String],
+	report: &mut FetchReport,
+) -> HashMap<String, Vec<JsonValue>> {
+	let mut result: HashMap<String, Vec<JsonValue>> = HashMap::new();
+	// Always saved, so removed packs disappear from the settings too
+	result.insert(HOMEBREW_COLLECTION_NAME.to_owned(), Vec::new());
+	let packs = join_all(homebrew.iter().map(|location| download_homebrew(location))).await;
+	for (location, pack) in homebrew.iter().zip(packs) {
+		match pack {
+			Ok(pack) => {
+				for (collection, items) in pack {
+					result.entry(collection).or_default().extend(items);
+				}
+			}
+			Err(err) => report.failed.push((location.clone(), err.to_string())),
+		}
+	}
+	result
+}
+
+// Settings and search filters tell homebrew apart by the source, so a pack
+// that reuses an official source is dropped as a whole and added to the report
+pub fn drop_official_sources(
+	homebrew: &mut HashMap<String, Vec<JsonValue>>,
+	official: &HashSet<&str>,
+	report: &mut FetchReport,
+) {
+	let field =
+		|item: &JsonValue, key: &str| item.get(key).and_then(JsonValue::as_str).map(str::to_owned);
+	let packs = homebrew
+		.get(HOMEBREW_COLLECTION_NAME)
+		.map(Vec::as_slice)
+		.unwrap_or_default();
+	let mut rejected = HashSet::new();
+	for pack in packs {
+		let (Some(source), Some(location)) = (field(pack, "source"), field(pack, "location"))
+		else {
+			continue;
+		};
+		if official.contains(source.as_str()) && rejected.insert(location.clone()) {
+			report.failed.push((
+				location,
+				format!("Source {source} is already used by the official data"),
+			));
+		}
+	}
+	let rejected_sources = packs
+		.iter()
+		.filter(|pack| field(pack, "location").is_some_and(|location| rejected.contains(&location)))
+		.filter_map(|pack| field(pack, "source"))
+		.collect::<HashSet<_>>();
+	for items in homebrew.values_mut() {
+		items.retain(|item| {
+			!field(item, "source").is_some_and(|source| rejected_sources.contains(&source))
+		});
+	}
+}
+
+pub fn homebrew_from_env() -> Vec<String> {
+	env::var(HOMEBREW_VAR)
+		.unwrap_or_default()
+		.split(',')
+		.map(str::trim)
+		.filter(|location| !location.is_empty())
+		.map(str::to_owned)
+		.collect()
+}
+
+async fn download_homebrew(
+	location: &str,
+) -> Result<HashMap<String, Vec<JsonValue>>, Box<dyn Error + Send + Sync>> {
+	let text = if location.starts_with("http://") || location.starts_with("https://") {
+		http_get_with_retries(location)
+			.await?
+			.ok_or_else(|| FetchError {
+				url: location.to_owned(),
+				desc: "Not found".to_string(),
+			})?
 	} else {
-		url.clone()
+		fs::read_to_string(location)?
+	};
+	info!("Successfully get homebrew: {}", location);
+	let json: JsonValue = serde_json::from_str(&text)?;
+	parse_homebrew(location, json)
+}
+
+// Every item gets a "homebrew" field with the full name of its pack,
+// the packs themselves go to the `_homebrew` collection
+fn parse_homebrew(
+	location: &str,
+	json: JsonValue,
+) -> Result<HashMap<String, Vec<JsonValue>>, Box<dyn Error + Send + Sync>> {
+	let bad_format = |desc: &str| FetchError {
+		url: location.to_owned(),
+		desc: desc.to_owned(),
+	};
+	let JsonValue::Object(doc) = json else {
+		return Err(bad_format("Homebrew file is not an object").into());
+	};
+	let sources = doc
+		.get("_meta")
+		.and_then(|meta| meta.get("sources"))
+		.and_then(JsonValue::as_array)
+		.map(|sources| {
+			sources
+				.iter()
+				.filter_map(|source| {
+					let json = source.get("json")?.as_str()?;
+					let full = source
+						.get("full")
+						.and_then(JsonValue::as_str)
+						.unwrap_or(json);
+					Some((json.to_owned(), full.to_owned()))
+				})
+				.collect::<Vec<_>>()
+		})
+		.unwrap_or_default();
+	let Some((default_source, default_name)) = sources.first().cloned() else {
+		return Err(bad_format("Homebrew file has no _meta.sources").into());
 	};
 
-	let response = reqwest::get(&file_url).await?;
+	let mut result: HashMap<String, Vec<JsonValue>> = HashMap::new();
+	for (collection, items) in doc {
+		// "_meta" and other service fields
+		if collection.starts_with('_') {
+			continue;
+		}
+		let JsonValue::Array(items) = items else {
+			continue;
+		};
+		let items = items.into_iter().filter_map(|item| {
+			let JsonValue::Object(mut item) = item else {
+				return None;
+			};
+			let source = match item.get("source").and_then(JsonValue::as_str) {
+				Some(source) => source.to_owned(),
+				None => {
+					item.insert(
+						"source".to_owned(),
+						JsonValue::String(default_source.clone()),
+					);
+					default_source.clone()
+				}
+			};
+			let name = sources
+				.iter()
+				.find(|(json, _)| *json == source)
+				.map(|(_, full)| full.clone())
+				.unwrap_or_else(|| default_name.clone());
+			item.insert("homebrew".to_owned(), JsonValue::String(name));
+			Some(JsonValue::Object(item))
+		});
+		result.entry(collection).or_default().extend(items);
+	}
 
-	match (is_file, response.status()) {
-		(_, reqwest::StatusCode::OK) => {
-			info!("Successfully get url: {}", file_url);
-			let text = response.text().await?;
+	let packs = sources.into_iter().map(|(source, name)| {
+		let mut pack = Map::new();
+		pack.insert("source".to_owned(), JsonValue::String(source));
+		pack.insert("name".to_owned(), JsonValue::String(name));
+		pack.insert(
+			"location".to_owned(),
+			JsonValue::String(location.to_owned()),
+		);
+		JsonValue::Object(pack)
+	});
+	result
+		.entry(HOMEBREW_COLLECTION_NAME.to_owned())
+		.or_default()
+		.extend(packs);
+	Ok(result)
+}
+
+async fn download(
+	source: &DataSource,
+	path: String,
+) -> Result<Vec<JsonValue>, Box<dyn Error + Send + Sync>> {
+	let is_file = path.ends_with(EXTENSION);
+	let file_path = if !is_file {
+		path.clone() + EXTENSION
+	} else {
+		path.clone()
+	};
+
+	match (is_file, source.read(&file_path).await?) {
+		(_, Some(text)) => {
+			info!("Successfully get url: {}", source.location(&file_path));
 			let json: JsonValue = serde_json::from_str(&text)?;
 
-			if url.ends_with("spells/sources.json") {
+			if path.ends_with("spells/sources.json") {
 				let mut new_array = Vec::new();
 				for (source, spells) in json.as_object().unwrap() {
 					for (spell_name, spell) in spells.as_object().unwrap() {
@@ -112,35 +642,265 @@ async fn download(url: String) -> Result<Vec<JsonValue>, Box<dyn Error + Send +
 
 			Ok(vec![json])
 		}
-		(false, reqwest::StatusCode::NOT_FOUND) => download_indexed(url).await,
-		_ => Err::<_, Box<dyn Error + Send + Sync>>(Box::new(FetchError {
-			url: file_url,
-			desc: format!("Unexpected status code: {}", response.status()),
+		(false, None) => download_indexed(source, path).await,
+		(true, None) => Err::<_, Box<dyn Error + Send + Sync>>(Box::new(FetchError {
+			url: source.location(&file_path),
+			desc: "Not found".to_string(),
 		})),
 	}
 }
 
 fn download_indexed(
-	url: String,
-) -> BoxFuture<'static, Result<Vec<JsonValue>, Box<dyn Error + Send + Sync>>> {
+	source: &DataSource,
+	path: String,
+) -> BoxFuture<'_, Result<Vec<JsonValue>, Box<dyn Error + Send + Sync>>> {
 	async move {
 		info!(
 			"File not found: {}, trying to treat it as a directory...",
-			url
+			source.location(&path)
 		);
-		let index_url = url.clone() + INDEX;
-		let index: HashMap<String, String> = reqwest::get(&index_url)
-			.await?
-			.json::<HashMap<String, String>>()
-			.await?;
-		let children = join_all(
+		let index_path = path.clone() + INDEX;
+		let index = source.read(&index_path).await?.ok_or_else(|| FetchError {
+			url: source.location(&index_path),
+			desc: "Not found".to_string(),
+		})?;
+		let index: HashMap<String, String> = serde_json::from_str(&index)?;
+		// A single missing file fails the whole directory, half a bestiary is worse than an old one
+		let children = try_join_all(
 			index
 				.values()
-				.map(|file| download(url.clone() + "/" + file))
+				.map(|file| download(source, path.clone() + "/" + file))
 				.collect::<Vec<_>>(),
 		)
-		.await;
-		Ok(children.into_iter().flatten().flatten().collect::<Vec<_>>())
+		.await?;
+		Ok(children.into_iter().flatten().collect::<Vec<_>>())
 	}
 	.boxed()
 }
+
+#[cfg(test)]
+mod test {
+	use std::fs::create_dir_all;
+
+	use tokio_test::block_on;
+
+	use super::*;
+
+	fn tarball(files: &[(&str, &str)]) -> DataSource {
+		let mut builder = tar::Builder::new(Vec::new());
+		for (path, text) in files {
+			let mut header = tar::Header::new_gnu();
+			header.set_size(text.len() as u64);
+			header.set_mode(0o644);
+			header.set_cksum();
+			builder
+				.append_data(
+					&mut header,
+					format!("5etools-v1.0.0/data/{path}"),
+					text.as_bytes(),
+				)
+				.unwrap();
+		}
+		let tarball = builder.into_inner().unwrap();
+		DataSource::read_tarball(Path::new("5etools.tar"), tarball.as_slice()).unwrap()
+	}
+
+	const FILES: &[(&str, &str)] = &[
+		("changelog.json", r#"[{"ver": "1.0.0"}]"#),
+		("bestiary/index.json", r#"{"MM": "bestiary-mm.json"}"#),
+		(
+			"bestiary/bestiary-mm.json",
+			r#"{"monster": [{"name": "Goblin", "source": "MM"}]}"#,
+		),
+		("bestiary/legendarygroups.json", r#"{"legendaryGroup": []}"#),
+	];
+
+	fn check_source(source: &DataSource) {
+		let changelog = block_on(fetch_changelog(source)).unwrap();
+		assert_eq!(changelog.len(), 1);
+
+		let monsters = block_on(download(source, "bestiary".to_owned())).unwrap();
+		assert_eq!(monsters.len(), 1);
+		assert_eq!(monsters[0]["monster"][0]["name"], "Goblin");
+
+		assert!(block_on(download(source, "spells".to_owned())).is_err());
+	}
+
+	#[test]
+	fn test_dir_source() {
+		let dir = env::temp_dir().join("roll_bot_test_dir_source");
+		for (path, text) in FILES {
+			let path = dir.join(path);
+			create_dir_all(path.parent().unwrap()).unwrap();
+			fs::write(path, text).unwrap();
+		}
+		let source = DataSource::new(dir.to_str().unwrap()).unwrap();
+		assert!(matches!(source, DataSource::Dir(_)));
+		check_source(&source);
+	}
+
+	#[test]
+	fn test_tarball_source() {
+		check_source(&tarball(FILES));
+	}
+
+	#[test]
+	fn test_from_list() {
+		let dir = env::temp_dir().join("roll_bot_test_from_list");
+		create_dir_all(&dir).unwrap();
+		let sources = DataSource::from_list(&format!(
+			"/nonexistent/5etools.tar.gz, {}, https://example.com/data/",
+			dir.display()
+		))
+		.map(|source| source.to_string())
+		.collect::<Vec<_>>();
+		assert_eq!(
+			sources,
+			vec![
+				dir.display().to_string(),
+				"https://example.com/data".to_owned()
+			]
+		);
+		assert_eq!(DataSource::from_list(" ").count(), MIRRORS.len());
+	}
+
+	fn all_collections() -> Vec<&'static Collection> {
+		COLLECTIONS.iter().collect()
+	}
+
+	#[test]
+	fn test_partial_fetch() {
+		let (data, report) = block_on(fetch(&tarball(FILES), &all_collections())).unwrap();
+		assert_eq!(data["monster"].len(), 1);
+		assert!(report.loaded.contains(&("monster".to_owned(), 1)));
+		assert!(report.failed.iter().any(|(command, _)| *command == "spell"));
+
+		let mut files = FILES.to_vec();
+		files.push((
+			"bestiary/index.json",
+			r#"{"MM": "bestiary-mm.json", "VGM": "bestiary-vgm.json"}"#,
+		));
+		files.push(("items-base.json", r#"{"baseitem": [{"name": "Club"}]}"#));
+		let (data, report) = block_on(fetch(&tarball(&files), &all_collections())).unwrap();
+		assert!(!data.contains_key("monster"));
+		assert_eq!(data["baseitem"].len(), 1);
+		assert!(report
+			.failed
+			.iter()
+			.any(|(command, _)| *command == "monster"));
+
+		// Only the given collections are fetched
+		let spells = COMMANDS.get("spell").copied().unwrap();
+		assert!(block_on(fetch(&tarball(&files), &[spells])).is_err());
+
+		assert!(block_on(fetch(
+			&tarball(&[("changelog.json", "[]")]),
+			&all_collections()
+		))
+		.is_err());
+	}
+
+	#[test]
+	fn test_homebrew() {
+		let path = env::temp_dir().join("roll_bot_test_homebrew.json");
+		fs::write(
+			&path,
+			r#"{
+				"_meta": {"sources": [{"json": "ToB", "abbreviation": "ToB", "full": "Tome of Beasts"}]},
+				"monster": [{"name": "Clockwork Hound"}, {"name": "Goblin Shaman", "source": "ToB"}],
+				"spell": [{"name": "Hellfire", "source": "ToB"}]
+			}"#,
+		)
+		.unwrap();
+		let homebrew = vec![
+			path.to_str().unwrap().to_owned(),
+			"/nonexistent/homebrew.json".to_owned(),
+		];
+		let mut report = FetchReport::default();
+		let data = block_on(fetch_homebrew(&homebrew, &mut report));
+		assert_eq!(data["monster"].len(), 2);
+		assert_eq!(data["monster"][0]["source"], "ToB");
+		assert_eq!(data["monster"][0]["homebrew"], "Tome of Beasts");
+		assert_eq!(data["spell"].len(), 1);
+		assert_eq!(data[HOMEBREW_COLLECTION_NAME][0]["source"], "ToB");
+		assert!(report
+			.failed
+			.iter()
+			.any(|(location, _)| location == &homebrew[1]));
+
+		assert!(parse_homebrew("no_meta.json", serde_json::json!({"monster": []})).is_err());
+	}
+
+	#[test]
+	fn test_drop_official_sources() {
+		let mut homebrew = parse_homebrew(
+			"fake_phb.json",
+			serde_json::json!({
+				"_meta": {"sources": [{"json": "PHB", "full": "Better Handbook"}, {"json": "BH2"}]},
+				"spell": [{"name": "Fireball"}, {"name": "Icebolt", "source": "BH2"}]
+			}),
+		)
+		.unwrap();
+		let tob = parse_homebrew(
+			"tob.json",
+			serde_json::json!({
+				"_meta": {"sources": [{"json": "ToB", "full": "Tome of Beasts"}]},
+				"monster": [{"name": "Clockwork Hound"}]
+			}),
+		)
+		.unwrap();
+		for (collection, items) in tob {
+			homebrew.entry(collection).or_default().extend(items);
+		}
+
+		let mut report = FetchReport::default();
+		drop_official_sources(&mut homebrew, &HashSet::from(["PHB", "MM"]), &mut report);
+		assert!(homebrew["spell"].is_empty());
+		assert_eq!(homebrew["monster"].len(), 1);
+		assert_eq!(homebrew[HOMEBREW_COLLECTION_NAME].len(), 1);
+		assert_eq!(homebrew[HOMEBREW_COLLECTION_NAME][0]["source"], "ToB");
+		assert_eq!(report.failed.len(), 1);
+		assert_eq!(report.failed[0].0, "fake_phb.json");
+	}
+
+	#[test]
+	fn test_check_mirrors() {
+		let sources = || {
+			vec![
+				DataSource::Dir(env::temp_dir().join("roll_bot_test_missing_mirror")),
+				tarball(&[("changelog.json", "{}")]),
+				tarball(FILES),
+				tarball(&[("changelog.json", r#"[{"ver": "1.10.0"}]"#)]),
+			]
+		};
+		let mirrors = block_on(check_mirrors(sources(), None));
+		assert_eq!(mirrors.len(), 1);
+		assert_eq!(mirrors[0].ver(), Some("1.10.0"));
+
+		let mirrors = block_on(check_mirrors(sources(), Some("1.9.0")));
+		assert_eq!(mirrors.len(), 1);
+		// The database is newer than every mirror
+		let mirrors = block_on(check_mirrors(sources(), Some("1.11.0")));
+		assert!(mirrors.is_empty());
+	}
+
+	#[test]
+	fn test_compare_versions() {
+		assert_eq!(compare_versions("1.9.2", "1.10.0"), Ordering::Less);
+		assert_eq!(compare_versions("1.10.0", "1.10.0"), Ordering::Equal);
+		assert_eq!(compare_versions("2.0", "1.99.1"), Ordering::Greater);
+	}
+
+	#[test]
+	fn test_tarball_path() {
+		assert_eq!(
+			tarball_path(Path::new("5etools/data/bestiary/index.json")).as_deref(),
+			Some("bestiary/index.json")
+		);
+		assert_eq!(
+			tarball_path(Path::new("./changelog.json")).as_deref(),
+			Some("changelog.json")
+		);
+		assert_eq!(tarball_path(Path::new("data/img/goblin.png")), None);
+	}
+}
@@ -1,5 +1,5 @@
 use super::{simple_format, Entry};
-use ejdb::bson::Document;
+use bson::Document;
 use regex::{Captures, Regex};
 
 pub trait Abbreviation: Entry {
@@ -0,0 +1,597 @@
+use std::{collections::BTreeMap, fmt::Write};
+
+use bson::{Bson, Document};
+use regex::Regex;
+
+use super::{roll::RollContext, utils::HtmlEscapable, EntryArrayUtils};
+
+const ABILITIES: [&str; 6] = ["str", "dex", "con", "int", "wis", "cha"];
+
+// Skill name, Foundry abbreviation and the default ability
+const SKILLS: [(&str, &str, &str); 18] = [
+	("acrobatics", "acr", "dex"),
+	("animal handling", "ani", "wis"),
+	("arcana", "arc", "int"),
+	("athletics", "ath", "str"),
+	("deception", "dec", "cha"),
+	("history", "his", "int"),
+	("insight", "ins", "wis"),
+	("intimidation", "itm", "cha"),
+	("investigation", "inv", "int"),
+	("medicine", "med", "wis"),
+	("nature", "nat", "int"),
+	("perception", "prc", "wis"),
+	("performance", "prf", "cha"),
+	("persuasion", "per", "cha"),
+	("religion", "rel", "int"),
+	("sleight of hand", "slt", "dex"),
+	("stealth", "ste", "dex"),
+	("survival", "sur", "wis"),
+];
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Attack {
+	pub name: String,
+	pub to_hit: i64,
+	pub damage: Option<String>,
+}
+
+// Only the numbers needed for rolls are kept, all bonuses are already summed up
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Character {
+	pub name: String,
+	pub abilities: BTreeMap<String, i64>,
+	pub proficiency_bonus: i64,
+	pub saves: BTreeMap<String, i64>,
+	pub skills: BTreeMap<String, i64>,
+	pub attacks: Vec<Attack>,
+}
+
+impl Character {
+	// Understands 5etools-like stat blocks and Foundry VTT actor exports
+	pub fn import(doc: &Document) -> Option<Self> {
+		let name = doc.get_str("name").ok()?.to_owned();
+		// Foundry used "data" before v10
+		let system = doc
+			.get_document("system")
+			.or_else(|_| doc.get_document("data"))
+			.ok();
+		let character = match system {
+			Some(system) => Self::import_foundry(name, doc, system),
+			None => Self::import_5etools(name, doc),
+		}?;
+		if character.abilities.is_empty() {
+			None
+		} else {
+			Some(character)
+		}
+	}
+
+	fn import_5etools(name: String, doc: &Document) -> Option<Self> {
+		let abilities = ABILITIES
+			.iter()
+			.filter_map(|ability| Some((ability.to_string(), get_number(doc.get(ability)?)?)))
+			.collect::<BTreeMap<_, _>>();
+		let level = doc.get("level").and_then(get_number);
+		let proficiency_bonus = ["proficiencyBonus", "pb"]
+			.iter()
+			.find_map(|key| doc.get(key).and_then(get_number))
+			.or_else(|| level.map(level_to_proficiency_bonus))
+			.unwrap_or(2);
+
+		let bonuses = |key: &str| -> BTreeMap<String, i64> {
+			doc.get_document(key)
+				.map(|bonuses| {
+					bonuses
+						.iter()
+						.filter_map(|(name, bonus)| Some((name.to_lowercase(), get_number(bonus)?)))
+						.collect()
+				})
+				.unwrap_or_default()
+		};
+
+		let mut attacks = doc
+			.get_array_of("attacks", Bson::as_document)
+			.unwrap_or_default()
+			.into_iter()
+			.filter_map(|attack| {
+				Some(Attack {
+					name: attack.get_str("name").ok()?.to_owned(),
+					to_hit: ["toHit", "hit"]
+						.iter()
+						.find_map(|key| attack.get(key).and_then(get_number))?,
+					damage: attack.get_str("damage").map(str::to_owned).ok(),
+				})
+			})
+			.collect::<Vec<_>>();
+		// Monster actions: "{@atk mw} {@hit 4} to hit ... {@damage 1d6 + 2}"
+		attacks.extend(
+			doc.get_array_of("action", Bson::as_document)
+				.unwrap_or_default()
+				.into_iter()
+				.filter_map(action_to_attack),
+		);
+
+		Some(Self {
+			name,
+			abilities,
+			proficiency_bonus,
+			saves: bonuses("save"),
+			skills: bonuses("skill"),
+			attacks,
+		})
+	}
+
+	fn import_foundry(name: String, doc: &Document, system: &Document) -> Option<Self> {
+		let foundry_abilities = system.get_document("abilities").ok()?;
+		let abilities = ABILITIES
+			.iter()
+			.filter_map(|ability| {
+				let value = foundry_abilities.get_document(ability).ok()?.get("value")?;
+				Some((ability.to_string(), get_number(value)?))
+			})
+			.collect::<BTreeMap<_, _>>();
+
+		let items = doc
+			.get_array_of("items", Bson::as_document)
+			.unwrap_or_default();
+		let class_levels = items
+			.iter()
+			.filter(|item| item.get_str("type").ok() == Some("class"))
+			.filter_map(|item| {
+				let item_system = item
+					.get_document("system")
+					.or_else(|_| item.get_document("data"));
+				item_system.ok()?.get("levels").and_then(get_number)
+			})
+			.sum::<i64>();
+		let level = system
+			.get_document("details")
+			.ok()
+			.and_then(|details| details.get("level"))
+			.and_then(get_number)
+			.unwrap_or(class_levels);
+		let proficiency_bonus = system
+			.get_document("attributes")
+			.ok()
+			.and_then(|attributes| attributes.get("prof"))
+			.and_then(get_number)
+			.unwrap_or_else(|| level_to_proficiency_bonus(level));
+
+		let modifier = |ability: &str| {
+			abilities
+				.get(ability)
+				.map(|score| score_to_modifier(*score))
+		};
+
+		let saves = ABILITIES
+			.iter()
+			.filter_map(|ability| {
+				let proficient = foundry_abilities
+					.get_document(ability)
+					.ok()?
+					.get("proficient")
+					.and_then(get_multiplier)
+					.unwrap_or(0.0);
+				let bonus = modifier(ability)? + (proficient * proficiency_bonus as f64) as i64;
+				Some((ability.to_string(), bonus))
+			})
+			.collect();
+
+		let skills = system
+			.get_document("skills")
+			.map(|skills| {
+				SKILLS
+					.iter()
+					.filter_map(|(skill, abbreviation, default_ability)| {
+						let foundry_skill = skills.get_document(abbreviation).ok()?;
+						let ability = foundry_skill.get_str("ability").unwrap_or(*default_ability);
+						let proficient = foundry_skill
+							.get("value")
+							.and_then(get_multiplier)
+							.unwrap_or(0.0);
+						let bonus =
+							modifier(ability)? + (proficient * proficiency_bonus as f64) as i64;
+						Some((skill.to_string(), bonus))
+					})
+					.collect()
+			})
+			.unwrap_or_default();
+
+		let attacks = items
+			.iter()
+			.filter(|item| item.get_str("type").ok() == Some("weapon"))
+			.filter_map(|item| {
+				let item_system = item
+					.get_document("system")
+					.or_else(|_| item.get_document("data"))
+					.ok()?;
+				let finesse = item_system
+					.get_document("properties")
+					.map(|properties| properties.get_bool("fin").unwrap_or(false))
+					.unwrap_or(false);
+				let ability = match item_system.get_str("ability") {
+					Ok(ability) if !ability.is_empty() => ability.to_owned(),
+					_ if item_system.get_str("actionType").ok() == Some("rwak") => "dex".to_owned(),
+					_ if finesse && modifier("dex") > modifier("str") => "dex".to_owned(),
+					_ => "str".to_owned(),
+				};
+				let ability_modifier = modifier(&ability)?;
+				let proficient = item_system.get_bool("proficient").unwrap_or(true);
+				let attack_bonus = item_system
+					.get("attackBonus")
+					.and_then(get_number)
+					.unwrap_or(0);
+				let damage = item_system
+					.get_document("damage")
+					.ok()
+					.and_then(|damage| damage.get_array("parts").ok())
+					.and_then(|parts| parts.first())
+					.and_then(Bson::as_array)
+					.and_then(|part| part.first())
+					.and_then(Bson::as_str)
+					.map(|formula| {
+						formula
+							.replace("@mod", &ability_modifier.to_string())
+							.replace("+ -", "- ")
+					});
+				Some(Attack {
+					name: item.get_str("name").ok()?.to_owned(),
+					to_hit: ability_modifier
+						+ attack_bonus + if proficient { proficiency_bonus } else { 0 },
+					damage,
+				})
+			})
+			.collect();
+
+		Some(Self {
+			name,
+			abilities,
+			proficiency_bonus,
+			saves,
+			skills,
+			attacks,
+		})
+	}
+
+	pub fn get_modifier(&self, ability: &str) -> Option<i64> {
+		self.abilities
+			.get(ability)
+			.map(|score| score_to_modifier(*score))
+	}
+
+	// Ability checks fall back to the ability modifier when there is no skill bonus
+	pub fn get_check_bonus(&self, name: &str) -> Option<i64> {
+		let name = name.trim().to_lowercase();
+		if let Some(ability) = parse_ability(&name) {
+			return self.get_modifier(ability);
+		}
+		let (skill, _, ability) = SKILLS
+			.iter()
+			.find(|(skill, abbreviation, _)| *skill == name || *abbreviation == name)?;
+		self.skills
+			.get(*skill)
+			.copied()
+			.or_else(|| self.get_modifier(ability))
+	}
+
+	pub fn get_save_bonus(&self, ability: &str) -> Option<i64> {
+		let ability = parse_ability(&ability.trim().to_lowercase())?;
+		self.saves
+			.get(ability)
+			.copied()
+			.or_else(|| self.get_modifier(ability))
+	}
+
+	pub fn find_attack(&self, name: &str) -> Option<&Attack> {
+		let name = name.trim().to_lowercase();
+		self.attacks
+			.iter()
+			.find(|attack| attack.name.to_lowercase() == name)
+			.or_else(|| {
+				self.attacks
+					.iter()
+					.find(|attack| attack.name.to_lowercase().starts_with(&name))
+			})
+	}
+
+	// Modifiers available as "@name" in roll expressions
+	pub fn get_roll_context(&self) -> RollContext {
+		let mut context = RollContext::new();
+		for ability in ABILITIES {
+			if let Some(modifier) = self.get_modifier(ability) {
+				context.insert(ability.to_owned(), modifier);
+			}
+		}
+		for (skill, _, _) in SKILLS {
+			if let Some(bonus) = self.get_check_bonus(skill) {
+				context.insert(skill.replace(' ', ""), bonus);
+			}
+		}
+		context.insert("prof".to_owned(), self.proficiency_bonus);
+		context
+	}
+
+	pub fn format_character(&self) -> String {
+		let mut result = format!(
+			"<b>{}</b>\nProficiency Bonus: +{}",
+			self.name.escape_html(),
+			self.proficiency_bonus
+		);
+		let abilities = ABILITIES
+			.iter()
+			.filter_map(|ability| {
+				let score = self.abilities.get(*ability)?;
+				Some(format!(
+					"{}: {score} ({})",
+					ability.to_uppercase(),
+					format_bonus(score_to_modifier(*score))
+				))
+			})
+			.collect::<Vec<_>>()
+			.join(", ");
+		let _ = write!(result, "\n{abilities}");
+		let saves = self
+			.saves
+			.iter()
+			.map(|(ability, bonus)| format!("{ability} {}", format_bonus(*bonus)))
+			.collect::<Vec<_>>();
+		if !saves.is_empty() {
+			let _ = write!(result, "\n<b>Saving Throws</b>: {}", saves.join(", "));
+		}
+		let skills = self
+			.skills
+			.iter()
+			.map(|(skill, bonus)| format!("{skill} {}", format_bonus(*bonus)))
+			.collect::<Vec<_>>();
+		if !skills.is_empty() {
+			let _ = write!(result, "\n<b>Skills</b>: {}", skills.join(", "));
+		}
+		for attack in &self.attacks {
+			let _ = write!(
+				result,
+				"\n<b>{}</b>: {} to hit",
+				attack.name.escape_html(),
+				format_bonus(attack.to_hit)
+			);
+			if let Some(damage) = &attack.damage {
+				let _ = write!(result, ", {} damage", damage.escape_html());
+			}
+		}
+		result
+	}
+}
+
+impl From<&Character> for Document {
+	fn from(character: &Character) -> Self {
+		let to_doc = |map: &BTreeMap<String, i64>| {
+			let mut doc = Document::new();
+			for (key, value) in map {
+				doc.insert(key.clone(), *value);
+			}
+			doc
+		};
+		let attacks = character
+			.attacks
+			.iter()
+			.map(|attack| {
+				let mut doc = Document::new();
+				doc.insert("name", attack.name.clone());
+				doc.insert("to_hit", attack.to_hit);
+				if let Some(damage) = &attack.damage {
+					doc.insert("damage", damage.clone());
+				}
+				Bson::Document(doc)
+			})
+			.collect::<Vec<_>>();
+
+		let mut doc = Document::new();
+		doc.insert("name", character.name.clone());
+		doc.insert("abilities", to_doc(&character.abilities));
+		doc.insert("proficiency_bonus", character.proficiency_bonus);
+		doc.insert("saves", to_doc(&character.saves));
+		doc.insert("skills", to_doc(&character.skills));
+		doc.insert("attacks", attacks);
+		doc
+	}
+}
+
+impl TryFrom<&Document> for Character {
+	type Error = bson::document::ValueAccessError;
+
+	fn try_from(doc: &Document) -> Result<Self, Self::Error> {
+		let from_doc = |key: &str| -> Result<BTreeMap<String, i64>, Self::Error> {
+			Ok(doc
+				.get_document(key)?
+				.iter()
+				.filter_map(|(key, value)| Some((key.clone(), get_number(value)?)))
+				.collect())
+		};
+		Ok(Self {
+			name: doc.get_str("name")?.to_owned(),
+			abilities: from_doc("abilities")?,
+			proficiency_bonus: doc.get_i64("proficiency_bonus")?,
+			saves: from_doc("saves")?,
+			skills: from_doc("skills")?,
+			attacks: doc
+				.get_array("attacks")?
+				.iter()
+				.filter_map(Bson::as_document)
+				.filter_map(|attack| {
+					Some(Attack {
+						name: attack.get_str("name").ok()?.to_owned(),
+						to_hit: attack.get_i64("to_hit").ok()?,
+						damage: attack.get_str("damage").map(str::to_owned).ok(),
+					})
+				})
+				.collect(),
+		})
+	}
+}
+
+fn action_to_attack(action: &Document) -> Option<Attack> {
+	lazy_static! {
+		static ref HIT_REGEX: Regex = Regex::new(r"\{@hit\s+(?P<hit>[+-]?\d+)\}").unwrap();
+		static ref DAMAGE_REGEX: Regex = Regex::new(r"\{@damage\s+(?P<damage>[^}|]+)").unwrap();
+	}
+
+	let name = action.get_str("name").ok()?;
+	let entries = action
+		.get_array_of("entries", Bson::as_str)
+		.unwrap_or_default()
+		.join(" ");
+	let to_hit = HIT_REGEX.captures(&entries)?["hit"].parse().ok()?;
+	let damage = DAMAGE_REGEX
+		.captures(&entries)
+		.map(|caps| caps["damage"].trim().to_owned());
+	Some(Attack {
+		name: name.to_owned(),
+		to_hit,
+		damage,
+	})
+}
+
+// Name of the "@" modifier for an ability or a skill, e.g. "Sleight of Hand" → "sleightofhand"
+pub fn get_context_key(name: &str) -> Option<String> {
+	let name = name.trim().to_lowercase();
+	if let Some(ability) = parse_ability(&name) {
+		return Some(ability.to_owned());
+	}
+	SKILLS
+		.iter()
+		.find(|(skill, _, _)| *skill == name)
+		.map(|(skill, _, _)| skill.replace(' ', ""))
+}
+
+// Whether "@key" is one of the modifiers of `Character::get_roll_context`
+pub fn is_roll_context_key(key: &str) -> bool {
+	let key = key.to_lowercase();
+	key == "prof"
+		|| ABILITIES.contains(&key.as_str())
+		|| SKILLS
+			.iter()
+			.any(|(skill, _, _)| skill.replace(' ', "") == key)
+}
+
+fn parse_ability(name: &str) -> Option<&'static str> {
+	ABILITIES.iter().copied().find(|ability| {
+		*ability == name
+			|| match *ability {
+				"str" => name == "strength",
+				"dex" => name == "dexterity",
+				"con" => name == "constitution",
+				"int" => name == "intelligence",
+				"wis" => name == "wisdom",
+				"cha" => name == "charisma",
+				_ => false,
+			}
+	})
+}
+
+fn score_to_modifier(score: i64) -> i64 {
+	(score - 10).div_euclid(2)
+}
+
+fn level_to_proficiency_bonus(level: i64) -> i64 {
+	2 + (level.max(1) - 1) / 4
+}
+
+fn format_bonus(bonus: i64) -> String {
+	if bonus >= 0 {
+		format!("+{bonus}")
+	} else {
+		bonus.to_string()
+	}
+}
+
+// Numbers come as integers, floats or strings like "+5"
+fn get_number(value: &Bson) -> Option<i64> {
+	match value {
+		Bson::Int32(n) => Some(*n as i64),
+		Bson::Int64(n) => Some(*n),
+		Bson::Double(n) => Some(*n as i64),
+		Bson::String(s) => s.trim().trim_start_matches('+').parse().ok(),
+		_ => None,
+	}
+}
+
+// Foundry proficiency: 0, 0.5 (half), 1 or 2 (expertise), sometimes a boolean
+fn get_multiplier(value: &Bson) -> Option<f64> {
+	match value {
+		Bson::Boolean(b) => Some(if *b { 1.0 } else { 0.0 }),
+		Bson::Double(n) => Some(*n),
+		value => get_number(value).map(|n| n as f64),
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn fighter() -> Character {
+		let json = serde_json::json!({
+			"name": "Bob",
+			"level": 5,
+			"str": 16,
+			"dex": 13,
+			"con": 14,
+			"int": 8,
+			"wis": 12,
+			"cha": 10,
+			"save": {"str": "+6"},
+			"skill": {"athletics": "+6"},
+			"attacks": [{"name": "Longsword", "toHit": 6, "damage": "1d8+3"}],
+		});
+		let doc = match crate::storage::json_to_bson(json) {
+			Bson::Document(doc) => doc,
+			_ => unreachable!(),
+		};
+		Character::import(&doc).unwrap()
+	}
+
+	#[test]
+	fn test_import() {
+		let bob = fighter();
+		assert_eq!(bob.proficiency_bonus, 3);
+		assert_eq!(bob.get_check_bonus("athletics"), Some(6));
+		assert_eq!(bob.get_check_bonus("stealth"), Some(1));
+		assert_eq!(bob.get_check_bonus("Intelligence"), Some(-1));
+		assert_eq!(bob.get_save_bonus("str"), Some(6));
+		assert_eq!(bob.get_save_bonus("wis"), Some(1));
+		assert_eq!(bob.find_attack("long").map(|a| a.to_hit), Some(6));
+		assert_eq!(bob.get_roll_context().get("stealth"), Some(&1));
+		assert_eq!(
+			get_context_key("Sleight of Hand"),
+			Some("sleightofhand".to_owned())
+		);
+		assert_eq!(get_context_key("dexterity"), Some("dex".to_owned()));
+		assert_eq!(get_context_key("longsword"), None);
+	}
+
+	#[test]
+	fn test_storage_roundtrip() {
+		let bob = fighter();
+		assert_eq!(Character::try_from(&Document::from(&bob)).ok(), Some(bob));
+	}
+
+	#[test]
+	fn test_action_to_attack() {
+		let mut action = Document::new();
+		action.insert("name", "Scimitar");
+		action.insert(
+			"entries",
+			vec![Bson::String(
+				"{@atk mw} {@hit 4} to hit, reach 5 ft., one target. {@h}5 ({@damage 1d6 + 2}) slashing damage."
+					.to_owned(),
+			)],
+		);
+		assert_eq!(
+			action_to_attack(&action),
+			Some(Attack {
+				name: "Scimitar".to_owned(),
+				to_hit: 4,
+				damage: Some("1d6 + 2".to_owned()),
+			})
+		);
+	}
+}
@@ -1,86 +1,100 @@
-use std::collections::HashMap;
+use std::fmt::Write;
 
-use crate::db::LogMessage;
 use crate::get_unix_time;
+use crate::stats::Summary;
+use crate::storage::CollectionInfo;
+use crate::update::UpdateStatus;
 
-pub fn format_collection_metadata(meta: ejdb::meta::DatabaseMetadata) -> String {
-	meta.collections()
+use super::utils::HtmlEscapable;
+
+pub fn format_collection_metadata(collections: &[CollectionInfo]) -> String {
+	collections
+		.iter()
 		.map(|col| {
 			format!(
 				"<code>{}</code>: <code>{}</code> records",
-				col.name(),
-				col.records()
+				col.name, col.records
 			)
 		})
 		.collect::<Vec<_>>()
 		.join("\n")
 }
 
-pub fn format_message_stats(msgs: Vec<LogMessage>) -> Result<String, ejdb::Error> {
-	let now = get_unix_time();
-	let mount_ago = now - 60 * 60 * 24 * 30;
-
-	let msg_total = msgs.len();
-	let msg_total_month = msgs.iter().filter(|msg| msg.timestamp >= mount_ago).count();
-
-	let max_latency = msgs.iter().map(|msg| msg.latency).max().unwrap_or_default() as usize;
-	let max_latency_month = msgs
-		.iter()
-		.filter(|msg| msg.timestamp >= mount_ago)
-		.map(|msg| msg.latency)
-		.max()
-		.unwrap_or_default() as usize;
+// Usage since last month / total
+pub fn format_message_stats(month: &Summary, total: &Summary) -> String {
+	format!(
+		"Total messages: <code>{}</code> / <code>{}</code>
+Unique users: <code>{}</code> / <code>{}</code>
+Max latency, ms: <code>{}</code> / <code>{}</code>
+P95 latency, ms: <code>{}</code> / <code>{}</code>
+Median latency, ms: <code>{}</code> / <code>{}</code>
+Avg latency, ms: <code>{}</code> / <code>{}</code>
+Min latency, ms: <code>{}</code> / <code>{}</code>",
+		month.messages,
+		total.messages,
+		month.users,
+		total.users,
+		month.latency.max(),
+		total.latency.max(),
+		month.latency.percentile(95),
+		total.latency.percentile(95),
+		month.latency.percentile(50),
+		total.latency.percentile(50),
+		month.latency.avg(),
+		total.latency.avg(),
+		month.latency.min(),
+		total.latency.min(),
+	)
+}
 
-	let min_latency = msgs.iter().map(|msg| msg.latency).min().unwrap_or_default() as usize;
-	let min_latency_month = msgs
-		.iter()
-		.filter(|msg| msg.timestamp >= mount_ago)
-		.map(|msg| msg.latency)
-		.min()
-		.unwrap_or_default() as usize;
+// "42s", "5m", "3h" or "2d"
+pub fn format_ago(secs: u64) -> String {
+	match secs {
+		0..=60 => format!("{secs}s"),
+		61..=3600 => format!("{}m", secs / 60),
+		3601..=86400 => format!("{}h", secs / 60 / 60),
+		86401..=u64::MAX => format!("{}d", secs / 60 / 60 / 24),
+	}
+}
 
-	let avg_latency = match msg_total {
-		0 => 0,
-		_ => msgs.iter().fold(0, |acc, msg| acc + msg.latency) as usize / msg_total,
+pub fn format_update_status(status: Option<UpdateStatus>) -> String {
+	let Some(status) = status else {
+		return "No data updates since the bot started".to_owned();
 	};
-
-	let avg_latency_month = match msg_total_month {
-		0 => 0,
-		_ => {
-			msgs.iter()
-				.filter(|msg| msg.timestamp >= mount_ago)
-				.fold(0, |acc, msg| acc + msg.latency) as usize
-				/ msg_total_month
+	let mut result = format!(
+		"<b>Last update</b>: {}{}\nFinished <code>{}</code> ago",
+		status.outcome.escape_html(),
+		if status.forced { " (forced)" } else { "" },
+		format_ago(get_unix_time().saturating_sub(status.timestamp)),
+	);
+	if let Some(mirror) = status.mirror {
+		write!(result, "\nMirror: <code>{}</code>", mirror.escape_html()).unwrap();
+	}
+	if let Some(ver) = status.ver {
+		write!(result, "\nVersion: <code>{}</code>", ver.escape_html()).unwrap();
+	}
+	if let Some(report) = status.report {
+		result.push_str("\n\n<b>Collections</b>");
+		for (collection, count) in report.loaded {
+			write!(
+				result,
+				"\n<code>{}</code>: {count} items",
+				collection.escape_html()
+			)
+			.unwrap();
 		}
-	};
-
-	let users: HashMap<i64, u64> = {
-		let mut users = HashMap::new();
-
-		msgs.iter().for_each(|msg| {
-			let old_ts = users.get(&msg.user_id);
-			match old_ts {
-				None => {
-					users.insert(msg.user_id, msg.timestamp);
-				}
-				Some(old_ts) => {
-					if old_ts < &msg.timestamp {
-						users.insert(msg.user_id, msg.timestamp);
-					}
-				}
+		if !report.failed.is_empty() {
+			result.push_str("\n\n<b>Failed</b>");
+			for (collection, err) in report.failed {
+				write!(
+					result,
+					"\n<code>{}</code>: {}",
+					collection.escape_html(),
+					err.escape_html()
+				)
+				.unwrap();
 			}
-		});
-		users
-	};
-
-	let users_total = users.len();
-	let users_total_month = users.iter().filter(|(_, ts)| ts >= &&mount_ago).count();
-
-	Ok(format!(
-		"Total messages: <code>{msg_total_month}</code> / <code>{msg_total}</code>
-Unique users: <code>{users_total_month}</code> / <code>{users_total}</code>
-Max latency, ms: <code>{max_latency_month}</code> / <code>{max_latency}</code>
-Avg latency, ms: <code>{avg_latency_month}</code> / <code>{avg_latency}</code>
-Min latency, ms: <code>{min_latency_month}</code> / <code>{min_latency}</code>",
-	))
+		}
+	}
+	result
 }
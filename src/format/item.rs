@@ -3,7 +3,7 @@ use super::{
 	FilterJoinable, Optionable,
 };
 use crate::DB;
-use ejdb::bson::{Bson, Document};
+use bson::{Bson, Document};
 use std::fmt::Write;
 
 pub trait Item: Entry {
@@ -116,17 +116,17 @@ impl Item for Document {
 		}
 
 		if let Some(entries) = self.get_entries("entries") {
-			write!(s, "\n\n{}", &entries.join("\n")).ok()?;
+			write!(s, "\n\n{}", entries.join("\n")).ok()?;
 		}
 		if let Some(entries) = type_abbreviation.and_then(|t| t.get_entries("entries")) {
-			write!(s, "\n\n{}", &entries.join("\n")).ok()?;
+			write!(s, "\n\n{}", entries.join("\n")).ok()?;
 		}
 		if let Some(entries) = type_additional_abbreviation.and_then(|t| t.get_entries("entries")) {
-			write!(s, "\n\n{}", &entries.join("\n")).ok()?;
+			write!(s, "\n\n{}", entries.join("\n")).ok()?;
 		}
 		for t in property_abbreviations {
 			if let Some(entries) = t.and_then(|t| t.get_entries("entries")) {
-				write!(s, "\n\n{}", &entries.join("\n")).ok()?;
+				write!(s, "\n\n{}", entries.join("\n")).ok()?;
 			}
 		}
 
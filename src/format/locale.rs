@@ -0,0 +1,614 @@
+use std::{fmt, str::FromStr};
+
+use super::roll::DieFormatError;
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum Lang {
+	#[default]
+	En,
+	Ru,
+}
+
+pub const LANGUAGES: [Lang; 2] = [Lang::En, Lang::Ru];
+
+impl Lang {
+	pub fn code(self) -> &'static str {
+		match self {
+			Lang::En => "en",
+			Lang::Ru => "ru",
+		}
+	}
+
+	pub fn name(self) -> &'static str {
+		match self {
+			Lang::En => "English",
+			Lang::Ru => "Русский",
+		}
+	}
+
+	// Telegram sends IETF tags like "en" or "ru-RU", unknown languages get English
+	pub fn from_language_code(code: &str) -> Option<Self> {
+		let code = code.split(['-', '_']).next()?;
+		Self::from_str(code).ok()
+	}
+}
+
+impl FromStr for Lang {
+	type Err = ();
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.trim().to_lowercase().as_str() {
+			"en" | "eng" | "english" => Ok(Lang::En),
+			"ru" | "rus" | "russian" | "русский" => Ok(Lang::Ru),
+			_ => Err(()),
+		}
+	}
+}
+
+// Every reply has a key, the catalog below has its text in each language.
+// Keys with an argument replace the "{}" of the text with it
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Msg {
+	// Language selection
+	NowISpeak(Lang),
+	ISpeak(Lang),
+	UnknownLang,
+	// Settings
+	ChatSettings,
+	PreferredSources(String),
+	HomebrewOff,
+	Homebrew(String),
+	AvailableHomebrew(String),
+	HomebrewHint,
+	UnknownHomebrew(String),
+	SettingsHint,
+	DefaultDie(u16),
+	CritDouble,
+	CritMax,
+	CritOff,
+	RerollEdits,
+	RerollSends,
+	LanguageAuto,
+	Language(Lang),
+	SettingsUsage,
+	OnlyChatAdmins,
+	OnlyBotAdmins,
+	// Personal data
+	MyDataInPrivate,
+	NothingLogged,
+	LoggedMessages(usize),
+	DeletedMessages(usize),
+	// Buttons
+	Reroll,
+	RollAgain,
+	SourceCode,
+	BuyMeACoffee,
+	News,
+	Chat,
+	// The argument is the table caption
+	RollOn(String),
+	RollOnThisTable,
+	CastAtLevel(i64),
+	// Roll buttons and comments, the argument is the bonus, the dice or the attack name
+	ToHit(String),
+	Damage(String),
+	// Headings, the argument is the user name
+	Rolls(String),
+	Finds(String),
+	// Follows the user name, the argument is the table caption
+	RollsOn(String),
+	RollsOnTheTable,
+	CriticalHit,
+	// Search, the argument is the command, e.g. "spell"
+	SearchPrompt(String),
+	SearchPlaceholder(String),
+	NotFound(String),
+	FoundNames(String),
+	NothingLikeThis(String),
+	EntriesFound(usize),
+	NothingFound,
+	TableNotFound,
+	// Rolls
+	Roll(DieFormatError),
+	// Command errors
+	FindUsage,
+	LootUsage,
+	RandomUsage,
+	SpawnUsage(usize),
+	SpawnNoHp(String),
+	HpUsage,
+	CondUsage,
+	CombatUsage,
+	// The argument is an example, e.g. "check stealth"
+	CharacterUsage(&'static str),
+	SlotsUsage,
+	RestUsage,
+	AdminUsage,
+	// Characters
+	NoCharacter,
+	NoCharacterInFile,
+	// The argument is the name of the first attack
+	CharacterImported(String),
+	UnknownSkill,
+	UnknownAbility,
+	UnknownAttack,
+	// Resources
+	UnknownResource,
+	NotEnoughResource,
+	NoResources,
+	ResourcesCleared,
+	Used(i64),
+	ShortRest(String),
+	LongRest(String),
+	// Combat
+	NoCombatants,
+	CombatOver,
+	NotInCombat(String),
+}
+
+pub fn tr(lang: Lang, msg: Msg) -> String {
+	let (en, ru, arg) = msg.text();
+	let text = match lang {
+		Lang::En => en,
+		Lang::Ru => ru,
+	};
+	// The argument is never parsed, so user names with "{}" stay as they are
+	match (text.split_once("{}"), arg) {
+		(Some((prefix, suffix)), Some(arg)) => format!("{prefix}{arg}{suffix}"),
+		_ => text.to_owned(),
+	}
+}
+
+impl fmt::Display for Msg {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(&tr(Lang::En, self.clone()))
+	}
+}
+
+impl Msg {
+	// (English, Russian, argument)
+	fn text(self) -> (&'static str, &'static str, Option<String>) {
+		use Msg::*;
+		match self {
+			NowISpeak(lang) => (
+				"Now I speak {}",
+				"Теперь мой язык — {}",
+				Some(lang.name().to_owned()),
+			),
+			ISpeak(lang) => (
+				"I speak {} in this chat. Switch the language with <code>/lang en</code> or <code>/lang ru</code>",
+				"В этом чате мой язык — {}. Сменить язык: <code>/lang en</code> или <code>/lang ru</code>",
+				Some(lang.name().to_owned()),
+			),
+			UnknownLang => (
+				"Nope, I don't speak this language yet. Try <code>/lang en</code> or <code>/lang ru</code>",
+				"Нет, этот язык я пока не знаю. Попробуй <code>/lang en</code> или <code>/lang ru</code>",
+				None,
+			),
+			ChatSettings => ("Chat settings", "Настройки чата", None),
+			PreferredSources(sources) => (
+				"Preferred sources: {}",
+				"Предпочитаемые источники: {}",
+				Some(sources),
+			),
+			HomebrewOff => ("Homebrew: off", "Homebrew: выключен", None),
+			Homebrew(sources) => ("Homebrew: {}", "Homebrew: {}", Some(sources)),
+			AvailableHomebrew(packs) => (
+				"Available homebrew: {}",
+				"Доступный homebrew: {}",
+				Some(packs),
+			),
+			HomebrewHint => (
+				"Homebrew is enabled with <code>/settings homebrew ToB CoS</code>",
+				"Homebrew включается так: <code>/settings homebrew ToB CoS</code>",
+				None,
+			),
+			UnknownHomebrew(sources) => (
+				"Unknown homebrew: {}. Only the available packs from <code>/settings</code> can be enabled",
+				"Неизвестный homebrew: {}. Включить можно только доступные наборы из <code>/settings</code>",
+				Some(sources),
+			),
+			SettingsHint => (
+				"Tap a button to change a setting. Sources are changed with <code>/settings sources PHB XGE</code>",
+				"Нажми на кнопку, чтобы изменить настройку. Источники меняются так: <code>/settings sources PHB XGE</code>",
+				None,
+			),
+			DefaultDie(die) => (
+				"🎲 Default die: d{}",
+				"🎲 Кубик по умолчанию: d{}",
+				Some(die.to_string()),
+			),
+			CritDouble => (
+				"💥 Critical hits: double dice",
+				"💥 Критические попадания: удвоить кубики",
+				None,
+			),
+			CritMax => (
+				"💥 Critical hits: max + roll",
+				"💥 Критические попадания: максимум + бросок",
+				None,
+			),
+			CritOff => (
+				"💥 Critical hits: off",
+				"💥 Критические попадания: выключены",
+				None,
+			),
+			RerollEdits => (
+				"🔁 Reroll: edits the message",
+				"🔁 Переброс: изменяет сообщение",
+				None,
+			),
+			RerollSends => (
+				"🔁 Reroll: sends a new message",
+				"🔁 Переброс: отправляет новое сообщение",
+				None,
+			),
+			LanguageAuto => ("🌐 Language: auto", "🌐 Язык: автоматически", None),
+			Language(lang) => (
+				"🌐 Language: {}",
+				"🌐 Язык: {}",
+				Some(lang.name().to_owned()),
+			),
+			SettingsUsage => (
+				"Nope, I can't understand that. Try something like <code>/settings die 100</code>, <code>/settings crit max</code>, <code>/settings sources PHB XGE</code>, <code>/settings reroll edit</code> or <code>/settings lang ru</code>",
+				"Нет, я не понимаю. Попробуй что-то вроде <code>/settings die 100</code>, <code>/settings crit max</code>, <code>/settings sources PHB XGE</code>, <code>/settings reroll edit</code> или <code>/settings lang ru</code>",
+				None,
+			),
+			OnlyChatAdmins => (
+				"Only chat admins can change the settings",
+				"Только администраторы чата могут менять настройки",
+				None,
+			),
+			OnlyBotAdmins => (
+				"Only bot admins can do that",
+				"Это могут только администраторы бота",
+				None,
+			),
+			MyDataInPrivate => (
+				"Send me <code>/mydata</code> in a private chat, so nobody else sees your data",
+				"Отправь мне <code>/mydata</code> в личном чате, чтобы никто больше не увидел твои данные",
+				None,
+			),
+			NothingLogged => (
+				"I haven't logged anything about you",
+				"Я ничего о тебе не сохранял",
+				None,
+			),
+			LoggedMessages(count) => (
+				"Messages I logged about you: {}",
+				"Сохранённых сообщений: {}",
+				Some(count.to_string()),
+			),
+			DeletedMessages(count) => (
+				"Deleted the messages I logged about you: {}",
+				"Удалено сохранённых сообщений: {}",
+				Some(count.to_string()),
+			),
+			Reroll => ("Reroll", "Перебросить", None),
+			RollAgain => ("🎲 Roll again", "🎲 Бросить ещё раз", None),
+			SourceCode => ("Source Code", "Исходный код", None),
+			BuyMeACoffee => ("Buy me a coffee", "Угостить кофе", None),
+			News => ("News", "Новости", None),
+			Chat => ("Chat", "Чат", None),
+			RollOn(caption) => ("🎲 Roll on {}", "🎲 Бросить: {}", Some(caption)),
+			RollOnThisTable => ("🎲 Roll on this table", "🎲 Бросить по таблице", None),
+			CastAtLevel(level) => (
+				"✨ Cast at level {}",
+				"✨ Сотворить на уровне {}",
+				Some(level.to_string()),
+			),
+			ToHit(text) => ("{} to hit", "{} на попадание", Some(text)),
+			Damage(text) => ("{} damage", "урон {}", Some(text)),
+			Rolls(name) => ("<b>{} rolls:</b>", "<b>{} бросает:</b>", Some(name)),
+			Finds(name) => ("<b>{} finds:</b>", "<b>{} находит:</b>", Some(name)),
+			RollsOn(caption) => ("rolls on {}", "бросает по таблице «{}»", Some(caption)),
+			RollsOnTheTable => ("rolls on the table", "бросает по таблице", None),
+			CriticalHit => ("💥 Critical hit!", "💥 Критическое попадание!", None),
+			// The second word must stay the command, see `extract_search_data_from_reply`
+			SearchPrompt(command) => (
+				"What {} should I look for? Please, <b>reply</b> to this message with a name:",
+				"Какой {} мне найти? Пожалуйста, <b>ответь</b> на это сообщение названием:",
+				Some(command),
+			),
+			SearchPlaceholder(command) => ("{} to search", "{}: что искать", Some(command)),
+			NotFound(command) => (
+				"Can't find any {} with this name, sorry :(",
+				"Не могу найти {} с таким названием, извини :(",
+				Some(command),
+			),
+			FoundNames(command) => (
+				"I've found these {} names:",
+				"Вот что я нашёл ({}):",
+				Some(command),
+			),
+			NothingLikeThis(command) => (
+				"Can't find any {} like this, sorry :(",
+				"Не могу найти подходящий {}, извини :(",
+				Some(command),
+			),
+			EntriesFound(count) => (
+				"Entries found: {}",
+				"Найдено записей: {}",
+				Some(count.to_string()),
+			),
+			NothingFound => (
+				"Can't find anything with these words, sorry :(",
+				"Не могу найти ничего с этими словами, извини :(",
+				None,
+			),
+			TableNotFound => (
+				"I can't find this table, sorry :(",
+				"Не могу найти эту таблицу, извини :(",
+				None,
+			),
+			Roll(err) => match err {
+				DieFormatError::TooLongText => (
+					"Wow, that was a lot of text! Too bad I'm too lazy to read it :)",
+					"Ого, сколько текста! Жаль, мне лень его читать :)",
+					None,
+				),
+				DieFormatError::TooManyRolls => (
+					"I don't have that many dices!",
+					"У меня нет столько кубиков!",
+					None,
+				),
+				DieFormatError::TooManyDice => (
+					"Nope, I don't have that many dices!",
+					"Нет, у меня нет столько кубиков!",
+					None,
+				),
+				DieFormatError::UnknownDie => (
+					"Nope, I don't have that kind of dice!",
+					"Нет, у меня нет такого кубика!",
+					None,
+				),
+				DieFormatError::Nonsense => (
+					"Nope, that doesn't make any sense",
+					"Нет, это не имеет никакого смысла",
+					None,
+				),
+				DieFormatError::BigNumber => (
+					"Wow, that's a big number!",
+					"Ого, какое большое число!",
+					None,
+				),
+				DieFormatError::Unexpected => (
+					"Wow, an error occurred, which shouldn't happen 🤔. Are you happy?",
+					"Ого, случилась ошибка, которой не должно было быть 🤔. Доволен?",
+					None,
+				),
+				DieFormatError::Unparsable => (
+					"Can't parse your message, sorry",
+					"Не могу разобрать твоё сообщение, извини",
+					None,
+				),
+				DieFormatError::NothingToRoll => (
+					"Err, sorry, I can't roll that. Maybe you need some /help ?",
+					"Эм, извини, я не могу это бросить. Может, нужна /help ?",
+					None,
+				),
+				DieFormatError::UnknownModifier => (
+					"Nope, I don't know this modifier. Did you import your character?",
+					"Нет, я не знаю этот модификатор. Ты загрузил своего персонажа?",
+					None,
+				),
+				DieFormatError::EmptyTable => (
+					"Can't roll on this table, sorry",
+					"Не могу бросить по этой таблице, извини",
+					None,
+				),
+			},
+			FindUsage => (
+				"Nope, I need to know what to look for. Try something like <code>/find frightened</code>",
+				"Нет, мне нужно знать, что искать. Попробуй что-то вроде <code>/find frightened</code>",
+				None,
+			),
+			LootUsage => (
+				"Nope, I can't understand that. Try something like <code>/loot cr:5 hoard</code>",
+				"Нет, я не понимаю. Попробуй что-то вроде <code>/loot cr:5 hoard</code>",
+				None,
+			),
+			RandomUsage => (
+				"Nope, I can't understand that. Try something like <code>/random monster env:forest cr:1-3</code>",
+				"Нет, я не понимаю. Попробуй что-то вроде <code>/random monster env:forest cr:1-3</code>",
+				None,
+			),
+			SpawnUsage(max) => (
+				"Nope, I can't spawn that. Try something like <code>/spawn 4 goblin</code> (up to {} monsters)",
+				"Нет, я не могу это призвать. Попробуй что-то вроде <code>/spawn 4 goblin</code> (не больше {} монстров)",
+				Some(max.to_string()),
+			),
+			SpawnNoHp(monster) => (
+				"I can't roll the HP of {}, set it with <code>/hp name 30</code>",
+				"Не могу бросить хиты для {}, задай их так: <code>/hp name 30</code>",
+				Some(monster),
+			),
+			HpUsage => (
+				"Nope, I can't understand that. Try something like <code>/hp goblin2 -7</code>",
+				"Нет, я не понимаю. Попробуй что-то вроде <code>/hp goblin2 -7</code>",
+				None,
+			),
+			CondUsage => (
+				"Nope, I can't understand that. Try something like <code>/cond goblin2 +prone</code>",
+				"Нет, я не понимаю. Попробуй что-то вроде <code>/cond goblin2 +prone</code>",
+				None,
+			),
+			CombatUsage => (
+				"Nope, I can't understand that. Try <code>/combat</code> or <code>/combat end</code>",
+				"Нет, я не понимаю. Попробуй <code>/combat</code> или <code>/combat end</code>",
+				None,
+			),
+			CharacterUsage(example) => (
+				"Nope, I need to know what to roll. Try something like <code>/{}</code>",
+				"Нет, мне нужно знать, что бросать. Попробуй что-то вроде <code>/{}</code>",
+				Some(example.to_owned()),
+			),
+			SlotsUsage => (
+				"Nope, I can't understand that. Try something like <code>/slots 4 3 2</code>, <code>/slots ki 5 short</code> or <code>/slots use 3</code>",
+				"Нет, я не понимаю. Попробуй что-то вроде <code>/slots 4 3 2</code>, <code>/slots ki 5 short</code> или <code>/slots use 3</code>",
+				None,
+			),
+			RestUsage => (
+				"Nope, I can't understand that. Try <code>/rest long</code> or <code>/rest short</code>",
+				"Нет, я не понимаю. Попробуй <code>/rest long</code> или <code>/rest short</code>",
+				None,
+			),
+			AdminUsage => (
+				"Nope, I can't understand that. Try <code>/admin update</code>, <code>/admin status</code> or <code>/admin rollback</code>",
+				"Нет, я не понимаю. Попробуй <code>/admin update</code>, <code>/admin status</code> или <code>/admin rollback</code>",
+				None,
+			),
+			NoCharacter => (
+				"I don't know your character yet. Send me a JSON file with it (e.g., a Foundry VTT actor export), in groups add the <code>/character</code> caption",
+				"Я пока не знаю твоего персонажа. Пришли мне JSON-файл с ним (например, экспорт актёра из Foundry VTT), в группах добавь подпись <code>/character</code>",
+				None,
+			),
+			NoCharacterInFile => (
+				"I can't find a character in this file, sorry :(\nI understand 5etools-like stat blocks and Foundry VTT actor exports",
+				"Не могу найти персонажа в этом файле, извини :(\nЯ понимаю блоки статистики как в 5etools и экспорт актёров из Foundry VTT",
+				None,
+			),
+			CharacterImported(attack) => (
+				"Got it! Now you can use <code>/check stealth</code>, <code>/save dex</code> or <code>/attack {}</code>",
+				"Готово! Теперь можно использовать <code>/check stealth</code>, <code>/save dex</code> или <code>/attack {}</code>",
+				Some(attack),
+			),
+			UnknownSkill => (
+				"Nope, I don't know this skill or ability",
+				"Нет, я не знаю такой навык или характеристику",
+				None,
+			),
+			UnknownAbility => (
+				"Nope, I don't know this ability",
+				"Нет, я не знаю такую характеристику",
+				None,
+			),
+			UnknownAttack => (
+				"Nope, your character doesn't have this attack",
+				"Нет, у твоего персонажа нет такой атаки",
+				None,
+			),
+			UnknownResource => (
+				"Nope, you don't have this resource. Set it up first, e.g. <code>/slots ki 5 short</code>",
+				"Нет, у тебя нет такого ресурса. Сначала добавь его, например <code>/slots ki 5 short</code>",
+				None,
+			),
+			NotEnoughResource => (
+				"Nope, you don't have enough of it left. Maybe it's time for a rest?",
+				"Нет, у тебя столько не осталось. Может, пора отдохнуть?",
+				None,
+			),
+			NoResources => (
+				"You don't have any resources yet. Set up spell slots with <code>/slots 4 3 2</code> or anything else with <code>/slots ki 5 short</code>",
+				"У тебя пока нет ресурсов. Задай ячейки заклинаний с помощью <code>/slots 4 3 2</code> или что-то другое с помощью <code>/slots ki 5 short</code>",
+				None,
+			),
+			ResourcesCleared => (
+				"All your resources are gone",
+				"Все твои ресурсы удалены",
+				None,
+			),
+			Used(amount) => ("Used {}", "Потрачено: {}", Some(amount.to_string())),
+			ShortRest(name) => (
+				"<b>{} takes a short rest</b>",
+				"<b>{} отдыхает (короткий отдых)</b>",
+				Some(name),
+			),
+			LongRest(name) => (
+				"<b>{} takes a long rest</b>",
+				"<b>{} отдыхает (продолжительный отдых)</b>",
+				Some(name),
+			),
+			NoCombatants => (
+				"There is no one in combat. Use <code>/spawn 4 goblin</code> or <code>/hp name 30</code> to add combatants",
+				"В бою никого нет. Добавь участников с помощью <code>/spawn 4 goblin</code> или <code>/hp name 30</code>",
+				None,
+			),
+			CombatOver => (
+				"The combat is over. Time for a short rest!",
+				"Бой окончен. Время для короткого отдыха!",
+				None,
+			),
+			NotInCombat(name) => (
+				"I can't find {} in this combat. Check <code>/combat</code> or add them with <code>/hp name 30</code>",
+				"Не могу найти {} в этом бою. Проверь <code>/combat</code> или добавь с помощью <code>/hp name 30</code>",
+				Some(name),
+			),
+		}
+	}
+}
+
+// Descriptions of the menu commands, keyed by the command. English ones are in `bot_commands`
+pub fn command_description(lang: Lang, command: &str) -> Option<&'static str> {
+	if lang != Lang::Ru {
+		return None;
+	}
+	Some(match command {
+		"roll" => "Бросить кубик (по умолчанию d20)",
+		"spell" => "Найти заклинание",
+		"item" => "Найти предмет",
+		"monster" => "Найти монстра",
+		"rule" => "Найти правило, действие, чувство или навык",
+		"find" => "Искать по тексту заклинаний, предметов и монстров",
+		"loot" => "Бросить случайное сокровище",
+		"random" => "Показать случайного монстра",
+		"spawn" => "Призвать монстров со случайными хитами",
+		"hp" => "Ранить или вылечить участника боя",
+		"cond" => "Добавить или снять состояние",
+		"combat" => "Показать состояние боя",
+		"check" => "Проверка навыка или характеристики персонажа",
+		"save" => "Спасбросок персонажа",
+		"attack" => "Атака персонажа",
+		"character" => "Показать загруженного персонажа",
+		"slots" => "Ячейки заклинаний и другие ресурсы",
+		"rest" => "Короткий или продолжительный отдых",
+		"lang" => "Выбрать язык бота",
+		"settings" => "Изменить настройки чата",
+		"mydata" => "Выгрузить данные, которые бот сохранил о тебе",
+		"forgetme" => "Удалить данные, которые бот сохранил о тебе",
+		"help" => "Показать справку",
+		_ => return None,
+	})
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_lang() {
+		assert_eq!(Lang::from_language_code("ru-RU"), Some(Lang::Ru));
+		assert_eq!(Lang::from_language_code("en"), Some(Lang::En));
+		assert_eq!(Lang::from_language_code("de"), None);
+		assert_eq!(Lang::from_str("Русский"), Ok(Lang::Ru));
+	}
+
+	#[test]
+	fn test_tr() {
+		assert_eq!(tr(Lang::En, Msg::Reroll), "Reroll");
+		assert_eq!(tr(Lang::Ru, Msg::Reroll), "Перебросить");
+		assert_eq!(
+			tr(Lang::Ru, Msg::Rolls("Bob".to_owned())),
+			"<b>Bob бросает:</b>"
+		);
+		assert_eq!(
+			tr(Lang::Ru, Msg::NotFound("spell".to_owned())),
+			"Не могу найти spell с таким названием, извини :("
+		);
+		// Arguments are never treated as templates
+		assert_eq!(
+			tr(Lang::En, Msg::Rolls("{}".to_owned())),
+			"<b>{} rolls:</b>"
+		);
+		assert_eq!(tr(Lang::Ru, Msg::ToHit("+7".to_owned())), "+7 на попадание");
+		assert_eq!(
+			Msg::Roll(DieFormatError::TooManyRolls).to_string(),
+			"I don't have that many dices!"
+		);
+		assert_eq!(
+			command_description(Lang::Ru, "help"),
+			Some("Показать справку")
+		);
+		assert_eq!(command_description(Lang::En, "help"), None);
+	}
+}
@@ -0,0 +1,181 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use bson::{Bson, Document};
+use rand::prelude::*;
+
+use super::{EntryArrayUtils, Optionable};
+use crate::{format::roll::roll_results, DB};
+
+// Coin denominations, from the most valuable one
+const COINS: [&str; 5] = ["pp", "gp", "ep", "sp", "cp"];
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum TreasureKind {
+	Individual,
+	Hoard,
+}
+
+// Rolls on DMG treasure tables (`loot.json` from 5etools) for the given challenge rating.
+// Items are left as {@item} tags, so they can be turned into buttons later.
+pub fn generate_loot(cr: i64, kind: TreasureKind) -> Option<String> {
+	let (collection, title) = match kind {
+		TreasureKind::Individual => ("individual", "Individual treasure"),
+		TreasureKind::Hoard => ("hoard", "Treasure hoard"),
+	};
+
+	let tier = DB.get_all(collection).ok()?.into_iter().find(|tier| {
+		let cr_min = tier.get_i64("crMin").unwrap_or(0);
+		let cr_max = tier.get_i64("crMax").unwrap_or(i64::MAX);
+		cr_min <= cr && cr <= cr_max
+	})?;
+	let rows = tier.get_array("table").ok()?;
+	let row = roll_row(rows).and_then(Bson::as_document);
+
+	let mut result = format!("<b>{title}</b>");
+	if let Ok(name) = tier.get_str("name") {
+		write!(result, "\n<i>{name}</i>").ok()?;
+	}
+	result.push('\n');
+
+	let mut coins = [0; COINS.len()];
+	for doc in [Some(&tier), row].into_iter().flatten() {
+		if let Ok(doc_coins) = doc.get_document("coins") {
+			for (i, coin) in COINS.iter().enumerate() {
+				coins[i] += doc_coins.get(coin).and_then(roll_amount).unwrap_or(0);
+			}
+		}
+	}
+	if let Some(coins) = format_coins(&coins) {
+		write!(result, "\n<b>Coins</b>: {coins}").ok()?;
+	}
+
+	if let Some(row) = row {
+		if let Some(gems) = format_valuables(row, "gems") {
+			write!(result, "\n<b>Gems</b> {gems}").ok()?;
+		}
+		if let Some(art) = format_valuables(row, "artObjects") {
+			write!(result, "\n<b>Art objects</b> {art}").ok()?;
+		}
+		if let Some(items) = format_magic_items(row) {
+			write!(result, "\n<b>Magic items</b>:\n{items}").ok()?;
+		}
+	}
+
+	Some(result)
+}
+
+fn format_coins(coins: &[i64]) -> Option<String> {
+	coins
+		.iter()
+		.zip(COINS)
+		.filter(|(amount, _)| **amount > 0)
+		.map(|(amount, coin)| format!("{amount}{coin}"))
+		.collect::<Vec<_>>()
+		.join(" ")
+		.into_option()
+}
+
+// Gems and art objects: {"type": 10, "amount": "2d6"}, where type is a value in gp
+fn format_valuables(row: &Document, key: &str) -> Option<String> {
+	let valuables = row.get_document(key).ok()?;
+	let value = valuables.get_i64("type").ok()?;
+	let amount = valuables.get("amount").and_then(roll_amount)?;
+
+	let table = DB
+		.get_all(key)
+		.ok()?
+		.into_iter()
+		.find(|table| table.get_i64("type").ok() == Some(value))?;
+	let rows = table.get_array("table").ok()?;
+
+	// Sorted, so the same gems are shown together
+	let mut found: BTreeMap<String, i64> = BTreeMap::new();
+	for _ in 0..amount {
+		if let Some(name) = roll_row(rows).and_then(row_text) {
+			*found.entry(name).or_default() += 1;
+		}
+	}
+
+	let found = found
+		.into_iter()
+		.map(|(name, count)| match count {
+			1 => name,
+			_ => format!("{name} ×{count}"),
+		})
+		.collect::<Vec<_>>()
+		.join(", ")
+		.into_option()?;
+	Some(format!("({value}gp each): {found}"))
+}
+
+// Magic items: [{"type": "A", "amount": "1d6"}], where type is a DMG magic item table
+fn format_magic_items(row: &Document) -> Option<String> {
+	row.get_array_of("magicItems", Bson::as_document)?
+		.into_iter()
+		.filter_map(|magic_items| {
+			let type_ = magic_items.get_str("type").ok()?;
+			let amount = magic_items.get("amount").and_then(roll_amount)?;
+			let table = DB.find_one_by("magicItems", "type", type_).ok()??;
+			let rows = table.get_array("table").ok()?;
+			Some(
+				(0..amount)
+					.filter_map(|_| roll_row(rows).and_then(row_text))
+					.map(|item| format!("\t• {item}"))
+					.collect::<Vec<_>>(),
+			)
+		})
+		.flatten()
+		.collect::<Vec<_>>()
+		.join("\n")
+		.into_option()
+}
+
+// Rows are either plain strings (equal chances) or {"min": 1, "max": 50, ...} documents
+fn roll_row(rows: &[Bson]) -> Option<&Bson> {
+	let die = rows
+		.iter()
+		.filter_map(Bson::as_document)
+		.filter_map(|row| row.get_i64("max").ok())
+		.max();
+
+	match die {
+		Some(die) => {
+			let roll = rand::thread_rng().gen_range(1..=die);
+			rows.iter().find(|row| {
+				row.as_document()
+					.map(|row| {
+						row.get_i64("min").unwrap_or(i64::MAX) <= roll
+							&& roll <= row.get_i64("max").unwrap_or(i64::MIN)
+					})
+					.unwrap_or(false)
+			})
+		}
+		None => rows.choose(&mut rand::thread_rng()),
+	}
+}
+
+fn row_text(row: &Bson) -> Option<String> {
+	match row {
+		Bson::String(s) => Some(s.to_owned()),
+		Bson::Document(doc) => match doc.get_str("item") {
+			Ok(item) => Some(item.to_owned()),
+			// Some rows require another roll, e.g. "Figurine of wondrous power (roll d8)"
+			Err(_) => roll_row(doc.get_array("table").ok()?).and_then(row_text),
+		},
+		_ => None,
+	}
+}
+
+fn roll_amount(amount: &Bson) -> Option<i64> {
+	match amount {
+		Bson::Int32(amount) => Some(*amount as i64),
+		Bson::Int64(amount) => Some(*amount),
+		// roll_results would treat a plain number as a number of d20
+		Bson::String(amount) => amount.parse::<i64>().ok().or_else(|| {
+			let rolls = roll_results(amount).ok()?;
+			rolls.first().map(|roll| roll.expression.calc())
+		}),
+		_ => None,
+	}
+}
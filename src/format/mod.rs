@@ -1,21 +1,30 @@
 pub mod abbreviation;
+pub mod character;
 pub mod db;
 pub mod item;
+pub mod locale;
+pub mod loot;
 pub mod monster;
+pub mod resources;
 pub mod roll;
+pub mod rule;
+pub mod settings;
 pub mod spell;
+pub mod table;
 pub mod telegram;
+pub mod tracker;
 pub mod utils;
 // Not in use yet
 // mod gen;
 
 use std::fmt::Write;
 
+use bson::{Bson, Document};
 use comfy_table::{presets::ASCII_NO_BORDERS, Cell, ContentArrangement, Row, Table};
-use ejdb::bson::Document;
-use ejdb::bson_crate::Bson;
 use regex::Regex;
 
+use utils::HtmlEscapable;
+
 pub trait Entry {
 	fn get_name(&self) -> Option<String>;
 	fn get_source(&self) -> Option<String>;
@@ -35,7 +44,11 @@ impl Entry for Document {
 		let page = self.get_i64("page");
 		let srd = self.get_bool("srd");
 
-		let mut result = source.to_string();
+		// Homebrew has to stand out, it's not the official content
+		let mut result = match self.get_str("homebrew") {
+			Ok(homebrew) => format!("🍺 Homebrew, {}: {source}", homebrew.escape_html()),
+			Err(_) => source.to_string(),
+		};
 
 		if let Ok(page) = page {
 			write!(result, ", page {page}").ok()?;
@@ -241,7 +254,7 @@ fn format_entry(entry: &Bson) -> Option<String> {
 
 fn simple_format(bs: &Bson) -> String {
 	match bs {
-		Bson::FloatingPoint(num) => format!("{num}"),
+		Bson::Double(num) => format!("{num}"),
 		Bson::String(s) => s.to_owned(),
 		Bson::Array(arr) => arr
 			.iter()
@@ -258,8 +271,8 @@ fn simple_format(bs: &Bson) -> String {
 			false => "No".to_owned(),
 		},
 		Bson::Null => "null".to_owned(),
-		Bson::I32(num) => format!("{num}"),
-		Bson::I64(num) => format!("{num}"),
+		Bson::Int32(num) => format!("{num}"),
+		Bson::Int64(num) => format!("{num}"),
 		_ => panic!("Unknown type: {:?}", bs.element_type()),
 	}
 }
@@ -302,22 +315,6 @@ fn demarkup(s: &str) -> String {
 	s.into()
 }
 
-#[cfg(test)]
-mod test {
-
-	use super::*;
-
-	#[test]
-	fn test_cost_to_string() {
-		assert_eq!(cost_to_string(1234567), "12345gp 6sp 7cp".to_string())
-	}
-
-	#[test]
-	fn test_cost_to_string_gp() {
-		assert_eq!(cost_to_string(1234500), "12345gp".to_string())
-	}
-}
-
 pub trait FilterJoinable: IntoIterator {
 	fn filter_join(self, sep: &str) -> Option<String>;
 }
@@ -372,3 +369,32 @@ impl<T> Optionable for Vec<T> {
 		}
 	}
 }
+
+#[cfg(test)]
+mod test {
+
+	use super::*;
+
+	#[test]
+	fn test_cost_to_string() {
+		assert_eq!(cost_to_string(1234567), "12345gp 6sp 7cp".to_string())
+	}
+
+	#[test]
+	fn test_cost_to_string_gp() {
+		assert_eq!(cost_to_string(1234500), "12345gp".to_string())
+	}
+
+	#[test]
+	fn test_homebrew_source() {
+		let mut doc = Document::new();
+		doc.insert("source", "ToB");
+		doc.insert("page", 12i64);
+		assert_eq!(doc.get_source().unwrap(), "ToB, page 12.");
+		doc.insert("homebrew", "Tome of Beasts");
+		assert_eq!(
+			doc.get_source().unwrap(),
+			"🍺 Homebrew, Tome of Beasts: ToB, page 12."
+		);
+	}
+}
@@ -1,21 +1,45 @@
 use super::{Capitalizable, Entry, EntryArrayUtils, EntryUtils, FilterJoinable, Optionable};
 use crate::DB;
-use ejdb::bson::{Bson, Document};
+use bson::{Bson, Document};
 use ordinal::Ordinal;
 
 use std::fmt::Write;
 
 pub trait Monster: Entry {
 	fn format_monster(&self) -> Option<String>;
+	fn get_environments(&self) -> Option<Vec<&str>>;
+	// Challenge rating in 1/8 units, so fractional ratings stay integers
+	fn get_cr_eighths(&self) -> Option<u32>;
+	fn get_hp_average(&self) -> Option<i64>;
+	fn get_hp_formula(&self) -> Option<&str>;
 }
 
 impl Monster for Document {
+	fn get_environments(&self) -> Option<Vec<&str>> {
+		self.get_array_of("environment", Bson::as_str)
+	}
+	fn get_cr_eighths(&self) -> Option<u32> {
+		match self.get("cr")? {
+			Bson::String(s) => parse_cr(s),
+			Bson::Document(doc) => parse_cr(doc.get_str("cr").ok()?),
+			_ => None,
+		}
+	}
+	fn get_hp_average(&self) -> Option<i64> {
+		self.get_document("hp").ok()?.get_i64("average").ok()
+	}
+	fn get_hp_formula(&self) -> Option<&str> {
+		self.get_document("hp").ok()?.get_str("formula").ok()
+	}
 	fn format_monster(&self) -> Option<String> {
 		let name = self.get_short_name().or_else(|| self.get_name())?;
 		let mut result = format!("<b>{name}</b>");
 
 		if let Some(val) = self.get_cr() {
 			write!(result, "\tCR {val}").ok()?;
+			if let Some(xp) = self.get_xp() {
+				write!(result, " ({} XP)", format_thousands(xp)).ok()?;
+			}
 		}
 
 		let meta = vec![
@@ -56,6 +80,9 @@ impl Monster for Document {
 		if let Some(cha) = self.get_cha() {
 			write!(result, "\t<b>Cha</b>: {cha}").ok()?;
 		}
+		if let Some(val) = self.get_proficiency_bonus() {
+			write!(result, "\n<b>Proficiency Bonus</b>: +{val}").ok()?;
+		}
 		if let Some(val) = self.get_save() {
 			write!(result, "\n<b>Saving Throws</b>: {val}").ok()?;
 		}
@@ -119,7 +146,7 @@ impl Monster for Document {
 		}
 
 		if let Some(source) = self.get_source() {
-			write!(result, "\n\n<i>{}</i>", &source).ok()?;
+			write!(result, "\n\n<i>{}</i>", source).ok()?;
 		}
 		Some(result)
 	}
@@ -147,6 +174,8 @@ trait MonsterPrivate: Monster {
 	fn get_passive(&self) -> Option<String>;
 	fn get_languages(&self) -> Option<String>;
 	fn get_cr(&self) -> Option<String>;
+	fn get_xp(&self) -> Option<u32>;
+	fn get_proficiency_bonus(&self) -> Option<u32>;
 	fn get_vulnerable(&self) -> Option<String>;
 	fn get_resist(&self) -> Option<String>;
 	fn get_immune(&self) -> Option<String>;
@@ -230,7 +259,11 @@ impl MonsterPrivate for Document {
 			return special;
 		}
 		let avg = hp.get_i64("average").ok().map(|i| i.to_string());
-		let formula = hp.get_str("formula").ok().map(|s| format!("({s})"));
+		// Rendered as a button, so the hit points can be rolled
+		let formula = hp
+			.get_str("formula")
+			.ok()
+			.map(|s| format!("({{@dice {s}}})"));
 		vec![avg, formula].filter_join(" ")
 	}
 	fn get_speed(&self) -> Option<String> {
@@ -240,7 +273,7 @@ impl MonsterPrivate for Document {
 				.filter_map(|key| match doc.get(key) {
 					Some(value) => match value {
 						Bson::Document(doc) => doc.format_speed_val(),
-						Bson::I64(i) => Some(i.to_string()),
+						Bson::Int64(i) => Some(i.to_string()),
 						_ => None,
 					},
 					None => None,
@@ -249,7 +282,7 @@ impl MonsterPrivate for Document {
 				.join(", ")
 				.into_option(),
 			Bson::String(s) => Some(s.to_string()),
-			Bson::I64(i) => Some(i.to_string()),
+			Bson::Int64(i) => Some(i.to_string()),
 			_ => None,
 		}
 	}
@@ -313,6 +346,12 @@ impl MonsterPrivate for Document {
 			_ => None,
 		}
 	}
+	fn get_xp(&self) -> Option<u32> {
+		self.get_cr_eighths().and_then(cr_to_xp)
+	}
+	fn get_proficiency_bonus(&self) -> Option<u32> {
+		self.get_cr_eighths().map(cr_to_proficiency_bonus)
+	}
 	fn get_vulnerable(&self) -> Option<String> {
 		self.format_damage_property("vulnerable")
 	}
@@ -550,22 +589,68 @@ impl MonsterUtils for Document {
 	}
 	fn get_stat(&self, stat: &str) -> Option<String> {
 		let num = self.get_i64(stat).ok()?;
-		let bonus = (num - 10) / 2;
-		if bonus >= 0 {
-			Some(format!("{num} (+{bonus})"))
-		} else {
-			Some(format!("{num} ({bonus})"))
-		}
+		Some(stat_to_string(num))
+	}
+}
+
+// Converts "1/4", "2" and similar challenge ratings into 1/8 units
+pub fn parse_cr(cr: &str) -> Option<u32> {
+	match cr.trim().split_once('/') {
+		Some(("1", denominator)) => match denominator {
+			"8" => Some(1),
+			"4" => Some(2),
+			"2" => Some(4),
+			_ => None,
+		},
+		Some(_) => None,
+		None => cr.trim().parse::<u32>().ok().map(|cr| cr * 8),
+	}
+}
+
+fn stat_to_string(num: i64) -> String {
+	// Modifiers are rounded down, so 9 gives -1, not 0
+	let bonus = (num - 10).div_euclid(2);
+	if bonus >= 0 {
+		format!("{num} (+{bonus})")
+	} else {
+		format!("{num} ({bonus})")
 	}
 }
 
-// fn stat_to_string(stat: i64) -> String {
-//     if bonus >= 0 {
-//         format!("{} (+{})", num, bonus)
-//     } else {
-//         format!("{} ({})", num, bonus)
-//     }
-// }
+// Takes a challenge rating in 1/8 units, see `parse_cr`
+fn cr_to_xp(cr: u32) -> Option<u32> {
+	const XP_BY_CR: [u32; 31] = [
+		10, 200, 450, 700, 1100, 1800, 2300, 2900, 3900, 5000, 5900, 7200, 8400, 10000, 11500,
+		13000, 15000, 18000, 20000, 22000, 25000, 33000, 41000, 50000, 62000, 75000, 90000, 105000,
+		120000, 135000, 155000,
+	];
+	match cr {
+		1 => Some(25),
+		2 => Some(50),
+		4 => Some(100),
+		cr if cr % 8 == 0 => XP_BY_CR.get((cr / 8) as usize).copied(),
+		_ => None,
+	}
+}
+
+fn cr_to_proficiency_bonus(cr: u32) -> u32 {
+	match cr / 8 {
+		0 => 2,
+		cr => 2 + (cr - 1) / 4,
+	}
+}
+
+fn format_thousands(num: u32) -> String {
+	let digits = num.to_string();
+	let mut result = String::new();
+	for (i, c) in digits.chars().enumerate() {
+		if i > 0 && (digits.len() - i).is_multiple_of(3) {
+			result.push(',');
+		}
+		result.push(c);
+	}
+	result
+}
 
 fn size_to_string(size: &str) -> &str {
 	match size {
@@ -597,3 +682,28 @@ fn alignment_to_string(alignment: &str) -> &str {
 		_ => alignment,
 	}
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_stat_to_string() {
+		assert_eq!(stat_to_string(16), "16 (+3)");
+		assert_eq!(stat_to_string(10), "10 (+0)");
+		assert_eq!(stat_to_string(9), "9 (-1)");
+		assert_eq!(stat_to_string(1), "1 (-5)");
+	}
+
+	#[test]
+	fn test_cr_derived_values() {
+		assert_eq!(parse_cr("1/4").and_then(cr_to_xp), Some(50));
+		assert_eq!(parse_cr("5").and_then(cr_to_xp), Some(1800));
+		assert_eq!(parse_cr("30").and_then(cr_to_xp), Some(155000));
+		assert_eq!(parse_cr("1/2").map(cr_to_proficiency_bonus), Some(2));
+		assert_eq!(parse_cr("5").map(cr_to_proficiency_bonus), Some(3));
+		assert_eq!(parse_cr("17").map(cr_to_proficiency_bonus), Some(6));
+		assert_eq!(format_thousands(155000), "155,000");
+		assert_eq!(format_thousands(450), "450");
+	}
+}
@@ -0,0 +1,200 @@
+use std::fmt::Write;
+
+use bson::Document;
+use ordinal::Ordinal;
+
+use super::{
+	locale::{tr, Lang, Msg},
+	utils::HtmlEscapable,
+};
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Rest {
+	Short,
+	Long,
+}
+
+// Spell slots, ki points, rage uses and so on
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Resource {
+	pub name: String,
+	pub current: i64,
+	pub max: i64,
+	// The shortest rest which restores this resource
+	pub rest: Rest,
+}
+
+impl Resource {
+	// "ki" and "Ki" are the same resource, "3" is a 3rd level spell slot
+	pub fn matches(&self, name: &str) -> bool {
+		resource_key(&self.name) == resource_key(name)
+	}
+
+	fn format(&self) -> String {
+		let mut result = format!("<b>{}</b>: ", self.name.escape_html());
+		let _ = write!(
+			result,
+			"{}{} {}/{}",
+			"●".repeat(self.current as usize),
+			"○".repeat((self.max - self.current) as usize),
+			self.current,
+			self.max
+		);
+		if self.rest == Rest::Short {
+			result.push_str(" (short rest)");
+		}
+		result
+	}
+}
+
+pub fn spell_slot_name(level: i64) -> String {
+	format!("{} level slots", Ordinal(level))
+}
+
+fn resource_key(name: &str) -> String {
+	let name = name.trim();
+	let name = match name.parse::<i64>() {
+		Ok(level) => spell_slot_name(level),
+		Err(_) => name.to_owned(),
+	};
+	name.chars()
+		.filter(|c| !c.is_whitespace())
+		.flat_map(char::to_lowercase)
+		.collect()
+}
+
+// Replaces all spell slots, `slots[0]` is the number of 1st level slots
+pub fn set_spell_slots(resources: &mut Vec<Resource>, slots: &[i64]) {
+	resources.retain(|resource| !(1..=9).any(|level| resource.matches(&level.to_string())));
+	let slots = slots
+		.iter()
+		.zip(1..)
+		.filter(|(max, _)| **max > 0)
+		.map(|(max, level)| Resource {
+			name: spell_slot_name(level),
+			current: *max,
+			max: *max,
+			rest: Rest::Long,
+		})
+		.collect::<Vec<_>>();
+	// Spell slots go first
+	resources.splice(0..0, slots);
+}
+
+pub fn set_resource(resources: &mut Vec<Resource>, name: &str, max: i64, rest: Rest) {
+	let resource = Resource {
+		name: name.to_owned(),
+		current: max,
+		max,
+		rest,
+	};
+	match resources.iter_mut().find(|r| r.matches(name)) {
+		Some(old) => *old = resource,
+		None => resources.push(resource),
+	}
+}
+
+pub fn spend<'a>(
+	resources: &'a mut [Resource],
+	name: &str,
+	amount: i64,
+) -> Result<&'a Resource, Msg> {
+	let resource = resources
+		.iter_mut()
+		.find(|r| r.matches(name))
+		.ok_or(Msg::UnknownResource)?;
+	if resource.current < amount {
+		return Err(Msg::NotEnoughResource);
+	}
+	resource.current -= amount;
+	Ok(resource)
+}
+
+// A long rest restores everything, a short rest only the short rest resources
+pub fn rest(resources: &mut [Resource], rest: Rest) {
+	resources
+		.iter_mut()
+		.filter(|resource| rest == Rest::Long || resource.rest == Rest::Short)
+		.for_each(|resource| resource.current = resource.max);
+}
+
+pub fn format_resources(resources: &[Resource], lang: Lang) -> String {
+	if resources.is_empty() {
+		return tr(lang, Msg::NoResources);
+	}
+	resources
+		.iter()
+		.map(Resource::format)
+		.collect::<Vec<_>>()
+		.join("\n")
+}
+
+pub fn format_resource(resource: &Resource) -> String {
+	resource.format()
+}
+
+impl From<&Resource> for Document {
+	fn from(resource: &Resource) -> Self {
+		let mut doc = Document::new();
+		doc.insert("name", resource.name.clone());
+		doc.insert("current", resource.current);
+		doc.insert("max", resource.max);
+		doc.insert(
+			"rest",
+			match resource.rest {
+				Rest::Short => "short",
+				Rest::Long => "long",
+			},
+		);
+		doc
+	}
+}
+
+impl TryFrom<&Document> for Resource {
+	type Error = bson::document::ValueAccessError;
+
+	fn try_from(doc: &Document) -> Result<Self, Self::Error> {
+		Ok(Self {
+			name: doc.get_str("name")?.to_owned(),
+			current: doc.get_i64("current")?,
+			max: doc.get_i64("max")?,
+			rest: match doc.get_str("rest")? {
+				"short" => Rest::Short,
+				_ => Rest::Long,
+			},
+		})
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_resources() {
+		let mut resources = Vec::new();
+		set_resource(&mut resources, "Ki", 2, Rest::Short);
+		set_spell_slots(&mut resources, &[4, 3, 2]);
+		assert_eq!(resources.len(), 4);
+		assert_eq!(resources[2].name, "3rd level slots");
+
+		assert_eq!(spend(&mut resources, "3", 2).map(|r| r.current), Ok(0));
+		assert!(spend(&mut resources, "3", 1).is_err());
+		assert_eq!(spend(&mut resources, "ki", 1).map(|r| r.current), Ok(1));
+		assert!(spend(&mut resources, "rage", 1).is_err());
+
+		rest(&mut resources, Rest::Short);
+		assert_eq!((resources[2].current, resources[3].current), (0, 2));
+		rest(&mut resources, Rest::Long);
+		assert_eq!(resources[2].current, 2);
+
+		assert_eq!(
+			format_resource(&resources[3]),
+			"<b>Ki</b>: ●● 2/2 (short rest)"
+		);
+		assert_eq!(
+			Resource::try_from(&Document::from(&resources[0])).ok(),
+			Some(resources[0].clone())
+		);
+	}
+}
@@ -1,30 +1,56 @@
 #![allow(clippy::redundant_closure_call)]
 
+use std::collections::HashMap;
 use std::fmt::Display;
 use std::{num::ParseIntError, str::FromStr};
 
 use peg::error::ParseError;
 use peg::str::LineCol;
 use rand::prelude::*;
+use regex::{Captures, Regex};
 use thiserror::Error;
 
-use crate::format::utils::zalgofy;
+use crate::format::{locale::Msg, utils::zalgofy};
 
 const PENTAGRAM: &str = "⛧";
+// The die used when none is given, e.g. "/r" or "/r +5"
+pub const DEFAULT_DIE: u16 = 20;
 
-#[derive(Error, Debug)]
+// The texts are in the locale catalog, see `Msg::Roll`
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("{}", Msg::Roll(self.clone()))]
 pub enum DieFormatError {
-	#[error("Wow, that was a lot of text! Too bad I'm too lazy to read it :)")]
 	TooLongText,
-
-	#[error("I don't have that many dices!")]
 	TooManyRolls,
-	#[error("{0}")]
-	ParseError(&'static str),
+	TooManyDice,
+	UnknownDie,
+	Nonsense,
+	BigNumber,
+	Unexpected,
+	Unparsable,
+	NothingToRoll,
+	UnknownModifier,
+	EmptyTable,
 }
 
 pub fn roll_dice(msg: &str) -> Result<String, DieFormatError> {
-	let response = roll_results(msg)?
+	roll_dice_with_die(msg, DEFAULT_DIE)
+}
+
+// Same as `roll_dice`, but "/r" or "/r 2" use the given die instead of d20
+pub fn roll_dice_with_die(msg: &str, die: u16) -> Result<String, DieFormatError> {
+	let response = format_rolls(&roll_results_with_die(msg, die)?);
+
+	if response.is_empty() {
+		warn!("Cannot parse: {}", msg);
+		Err(DieFormatError::NothingToRoll)
+	} else {
+		Ok(response)
+	}
+}
+
+fn format_rolls(rolls: &[RollLine]) -> String {
+	rolls
 		.iter()
 		.map(|roll| match &roll.comment {
 			Some(comment) => format!(
@@ -36,17 +62,102 @@ pub fn roll_dice(msg: &str) -> Result<String, DieFormatError> {
 			None => format!("{} = {}", roll.expression, roll.expression.calc()),
 		})
 		.collect::<Vec<_>>()
-		.join("\n");
+		.join("\n")
+}
 
-	if response.is_empty() {
-		warn!("Cannot parse: {}", msg);
-		Ok("Err, sorry, I can't roll that. Maybe you need some /help ?".to_owned())
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum CritRule {
+	// Roll all damage dice twice, as in the PHB
+	#[default]
+	Double,
+	// Add the maximum of the damage dice to the roll
+	Max,
+	// Critical hits are not rolled automatically
+	Off,
+}
+
+// "2d6+3" becomes "4d6+3" or "2d6+12+3"
+pub fn crit_damage(damage: &str, rule: CritRule) -> String {
+	lazy_static! {
+		static ref DICE_REGEX: Regex = Regex::new(r"(?P<num>\d*)[dD](?P<face>\d+)").unwrap();
+	}
+
+	DICE_REGEX
+		.replace_all(damage, |caps: &Captures| {
+			let num = caps["num"].parse::<u32>().unwrap_or(1);
+			let face = caps["face"].parse::<u32>().unwrap_or_default();
+			match rule {
+				CritRule::Double => format!("{}d{face}", num * 2),
+				CritRule::Max => format!("{num}d{face}+{}", num * face),
+				CritRule::Off => caps[0].to_owned(),
+			}
+		})
+		.into_owned()
+}
+
+// Rolls to hit, then the damage, which is increased on a natural 20.
+// Returns the result and whether it was a critical hit
+pub fn roll_attack(
+	to_hit: &str,
+	damage: Option<(&str, &str)>,
+	crit: CritRule,
+) -> Result<(String, bool), DieFormatError> {
+	let to_hit = roll_results(to_hit)?;
+	let critical = crit != CritRule::Off && to_hit.first().is_some_and(RollLine::is_critical);
+	let mut result = format_rolls(&to_hit);
+	if let Some((damage, comment)) = damage {
+		let damage = if critical {
+			crit_damage(damage, crit)
+		} else {
+			damage.to_owned()
+		};
+		let damage = roll_results(&format!("{damage} \"{comment}\""))?;
+		result.push('\n');
+		result.push_str(&format_rolls(&damage));
+	}
+	Ok((result, critical))
+}
+
+// Named modifiers of a character, e.g. "dex" or "stealth", used as "d20+@stealth"
+pub type RollContext = HashMap<String, i64>;
+
+pub fn roll_dice_with_context(msg: &str, context: &RollContext) -> Result<String, DieFormatError> {
+	roll_dice(&substitute_context(msg, context)?)
+}
+
+fn substitute_context(msg: &str, context: &RollContext) -> Result<String, DieFormatError> {
+	lazy_static! {
+		static ref CONTEXT_REGEX: Regex = Regex::new(r"(?P<sign>[+-]?)\s*@(?P<name>\w+)").unwrap();
+	}
+
+	let mut unknown = false;
+	let result = CONTEXT_REGEX.replace_all(msg, |caps: &Captures| {
+		let Some(value) = context.get(&caps["name"].to_lowercase()) else {
+			unknown = true;
+			return String::new();
+		};
+		// The parser has no unary minus, so the sign is merged into the operator
+		let value = if &caps["sign"] == "-" { -value } else { *value };
+		match (caps["sign"].is_empty(), value < 0) {
+			(false, false) => format!("+{value}"),
+			(false, true) => value.to_string(),
+			(true, false) => value.to_string(),
+			(true, true) => format!("(0{value})"),
+		}
+	});
+
+	if unknown {
+		Err(DieFormatError::UnknownModifier)
 	} else {
-		Ok(response)
+		Ok(result.into_owned())
 	}
 }
 
 pub fn roll_results(msg: &str) -> Result<Vec<RollLine>, DieFormatError> {
+	roll_results_with_die(msg, DEFAULT_DIE)
+}
+
+fn roll_results_with_die(msg: &str, die: u16) -> Result<Vec<RollLine>, DieFormatError> {
 	if msg.len() > u16::MAX as usize {
 		return Err(DieFormatError::TooLongText);
 	}
@@ -62,12 +173,16 @@ pub fn roll_results(msg: &str) -> Result<Vec<RollLine>, DieFormatError> {
 				comment,
 			} = rolls
 			{
-				let substitution = Expression::Value(Operand::Dice(Dice::new_num(num, 20)));
+				let substitution = Expression::Value(Operand::Dice(Dice::new_num(num, die)));
 				RollLine {
 					expression: substitution,
 					comment,
 				}
 			} else {
+				let mut rolls = rolls;
+				if die != DEFAULT_DIE {
+					rolls.expression.replace_default_die(die);
+				}
 				rolls
 			}
 		})
@@ -76,12 +191,18 @@ pub fn roll_results(msg: &str) -> Result<Vec<RollLine>, DieFormatError> {
 
 impl From<ParseError<LineCol>> for DieFormatError {
 	fn from(err: ParseError<LineCol>) -> Self {
-		let e = err
-			.expected
+		// Custom errors of the grammar are among the expected tokens
+		err.expected
 			.tokens()
-			.find(|s| s.starts_with("Nope") || s.starts_with("Wow"))
-			.unwrap_or("Can't parse your message, sorry");
-		Self::ParseError(e)
+			.find_map(|token| match token {
+				"too many dice" => Some(Self::TooManyDice),
+				"unknown die" => Some(Self::UnknownDie),
+				"nonsense" => Some(Self::Nonsense),
+				"big number" => Some(Self::BigNumber),
+				"unexpected" => Some(Self::Unexpected),
+				_ => None,
+			})
+			.unwrap_or(Self::Unparsable)
 	}
 }
 
@@ -185,6 +306,8 @@ pub struct Dice {
 	pub selectors: Vec<DiceSelector>,
 	results: Vec<i32>,
 	total: i64,
+	// Not written by the user, e.g. "/r +5", so it can be replaced with another default die
+	implicit: bool,
 }
 
 impl Dice {
@@ -216,6 +339,7 @@ impl Dice {
 			selectors,
 			results: results_full,
 			total,
+			implicit: false,
 		}
 	}
 
@@ -234,7 +358,10 @@ impl Dice {
 
 impl Default for Dice {
 	fn default() -> Self {
-		Self::new(1, DiceFace::Num(20), vec![])
+		Self {
+			implicit: true,
+			..Self::new(1, DiceFace::Num(DEFAULT_DIE), vec![])
+		}
 	}
 }
 
@@ -387,6 +514,35 @@ impl Default for Expression {
 }
 
 impl Expression {
+	fn replace_default_die(&mut self, die: u16) {
+		match self {
+			Expression::Value(Operand::Dice(dice)) if dice.implicit => {
+				*dice = Dice::new_num(dice.num, die);
+			}
+			Expression::Value(_) => {}
+			Expression::Plus(a, b)
+			| Expression::Minus(a, b)
+			| Expression::Multiply(a, b)
+			| Expression::Divide(a, b)
+			| Expression::DivideFloor(a, b) => {
+				a.replace_default_die(die);
+				b.replace_default_die(die);
+			}
+		}
+	}
+
+	fn first_dice(&self) -> Option<&Dice> {
+		match self {
+			Expression::Value(Operand::Dice(dice)) => Some(dice),
+			Expression::Value(_) => None,
+			Expression::Plus(a, b)
+			| Expression::Minus(a, b)
+			| Expression::Multiply(a, b)
+			| Expression::Divide(a, b)
+			| Expression::DivideFloor(a, b) => a.first_dice().or_else(|| b.first_dice()),
+		}
+	}
+
 	pub fn calc(&self) -> i64 {
 		match self {
 			Expression::Value(operand) => match operand {
@@ -410,6 +566,18 @@ pub struct RollLine {
 }
 
 impl RollLine {
+	// A natural 20 on the first d20, with or without advantage
+	pub fn is_critical(&self) -> bool {
+		self.expression.first_dice().is_some_and(|dice| {
+			dice.face == DiceFace::Num(20)
+				&& (dice.num == 1
+					|| matches!(
+						dice.selectors.as_slice(),
+						[DiceSelector::KeepHigh(1)] | [DiceSelector::KeepLow(1)]
+					)) && dice.total == 20
+		})
+	}
+
 	fn new(expression: Expression, comment: Option<String>) -> Self {
 		match comment {
 			None => Self {
@@ -437,11 +605,11 @@ peg::parser! {
 
 		rule num() -> u16
 		= num:$(['0'..='9']+)
-			{? num.parse().or(Err("Wow, that's a big number!")) }
+			{? num.parse().or(Err("big number")) }
 
 		rule dice_num() -> DiceNum
 		= num:$(num() / "+" / "-")
-			{? num.parse().or(Err("Wow, an error occurred, which shouldn't happen 🤔. Are you happy?")) }
+			{? num.parse().or(Err("unexpected")) }
 
 		rule dice_face() -> DiceFace
 		= num:$(num() / "%" / "⛧" / "F")
@@ -451,7 +619,7 @@ peg::parser! {
 					"F" => DiceFace::Fudge,
 					PENTAGRAM | "0" => DiceFace::Zalgo,
 					_ => DiceFace::Num(
-						num.parse().or(Err("Wow, an error occurred, which shouldn't happen 🤔. Are you happy?"))?
+						num.parse().or(Err("unexpected"))?
 					),
 				})
 			}
@@ -486,12 +654,12 @@ peg::parser! {
 				let (dice_num, selectors) = match (dice_num, selectors.as_slice()) {
 					(DiceNum::Advantage, []) => (2, vec![DiceSelector::KeepHigh(1)]),
 					(DiceNum::Disadvantage, []) => (2, vec![DiceSelector::KeepLow(1)]),
-					(DiceNum::Num(200..), _) => return Err("Nope, I don't have that many dices!"),
+					(DiceNum::Num(200..), _) => return Err("too many dice"),
 					(DiceNum::Num(num), _) => (num, selectors),
-					_ => return Err("Nope, that doesn't make any sense"),
+					_ => return Err("nonsense"),
 				};
 				if face.get_min_value() > 1000 {
-					return Err("Nope, I don't have that kind of dice!")
+					return Err("unknown die")
 				}
 				Ok(Dice::new(dice_num, face, selectors))
 			}
@@ -652,6 +820,7 @@ mod test {
 				],
 				results: vec![],
 				total: 0,
+				implicit: false,
 			})
 		);
 	}
@@ -666,6 +835,7 @@ mod test {
 				selectors: vec![],
 				results: vec![],
 				total: 0,
+				implicit: false,
 			})
 		);
 	}
@@ -680,6 +850,7 @@ mod test {
 				selectors: vec![],
 				results: vec![],
 				total: 0,
+				implicit: false,
 			})
 		);
 	}
@@ -793,6 +964,43 @@ mod test {
 		assert_err!(roll_parser::expressions("10000000d5"));
 	}
 
+	#[test]
+	fn test_substitute_context() {
+		let context = RollContext::from([("dex".to_owned(), 3), ("str".to_owned(), -1)]);
+		assert_eq!(substitute_context("d20+@dex", &context).unwrap(), "d20+3");
+		assert_eq!(
+			substitute_context("d20 + @str", &context).unwrap(),
+			"d20 -1"
+		);
+		assert_eq!(substitute_context("d20-@str", &context).unwrap(), "d20+1");
+		assert_eq!(substitute_context("@STR", &context).unwrap(), "(0-1)");
+		assert_err!(substitute_context("d20+@cha", &context));
+	}
+
+	#[test]
+	fn test_default_die() {
+		let first_face = |msg: &str, die: u16| {
+			roll_results_with_die(msg, die).unwrap()[0]
+				.expression
+				.first_dice()
+				.map(|dice| dice.face.clone())
+		};
+		assert_eq!(first_face("+5", 6), Some(DiceFace::Num(6)));
+		assert_eq!(first_face("2", 100), Some(DiceFace::Num(100)));
+		assert_eq!(first_face("", 12), Some(DiceFace::Num(12)));
+		// Explicit dice and advantage are always d20
+		assert_eq!(first_face("1d20", 6), Some(DiceFace::Num(20)));
+		assert_eq!(first_face("+", 6), Some(DiceFace::Num(20)));
+	}
+
+	#[test]
+	fn test_crit_damage() {
+		assert_eq!(crit_damage("2d6+3", CritRule::Double), "4d6+3");
+		assert_eq!(crit_damage("d8+1d6", CritRule::Double), "2d8+2d6");
+		assert_eq!(crit_damage("2d6+3", CritRule::Max), "2d6+12+3");
+		assert_eq!(crit_damage("2d6+3", CritRule::Off), "2d6+3");
+	}
+
 	#[test]
 	fn test_comment() {
 		let expr = roll_parser::expressions("d20 + 5 to sneak the target");
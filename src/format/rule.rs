@@ -0,0 +1,91 @@
+use super::{Capitalizable, Entry, FilterJoinable, Optionable};
+use bson::{Bson, Document};
+
+use std::fmt::Write;
+
+// Actions, senses, skills and variant rules share the same simple layout,
+// so they are formatted by a single trait
+pub trait Rule: Entry {
+	fn get_rule_type(&self) -> Option<String>;
+	fn get_ability(&self) -> Option<String>;
+	fn get_time(&self) -> Option<String>;
+
+	fn format_rule(&self) -> Option<String>;
+}
+
+impl Rule for Document {
+	fn get_rule_type(&self) -> Option<String> {
+		self.get_str("ruleType")
+			.ok()
+			.map(|rule_type| match rule_type {
+				"C" => "core rule",
+				"O" => "optional rule",
+				"P" => "prerelease rule",
+				"V" => "variant rule",
+				"VO" => "variant optional rule",
+				"VV" => "variant variant rule",
+				"U" => "unknown rule",
+				_ => rule_type,
+			})
+			.map(str::to_string)
+	}
+
+	fn get_ability(&self) -> Option<String> {
+		self.get_str("ability")
+			.ok()
+			.map(|ability| match ability {
+				"str" => "strength",
+				"dex" => "dexterity",
+				"con" => "constitution",
+				"int" => "intelligence",
+				"wis" => "wisdom",
+				"cha" => "charisma",
+				_ => ability,
+			})
+			.map(str::to_string)
+	}
+
+	fn get_time(&self) -> Option<String> {
+		let times = self.get_array("time").ok()?;
+
+		times
+			.iter()
+			.filter_map(|time| match time {
+				Bson::String(s) => Some(s.to_string()),
+				Bson::Document(time) => {
+					let number = time.get_i64("number").map(|number| number.to_string()).ok();
+					let unit = time.get_str("unit").map(str::to_owned).ok();
+					vec![number, unit].filter_join(" ")
+				}
+				_ => None,
+			})
+			.collect::<Vec<_>>()
+			.join(", ")
+			.into_option()
+	}
+
+	fn format_rule(&self) -> Option<String> {
+		let mut s = format!("<b>{}</b>", self.get_name()?);
+
+		let meta = vec![self.get_rule_type(), self.get_ability()]
+			.filter_join(", ")
+			.map(|s| s.capitalize());
+		if let Some(meta) = meta {
+			write!(s, "\n<i>{meta}</i>").ok()?;
+		}
+
+		if let Some(time) = self.get_time() {
+			write!(s, "\n\n<b>Time</b>: {time}").ok()?;
+		}
+
+		if let Some(entries) = self.get_entries("entries") {
+			write!(s, "\n\n{}", entries.join("\n")).ok()?;
+		}
+
+		if let Some(source) = self.get_source() {
+			write!(s, "\n\n<i>{source}</i>").ok()?;
+		}
+
+		Some(s)
+	}
+}
@@ -0,0 +1,317 @@
+use bson::{Bson, Document};
+
+use super::{
+	locale::{tr, Lang, Msg, LANGUAGES},
+	roll::{CritRule, DEFAULT_DIE},
+	utils::HtmlEscapable,
+};
+
+// Buttons cycle through these dice
+pub const DICE: [u16; 7] = [20, 100, 12, 10, 8, 6, 4];
+// Items without a source are looked up in these books, in this order
+const DEFAULT_SOURCES: [&str; 1] = ["PHB"];
+const MAX_SOURCES: usize = 10;
+
+// Per-chat preferences, see `/settings`
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Settings {
+	// Used by "/r", "/r 2" or "/r +5"
+	pub die: u16,
+	pub crit: CritRule,
+	pub sources: Vec<String>,
+	// Sources of the enabled homebrew packs, homebrew is hidden by default
+	pub homebrew: Vec<String>,
+	// Reroll button edits the message instead of sending a new one
+	pub reroll_edits: bool,
+	// None means the Telegram language of each user
+	pub lang: Option<Lang>,
+}
+
+impl Default for Settings {
+	fn default() -> Self {
+		Self {
+			die: DEFAULT_DIE,
+			crit: CritRule::default(),
+			sources: DEFAULT_SOURCES.map(str::to_owned).to_vec(),
+			homebrew: Vec::new(),
+			reroll_edits: false,
+			lang: None,
+		}
+	}
+}
+
+impl Settings {
+	// "/settings die 100", the keys are the same as the button data
+	pub fn set(&mut self, key: &str, value: &str) -> Result<(), Msg> {
+		let value = value.trim();
+		match key {
+			"die" => {
+				let die = value.trim_start_matches(['d', 'D']);
+				self.die = match die {
+					"%" => 100,
+					die => die
+						.parse()
+						.ok()
+						.filter(|die| (2..=1000).contains(die))
+						.ok_or(Msg::SettingsUsage)?,
+				};
+			}
+			"crit" => {
+				self.crit = match value.to_lowercase().as_str() {
+					"double" => CritRule::Double,
+					"max" => CritRule::Max,
+					"off" | "none" => CritRule::Off,
+					_ => return Err(Msg::SettingsUsage),
+				}
+			}
+			"sources" => {
+				let sources = value
+					.split([' ', ','])
+					.filter(|source| !source.is_empty())
+					.map(str::to_uppercase)
+					.collect::<Vec<_>>();
+				if !(1..=MAX_SOURCES).contains(&sources.len()) {
+					return Err(Msg::SettingsUsage);
+				}
+				self.sources = sources;
+			}
+			// Homebrew sources are case-sensitive, unlike the official ones
+			"homebrew" => {
+				self.homebrew = match value.to_lowercase().as_str() {
+					"off" | "none" => Vec::new(),
+					_ => value
+						.split([' ', ','])
+						.filter(|source| !source.is_empty())
+						.map(str::to_owned)
+						.collect(),
+				};
+				if self.homebrew.len() > MAX_SOURCES {
+					return Err(Msg::SettingsUsage);
+				}
+			}
+			"reroll" => {
+				self.reroll_edits = match value.to_lowercase().as_str() {
+					"edit" => true,
+					"new" => false,
+					_ => return Err(Msg::SettingsUsage),
+				}
+			}
+			"lang" => {
+				self.lang = match value.to_lowercase().as_str() {
+					"auto" => None,
+					lang => Some(lang.parse().map_err(|_| Msg::SettingsUsage)?),
+				}
+			}
+			_ => return Err(Msg::SettingsUsage),
+		}
+		Ok(())
+	}
+
+	// Settings menu buttons switch to the next value
+	pub fn cycle(&mut self, key: &str) -> Result<(), Msg> {
+		match key {
+			"die" => {
+				let i = DICE.iter().position(|die| *die == self.die);
+				self.die = DICE[i.map(|i| (i + 1) % DICE.len()).unwrap_or_default()];
+			}
+			"crit" => {
+				self.crit = match self.crit {
+					CritRule::Double => CritRule::Max,
+					CritRule::Max => CritRule::Off,
+					CritRule::Off => CritRule::Double,
+				}
+			}
+			"reroll" => self.reroll_edits = !self.reroll_edits,
+			"lang" => {
+				// Auto → en → ru → auto
+				let i = self
+					.lang
+					.and_then(|lang| LANGUAGES.iter().position(|l| *l == lang));
+				self.lang = match i {
+					None => LANGUAGES.first().copied(),
+					Some(i) => LANGUAGES.get(i + 1).copied(),
+				};
+			}
+			_ => return Err(Msg::SettingsUsage),
+		}
+		Ok(())
+	}
+
+	// `available_homebrew` is (source, pack name) of the loaded homebrew
+	pub fn format(&self, lang: Lang, available_homebrew: &[(String, String)]) -> String {
+		let homebrew = if self.homebrew.is_empty() {
+			tr(lang, Msg::HomebrewOff)
+		} else {
+			tr(lang, Msg::Homebrew(self.homebrew.join(", ").escape_html()))
+		};
+		let available = if available_homebrew.is_empty() {
+			String::new()
+		} else {
+			let packs = available_homebrew
+				.iter()
+				.map(|(source, name)| {
+					format!(
+						"<code>{}</code> ({})",
+						source.escape_html(),
+						name.escape_html()
+					)
+				})
+				.collect::<Vec<_>>()
+				.join(", ");
+			format!(
+				"\n{}\n{}",
+				tr(lang, Msg::AvailableHomebrew(packs)),
+				tr(lang, Msg::HomebrewHint)
+			)
+		};
+		format!(
+			"<b>{}</b>\n{}\n{homebrew}{available}\n\n{}",
+			tr(lang, Msg::ChatSettings),
+			tr(
+				lang,
+				Msg::PreferredSources(self.sources.join(", ").escape_html())
+			),
+			tr(lang, Msg::SettingsHint)
+		)
+	}
+
+	// (button text, key) pairs for the settings menu
+	pub fn buttons(&self, lang: Lang) -> Vec<(String, &'static str)> {
+		let crit = match self.crit {
+			CritRule::Double => Msg::CritDouble,
+			CritRule::Max => Msg::CritMax,
+			CritRule::Off => Msg::CritOff,
+		};
+		let reroll = if self.reroll_edits {
+			Msg::RerollEdits
+		} else {
+			Msg::RerollSends
+		};
+		let language = match self.lang {
+			Some(lang) => Msg::Language(lang),
+			None => Msg::LanguageAuto,
+		};
+		vec![
+			(tr(lang, Msg::DefaultDie(self.die)), "die"),
+			(tr(lang, crit), "crit"),
+			(tr(lang, reroll), "reroll"),
+			(tr(lang, language), "lang"),
+		]
+	}
+}
+
+impl From<&Settings> for Document {
+	fn from(settings: &Settings) -> Self {
+		let mut doc = Document::new();
+		doc.insert("die", settings.die as i64);
+		doc.insert(
+			"crit",
+			match settings.crit {
+				CritRule::Double => "double",
+				CritRule::Max => "max",
+				CritRule::Off => "off",
+			},
+		);
+		doc.insert(
+			"sources",
+			settings
+				.sources
+				.iter()
+				.cloned()
+				.map(Bson::String)
+				.collect::<Vec<_>>(),
+		);
+		doc.insert(
+			"homebrew",
+			settings
+				.homebrew
+				.iter()
+				.cloned()
+				.map(Bson::String)
+				.collect::<Vec<_>>(),
+		);
+		doc.insert("reroll_edits", settings.reroll_edits);
+		if let Some(lang) = settings.lang {
+			doc.insert("lang", lang.code());
+		}
+		doc
+	}
+}
+
+// Missing fields get the default values, so new settings don't break the old documents
+impl From<&Document> for Settings {
+	fn from(doc: &Document) -> Self {
+		let default = Settings::default();
+		Self {
+			die: doc
+				.get_i64("die")
+				.ok()
+				.and_then(|die| u16::try_from(die).ok())
+				.unwrap_or(default.die),
+			crit: match doc.get_str("crit") {
+				Ok("max") => CritRule::Max,
+				Ok("off") => CritRule::Off,
+				_ => default.crit,
+			},
+			sources: doc
+				.get_array("sources")
+				.map(|sources| {
+					sources
+						.iter()
+						.filter_map(Bson::as_str)
+						.map(str::to_owned)
+						.collect()
+				})
+				.unwrap_or(default.sources),
+			homebrew: doc
+				.get_array("homebrew")
+				.map(|homebrew| {
+					homebrew
+						.iter()
+						.filter_map(Bson::as_str)
+						.map(str::to_owned)
+						.collect()
+				})
+				.unwrap_or(default.homebrew),
+			reroll_edits: doc.get_bool("reroll_edits").unwrap_or(default.reroll_edits),
+			lang: doc.get_str("lang").ok().and_then(|lang| lang.parse().ok()),
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_settings() {
+		let mut settings = Settings::default();
+		assert!(settings.set("die", "d100").is_ok());
+		assert!(settings.set("die", "1").is_err());
+		assert!(settings.set("sources", "phb, xge").is_ok());
+		assert_eq!(settings.sources, vec!["PHB", "XGE"]);
+		assert!(settings.set("homebrew", "ToB CoS").is_ok());
+		assert_eq!(settings.homebrew, vec!["ToB", "CoS"]);
+		assert!(settings.set("lang", "ru").is_ok());
+		assert!(settings.set("color", "red").is_err());
+
+		assert!(settings.cycle("die").is_ok());
+		assert_eq!(settings.die, 12);
+		assert!(settings.cycle("crit").is_ok());
+		assert_eq!(settings.crit, CritRule::Max);
+		assert!(settings.cycle("lang").is_ok());
+		assert_eq!(settings.lang, None);
+
+		assert_eq!(Settings::from(&Document::from(&settings)), settings);
+		// Documents saved by `/lang` only have the language
+		let mut doc = Document::new();
+		doc.insert("lang", "ru");
+		assert_eq!(
+			Settings::from(&doc),
+			Settings {
+				lang: Some(Lang::Ru),
+				..Settings::default()
+			}
+		);
+	}
+}
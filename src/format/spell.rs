@@ -1,11 +1,15 @@
 use crate::DB;
 
 use super::{Capitalizable, Entry, FilterJoinable, Optionable};
-use ejdb::bson::Document;
+use bson::{Bson, Document};
 use ordinal::Ordinal;
+use regex::Regex;
 
 use std::fmt::Write;
 
+const MAX_SPELL_LEVEL: i64 = 9;
+const MAX_CHARACTER_LEVEL: i64 = 20;
+
 pub trait Spell: Entry {
 	fn get_meta(&self) -> Option<String>;
 	fn get_casting_time(&self) -> Option<String>;
@@ -13,8 +17,18 @@ pub trait Spell: Entry {
 	fn get_components(&self) -> Option<String>;
 	fn get_duration(&self) -> Option<String>;
 	fn get_classes(&self) -> Option<Vec<String>>;
+	// Damage (or healing) dice when cast with the given slot level.
+	// For cantrips the level is a character level.
+	fn get_scaled_dice(&self, level: i64) -> Vec<ScaledDice>;
 
 	fn format_spell(&self) -> Option<String>;
+	// The level is clamped to the ones the spell can be cast at, like the cast button
+	fn format_scaling(&self, level: i64) -> Option<String>;
+}
+
+pub struct ScaledDice {
+	pub label: Option<String>,
+	pub dice: String,
 }
 
 impl Spell for Document {
@@ -116,11 +130,11 @@ impl Spell for Document {
 	fn get_classes(&self) -> Option<Vec<String>> {
 		let search_name = format!("{} ({})", self.get_name()?, self.get_str("source").ok()?);
 		let refs = DB.get_item("spell_sources", &search_name).ok()??;
-		let classes = refs.get_array("class").ok()?.into_iter();
+		let classes = refs.get_array("class").ok()?.iter();
 		let class_variants = refs
 			.get_array("classVariant")
 			.ok()
-			.map(|class_variants| class_variants.into_iter())
+			.map(|class_variants| class_variants.iter())
 			.unwrap_or_default();
 
 		Some(
@@ -140,45 +154,131 @@ impl Spell for Document {
 		)
 	}
 
+	fn get_scaled_dice(&self, level: i64) -> Vec<ScaledDice> {
+		lazy_static! {
+			static ref SCALE_REGEX: Regex = Regex::new(
+				r"\{@scale(?:damage|dice) (?P<base>[^|}]+)\|(?P<levels>[^|}]+)\|(?P<step>[^|}]+)[^}]*\}"
+			)
+			.unwrap();
+		}
+
+		let mut texts = Vec::new();
+		for key in ["entries", "entriesHigherLevel"] {
+			if let Ok(entries) = self.get_array(key) {
+				entries
+					.iter()
+					.for_each(|entry| collect_strings(entry, &mut texts));
+			}
+		}
+
+		let mut result = texts
+			.iter()
+			.flat_map(|text| SCALE_REGEX.captures_iter(text))
+			.filter_map(|caps| {
+				let levels = parse_levels(&caps["levels"])?;
+				let steps = levels
+					.iter()
+					.filter(|l| **l <= level)
+					.count()
+					.saturating_sub(1);
+				Some(ScaledDice {
+					label: None,
+					dice: scale_dice(&caps["base"], &caps["step"], steps),
+				})
+			})
+			.collect::<Vec<_>>();
+
+		// Cantrips: {"label": "fire damage", "scaling": {"1": "1d10", "5": "2d10", ...}}
+		let scaling_level_dice = match self.get("scalingLevelDice") {
+			Some(Bson::Document(doc)) => vec![doc],
+			Some(Bson::Array(arr)) => arr.iter().filter_map(Bson::as_document).collect(),
+			_ => vec![],
+		};
+		for scaling in scaling_level_dice {
+			let dice = scaling.get_document("scaling").ok().and_then(|scaling| {
+				scaling
+					.iter()
+					.filter_map(|(lvl, dice)| Some((lvl.parse::<i64>().ok()?, dice.as_str()?)))
+					.filter(|(lvl, _)| *lvl <= level)
+					.max_by_key(|(lvl, _)| *lvl)
+					.map(|(_, dice)| dice.to_owned())
+			});
+			if let Some(dice) = dice {
+				result.push(ScaledDice {
+					label: scaling.get_str("label").map(str::to_owned).ok(),
+					dice,
+				});
+			}
+		}
+
+		result
+	}
+
+	fn format_scaling(&self, level: i64) -> Option<String> {
+		let (level, title) = match self.get_i64("level").ok()? {
+			0 => {
+				let level = level.clamp(1, MAX_CHARACTER_LEVEL);
+				(level, format!("At {} character level", Ordinal(level)))
+			}
+			spell_level => {
+				let level = level.clamp(spell_level, MAX_SPELL_LEVEL);
+				(level, format!("Cast at {} level", Ordinal(level)))
+			}
+		};
+
+		self.get_scaled_dice(level)
+			.into_iter()
+			.map(|scaled| {
+				let label = scaled
+					.label
+					.map(|label| format!(" ({label})"))
+					.unwrap_or_default();
+				format!("<b>{title}</b>{label}: <b>{}</b>", scaled.dice)
+			})
+			.collect::<Vec<_>>()
+			.join("\n")
+			.into_option()
+	}
+
 	fn format_spell(&self) -> Option<String> {
 		let mut s = format!("<b>{}</b>", self.get_name()?);
 
 		if let Some(meta) = self.get_meta() {
-			write!(s, "\n<i>{}</i>", &meta).ok()?;
+			write!(s, "\n<i>{}</i>", meta).ok()?;
 		}
 
 		s.push('\n');
 
 		if let Some(casting_time) = self.get_casting_time() {
-			write!(s, "\n<b>Casting time</b>: {}", &casting_time).ok()?;
+			write!(s, "\n<b>Casting time</b>: {}", casting_time).ok()?;
 		}
 
 		if let Some(range) = self.get_range() {
-			write!(s, "\n<b>Range</b>: {}", &range).ok()?;
+			write!(s, "\n<b>Range</b>: {}", range).ok()?;
 		}
 
 		if let Some(components) = self.get_components() {
-			write!(s, "\n<b>Components</b>: {}", &components).ok()?;
+			write!(s, "\n<b>Components</b>: {}", components).ok()?;
 		}
 
 		if let Some(duration) = self.get_duration() {
-			write!(s, "\n<b>Duration</b>: {}", &duration).ok()?;
+			write!(s, "\n<b>Duration</b>: {}", duration).ok()?;
 		}
 
 		if let Some(entries) = self.get_entries("entries") {
-			write!(s, "\n\n{}", &entries.join("\n")).ok()?;
+			write!(s, "\n\n{}", entries.join("\n")).ok()?;
 		}
 
 		if let Some(entries_high_level) = self.get_entries("entriesHigherLevel") {
-			write!(s, "\n\n{}", &entries_high_level.join("\n")).ok()?;
+			write!(s, "\n\n{}", entries_high_level.join("\n")).ok()?;
 		}
 
 		if let Some(classes) = self.get_classes() {
-			write!(s, "\n\n<b>Classes</b>: {}", &classes.join(", ")).ok()?;
+			write!(s, "\n\n<b>Classes</b>: {}", classes.join(", ")).ok()?;
 		}
 
 		if let Some(source) = self.get_source() {
-			write!(s, "\n\n<i>{}</i>", &source).ok()?;
+			write!(s, "\n\n<i>{}</i>", source).ok()?;
 		}
 
 		Some(s)
@@ -199,9 +299,9 @@ fn get_distance(range: &Document) -> Option<String> {
 fn get_material(components: &Document) -> Option<String> {
 	let m = components.get("m")?;
 	match m {
-		ejdb::bson_crate::Bson::String(s) => Some(format!("M ({s})")),
-		ejdb::bson_crate::Bson::Boolean(_) => Some("M".to_string()),
-		ejdb::bson_crate::Bson::Document(obj) => {
+		bson::Bson::String(s) => Some(format!("M ({s})")),
+		bson::Bson::Boolean(_) => Some("M".to_string()),
+		bson::Bson::Document(obj) => {
 			let text = obj.get_str("text");
 			match text {
 				Ok(text) => Some(format!("M ({text})")),
@@ -250,3 +350,112 @@ fn get_duration(duration: &Document) -> Option<String> {
 
 	result.map(|s| s.capitalize())
 }
+
+// "fireball @5" → ("fireball", Some(5))
+pub fn split_spell_level(arg: &str) -> (&str, Option<i64>) {
+	lazy_static! {
+		static ref LEVEL_REGEX: Regex = Regex::new(r"^(?P<name>.*?)\s*@(?P<level>\d+)$").unwrap();
+	}
+
+	match LEVEL_REGEX.captures(arg) {
+		Some(caps) => match (caps.name("name"), caps["level"].parse().ok()) {
+			(Some(name), Some(level)) => (name.as_str(), Some(level)),
+			_ => (arg, None),
+		},
+		None => (arg, None),
+	}
+}
+
+fn collect_strings<'a>(b: &'a Bson, result: &mut Vec<&'a str>) {
+	match b {
+		Bson::String(s) => result.push(s),
+		Bson::Array(arr) => arr.iter().for_each(|b| collect_strings(b, result)),
+		Bson::Document(doc) => doc.iter().for_each(|(_, b)| collect_strings(b, result)),
+		_ => {}
+	}
+}
+
+// "3-9" or "1,3,5,7,9"
+fn parse_levels(levels: &str) -> Option<Vec<i64>> {
+	match levels.split_once('-') {
+		Some((min, max)) => Some((min.trim().parse().ok()?..=max.trim().parse().ok()?).collect()),
+		None => levels
+			.split(',')
+			.map(|level| level.trim().parse().ok())
+			.collect(),
+	}
+}
+
+// Adds `steps` times `step` dice to `base`, merging them when the dice faces match: 8d6 + 2×1d6 = 10d6
+fn scale_dice(base: &str, step: &str, steps: usize) -> String {
+	lazy_static! {
+		static ref DICE_REGEX: Regex = Regex::new(r"^(?P<num>\d*)d(?P<face>\d+)$").unwrap();
+	}
+
+	let (base, step) = (base.trim(), step.trim());
+	if steps == 0 {
+		return base.to_owned();
+	}
+
+	let parse = |dice: &str| -> Option<(usize, String)> {
+		let caps = DICE_REGEX.captures(dice)?;
+		let num = match &caps["num"] {
+			"" => 1,
+			num => num.parse().ok()?,
+		};
+		Some((num, caps["face"].to_owned()))
+	};
+
+	match (parse(base), parse(step)) {
+		(Some((base_num, base_face)), Some((step_num, step_face))) if base_face == step_face => {
+			format!("{}d{base_face}", base_num + step_num * steps)
+		}
+		(_, Some((step_num, step_face))) => format!("{base}+{}d{step_face}", step_num * steps),
+		_ => base.to_owned() + &format!("+{step}").repeat(steps),
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_split_spell_level() {
+		assert_eq!(split_spell_level("fireball @5"), ("fireball", Some(5)));
+		assert_eq!(split_spell_level("fireball"), ("fireball", None));
+	}
+
+	#[test]
+	fn test_format_scaling() {
+		let fireball = bson::doc! {
+			"name": "Fireball",
+			"level": 3i64,
+			"entries": ["Each creature takes {@damage 8d6} fire damage."],
+			"entriesHigherLevel": [(bson::doc! {
+				"type": "entries",
+				"entries": ["The damage increases by {@scaledamage 8d6|3-9|1d6} for each slot level above 3rd."]
+			})]
+		};
+		assert_eq!(
+			fireball.format_scaling(5).unwrap(),
+			"<b>Cast at 5th level</b>: <b>10d6</b>"
+		);
+		assert_eq!(
+			fireball.format_scaling(1).unwrap(),
+			"<b>Cast at 3rd level</b>: <b>8d6</b>"
+		);
+		assert_eq!(
+			fireball.format_scaling(12).unwrap(),
+			"<b>Cast at 9th level</b>: <b>14d6</b>"
+		);
+	}
+
+	#[test]
+	fn test_scale_dice() {
+		assert_eq!(scale_dice("8d6", "1d6", 2), "10d6");
+		assert_eq!(scale_dice("8d6", "1d6", 0), "8d6");
+		assert_eq!(scale_dice("3d8+5", "1d8", 1), "3d8+5+1d8");
+		assert_eq!(parse_levels("3-5"), Some(vec![3, 4, 5]));
+		assert_eq!(parse_levels("1,3,5"), Some(vec![1, 3, 5]));
+	}
+}
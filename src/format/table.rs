@@ -0,0 +1,198 @@
+use std::fmt::Write;
+
+use bson::{Bson, Document};
+use regex::Regex;
+
+use super::{format_entry, Entry};
+use crate::format::roll::{roll_results, DieFormatError};
+
+// A table which first column is a die range, e.g. Wild Magic Surge or Trinkets
+pub struct RollableTable {
+	pub caption: Option<String>,
+	pub dice: String,
+	rows: Vec<TableRow>,
+}
+
+struct TableRow {
+	min: i64,
+	max: i64,
+	cells: Vec<String>,
+}
+
+pub trait Tables: Entry {
+	// All rollable tables of the entry, in the order they appear in the document
+	fn get_rollable_tables(&self) -> Vec<RollableTable>;
+}
+
+impl Tables for Document {
+	fn get_rollable_tables(&self) -> Vec<RollableTable> {
+		let mut result = Vec::new();
+		collect_tables(self, &mut result);
+		result
+	}
+}
+
+fn collect_tables(doc: &Document, result: &mut Vec<RollableTable>) {
+	if let Some(table) = RollableTable::from_entry(doc) {
+		result.push(table);
+		return;
+	}
+	doc.iter()
+		.for_each(|(_, value)| collect_bson_tables(value, result));
+}
+
+fn collect_bson_tables(b: &Bson, result: &mut Vec<RollableTable>) {
+	match b {
+		Bson::Document(doc) => collect_tables(doc, result),
+		Bson::Array(arr) => arr.iter().for_each(|b| collect_bson_tables(b, result)),
+		_ => {}
+	}
+}
+
+impl RollableTable {
+	fn from_entry(entry: &Document) -> Option<Self> {
+		lazy_static! {
+			static ref DICE_REGEX: Regex = Regex::new(r"^\d*[dD]\d+$").unwrap();
+		}
+
+		if entry.get_str("type").ok()? != "table" {
+			return None;
+		}
+
+		let raw_rows = entry.get_array("rows").ok()?;
+		let rows = raw_rows
+			.iter()
+			.filter_map(TableRow::from_bson)
+			.collect::<Vec<_>>();
+		// Every row must have a range, otherwise some rolls would land nowhere
+		if rows.is_empty() || rows.len() != raw_rows.len() {
+			return None;
+		}
+
+		let max = rows.iter().map(|row| row.max).max()?;
+		let dice = entry
+			.get_array("colLabels")
+			.ok()
+			.and_then(|labels| labels.first())
+			.and_then(Bson::as_str)
+			.map(strip_tags)
+			.filter(|label| DICE_REGEX.is_match(label))
+			.unwrap_or_else(|| format!("1d{max}"));
+
+		Some(Self {
+			caption: entry.get_str("caption").map(str::to_owned).ok(),
+			dice,
+			rows,
+		})
+	}
+
+	pub fn roll(&self) -> Result<String, DieFormatError> {
+		let rolls = roll_results(&self.dice)?;
+		let Some(roll) = rolls.first() else {
+			return Err(DieFormatError::EmptyTable);
+		};
+		let value = roll.expression.calc();
+
+		let mut result = format!("{} = {value}\n\n", roll.expression);
+		match self
+			.rows
+			.iter()
+			.find(|row| row.min <= value && value <= row.max)
+		{
+			Some(row) => {
+				let range = if row.min == row.max {
+					row.min.to_string()
+				} else {
+					format!("{}–{}", row.min, row.max)
+				};
+				let _ = write!(result, "<b>{range}</b>: {}", row.cells.join(" | "));
+			}
+			None => result.push_str("Nothing happens"),
+		}
+
+		Ok(result)
+	}
+}
+
+impl TableRow {
+	fn from_bson(row: &Bson) -> Option<Self> {
+		let cells = match row {
+			Bson::Array(cells) => cells,
+			Bson::Document(doc) => doc.get_array("row").ok()?,
+			_ => return None,
+		};
+		let (first, rest) = cells.split_first()?;
+
+		let (min, max) = match first {
+			Bson::String(s) => parse_range(s)?,
+			Bson::Int32(n) => (*n as i64, *n as i64),
+			Bson::Int64(n) => (*n, *n),
+			Bson::Document(cell) => {
+				let roll = cell.get_document("roll").ok()?;
+				if let Ok(exact) = roll.get_i64("exact") {
+					(exact, exact)
+				} else {
+					(roll.get_i64("min").ok()?, roll.get_i64("max").ok()?)
+				}
+			}
+			_ => return None,
+		};
+
+		Some(Self {
+			min,
+			max,
+			cells: rest.iter().filter_map(format_entry).collect(),
+		})
+	}
+}
+
+// Parses "5", "01-10", "91–00" and similar ranges from the first table column
+fn parse_range(s: &str) -> Option<(i64, i64)> {
+	lazy_static! {
+		static ref RANGE_REGEX: Regex =
+			Regex::new(r"^\s*(?P<min>\d+)\s*(?:[-–—]\s*(?P<max>\d+))?\s*$").unwrap();
+	}
+
+	let parse_num = |s: &str| -> Option<i64> {
+		match s {
+			"00" => Some(100),
+			_ => s.parse().ok(),
+		}
+	};
+
+	let s = strip_tags(s);
+	let caps = RANGE_REGEX.captures(&s)?;
+	let min = parse_num(caps.name("min")?.as_str())?;
+	let max = match caps.name("max") {
+		Some(max) => parse_num(max.as_str())?,
+		None => min,
+	};
+	Some((min, max))
+}
+
+// Replaces 5etools tags like {@dice d100} with their text
+fn strip_tags(s: &str) -> String {
+	lazy_static! {
+		static ref TAG_REGEX: Regex = Regex::new(r"\{@\w+\s+(?P<text>[^|}]*)[^}]*\}").unwrap();
+	}
+	TAG_REGEX.replace_all(s, "$text").trim().to_owned()
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_parse_range() {
+		assert_eq!(parse_range("5"), Some((5, 5)));
+		assert_eq!(parse_range("01-10"), Some((1, 10)));
+		assert_eq!(parse_range("91–00"), Some((91, 100)));
+		assert_eq!(parse_range("Effect"), None);
+	}
+
+	#[test]
+	fn test_strip_tags() {
+		assert_eq!(strip_tags("{@dice d100}"), "d100");
+		assert_eq!(strip_tags("d8"), "d8");
+	}
+}
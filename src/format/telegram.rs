@@ -2,6 +2,8 @@ use teloxide::types::ChatKind;
 
 use crate::PROJECT_URL;
 
+use super::locale::Lang;
+
 pub fn chat_type_to_string(chat_type: &ChatKind) -> &'static str {
 	match chat_type {
 		ChatKind::Public(c) => match c.kind {
@@ -13,26 +15,101 @@ pub fn chat_type_to_string(chat_type: &ChatKind) -> &'static str {
 	}
 }
 
-pub fn help_message() -> String {
+pub fn help_message(lang: Lang) -> String {
+	match lang {
+		Lang::En => help_message_en(),
+		Lang::Ru => help_message_ru(),
+	}
+}
+
+fn help_message_en() -> String {
 	format!("Hi! I'm a bot. The Roll Bot!
 I can help you with your Dungeons&Dragons game (5th edition) and many others that require throwing dices. I can:
 
 /roll (or /r) - roll a die. By default I'll use d20, but you can give me any number of dices! e.g.: <code>/roll 2d6 +5</code>
 run <code>/help roll</code> to learn all the secrets of this command
+You can send several commands at once, e.g.: <code>/r 1d20+5 to hit /r 2d6+3 damage</code>
 
 /monster (or /m) - search for a monster. I'll look in every book in Candlekeep and find at least one. e.g.: <code>/monster tarasque</code>
 
 /spell (or /s) - search for a spell. I'll ask Elminster personally about it. e.g.: <code>/spell fireball</code>
+Add a slot level to see how much damage it deals when upcasted: <code>/spell fireball @5</code>
 
 /item (or /i) - search for an item. I'll cast Legend Lore spell to know what it is. e.g.: <code>/item bag of holding</code>
 
+/random - show a random monster for your next encounter. e.g.: <code>/random monster env:forest cr:1-3</code>
+
+/spawn - spawn a few monsters with rolled hit points and track their HP with buttons. e.g.: <code>/spawn 4 goblin</code>
+
+/hp, /cond and /combat - keep track of the fight: <code>/hp goblin2 -7</code> deals damage, <code>/hp Bob 30</code> adds a player, <code>/cond goblin2 +prone</code> adds a condition and <code>/combat</code> shows everyone. <code>/combat end</code> clears the board
+
+/check, /save and /attack - roll for your character. Send me a JSON file with it (e.g., a Foundry VTT actor export) first, then try <code>/check stealth</code>, <code>/save dex</code> or <code>/attack longsword</code>. Its modifiers also work in rolls: <code>/roll d20+@dex</code>
+
+/slots and /rest - track spell slots and other resources: <code>/slots 4 3 2</code> sets up spell slots, <code>/slots ki 5 short</code> adds any other resource, <code>/slots use 3</code> spends a slot, <code>/rest short</code> and <code>/rest long</code> restore them
+
+/find - search the text of spells, items and monsters when you don't remember the name. e.g.: <code>/find items that grant darkvision</code>
+
+/loot - roll a random treasure. I'll count every copper for you. e.g.: <code>/loot cr:5 hoard</code>
+
+/rule - search for a rule, action, sense or skill. I'll settle your table arguments once and for all. e.g.: <code>/rule grappling</code>
+
+/lang - choose my language in this chat. e.g.: <code>/lang ru</code>
+
+/settings - change the chat settings: the default die, critical hits, preferred sources, homebrew, rerolls and language. e.g.: <code>/settings die 100</code>
+
 My code is open like your brain to a Mind Flayer!
 You can get it <a href=\"{PROJECT_URL}\">here</a> (code, not brain)
 Suggestions and contributions are welcome.")
 }
 
-pub fn help_roll_message() -> &'static str {
-	r#"The bot supports full <a href="https://en.wikipedia.org/wiki/Dice_notation">dice notation</a>.
+fn help_message_ru() -> String {
+	format!("Привет! Я бот. Тот самый Roll Bot!
+Я помогу с твоей игрой в Dungeons&Dragons (5-я редакция) и во многие другие, где нужно бросать кубики. Я умею:
+
+/roll (или /r) - бросить кубик. По умолчанию я бросаю d20, но можно дать мне сколько угодно кубиков! Например: <code>/roll 2d6 +5</code>
+запусти <code>/help roll</code>, чтобы узнать все секреты этой команды
+Можно отправить несколько команд сразу, например: <code>/r 1d20+5 попадание /r 2d6+3 урон</code>
+
+/monster (или /m) - найти монстра. Я поищу в каждой книге Свечной Крепости и найду хотя бы одного. Например: <code>/monster tarasque</code>
+
+/spell (или /s) - найти заклинание. Я лично спрошу о нём Эльминстера. Например: <code>/spell fireball</code>
+Добавь уровень ячейки, чтобы увидеть урон при накладывании на более высоком уровне: <code>/spell fireball @5</code>
+
+/item (или /i) - найти предмет. Я наложу Легенды и предания, чтобы узнать, что это. Например: <code>/item bag of holding</code>
+
+/random - показать случайного монстра для следующей встречи. Например: <code>/random monster env:forest cr:1-3</code>
+
+/spawn - призвать несколько монстров со случайными хитами и отслеживать их хиты кнопками. Например: <code>/spawn 4 goblin</code>
+
+/hp, /cond и /combat - следить за боем: <code>/hp goblin2 -7</code> наносит урон, <code>/hp Bob 30</code> добавляет игрока, <code>/cond goblin2 +prone</code> добавляет состояние, а <code>/combat</code> показывает всех. <code>/combat end</code> очищает доску
+
+/check, /save и /attack - бросать за твоего персонажа. Сначала пришли мне JSON-файл с ним (например, экспорт актёра из Foundry VTT), затем попробуй <code>/check stealth</code>, <code>/save dex</code> или <code>/attack longsword</code>. Его модификаторы работают и в бросках: <code>/roll d20+@dex</code>
+
+/slots и /rest - следить за ячейками заклинаний и другими ресурсами: <code>/slots 4 3 2</code> задаёт ячейки, <code>/slots ki 5 short</code> добавляет любой другой ресурс, <code>/slots use 3</code> тратит ячейку, <code>/rest short</code> и <code>/rest long</code> восстанавливают их
+
+/find - искать по тексту заклинаний, предметов и монстров, если не помнишь название. Например: <code>/find items that grant darkvision</code>
+
+/loot - бросить случайное сокровище. Я пересчитаю каждую медную монетку. Например: <code>/loot cr:5 hoard</code>
+
+/rule - найти правило, действие, чувство или навык. Я раз и навсегда решу ваши споры за столом. Например: <code>/rule grappling</code>
+
+/lang - выбрать мой язык в этом чате. Например: <code>/lang en</code>
+
+/settings - изменить настройки чата: кубик по умолчанию, критические попадания, предпочитаемые источники, homebrew, перебросы и язык. Например: <code>/settings die 100</code>
+
+Мой код открыт, как твой мозг перед Пожирателем Разума!
+Его можно найти <a href=\"{PROJECT_URL}\">здесь</a> (код, не мозг)
+Предложения и помощь приветствуются.")
+}
+
+pub fn help_roll_message(lang: Lang) -> &'static str {
+	match lang {
+		Lang::En => HELP_ROLL_EN,
+		Lang::Ru => HELP_ROLL_RU,
+	}
+}
+
+const HELP_ROLL_EN: &str = r#"The bot supports full <a href="https://en.wikipedia.org/wiki/Dice_notation">dice notation</a>.
 Consider an example:
 <pre>
 /roll 1d20 + 5 longsword
@@ -66,5 +143,52 @@ And a couple of shortcuts again:
 
 Selector can be chained together:
 <code>5d20kh2dh1</code> → gives the second best roll of 5 d20
-"#
-}
+
+You can also just ask in plain words, in English or in Russian:
+<code>/r with advantage plus five</code> → +d20 + 5
+<code>/r 2 d 6 plus 3</code> → 2d6 + 3
+<code>/r брось 2к6 плюс три</code> → 2d6 + 3
+<code>/r stealth</code> → d20 + your stealth bonus (import your character first)
+"#;
+
+const HELP_ROLL_RU: &str = r#"Бот поддерживает полную <a href="https://ru.wikipedia.org/wiki/Нотация_игральных_костей">нотацию игральных костей</a>.
+Рассмотрим пример:
+<pre>
+/roll 1d20 + 5 длинный меч
+</pre>
+<code>1</code> - сколько кубиков бросить. Это может быть любое число ИЛИ <code>+</code> ИЛИ <code>-</code> для преимущества или помехи соответственно.
+<code>d20</code> - как можно догадаться, кубик для броска. <code>20</code> - число его граней, может быть любым. Вместо <code>d</code> можно писать <code>к</code> или <code>д</code>: <code>2к6</code>
+<code>5</code> - это "простое значение", но это может быть и другой кубик, например <code>1d4</code>
+<code>длинный меч</code> - комментарий к броску.
+
+Есть много сокращений, чтобы не стереть клавиатуру:
+<code>/r</code> → 1d20
+<code>/r 2</code> → 2d20
+<code>/r d4</code> → 1d4
+<code>/r +</code> → +d20 (d20 с преимуществом)
+<code>/r -</code> → -d20 (d20 с помехой)
+<code>/r +5</code> → 1d20+5
+<code>/r 2d%</code> → 2d100
+
+Поддерживаются кубики Fudge/Fate:
+<code>/r 4dF</code>
+
+К любому броску можно применить селекторы:
+<code>5d20kh2</code> → <b>оставить</b> 2 <b>наибольших</b> кубика из 5
+<code>5d20kl2</code> → <b>оставить</b> 2 <b>наименьших</b> кубика
+<code>5d20dh2</code> → <b>отбросить</b> 2 <b>наибольших</b> кубика
+<code>5d20dl2</code> → <b>отбросить</b> 2 <b>наименьших</b> кубика
+
+И снова пара сокращений:
+<code>2d20H</code> → 2d20kh1 (он же бросок с преимуществом)
+<code>2d20L</code> → 2d20kl1 (он же бросок с помехой)
+
+Селекторы можно объединять:
+<code>5d20kh2dh1</code> → второй лучший результат из 5 d20
+
+Можно просто попросить словами, по-русски или по-английски:
+<code>/r брось 2к6 плюс три</code> → 2d6 + 3
+<code>/r с преимуществом плюс пять</code> → +d20 + 5
+<code>/r 2 d 6 plus 3</code> → 2d6 + 3
+<code>/r stealth</code> → d20 + твой бонус скрытности (сначала загрузи персонажа)
+"#;
@@ -0,0 +1,272 @@
+use std::fmt::Write;
+
+use bson::{Bson, Document};
+
+use super::{
+	locale::{tr, Lang, Msg},
+	monster::Monster,
+	roll::roll_results,
+	utils::HtmlEscapable,
+	Entry,
+};
+
+// Telegram allows at most 100 buttons, each instance has a row of 4
+pub const MAX_SPAWN: usize = 20;
+// `/cond` only takes these, each one is a button with its rules
+pub const CONDITIONS: &[&str] = &[
+	"blinded",
+	"charmed",
+	"deafened",
+	"exhaustion",
+	"frightened",
+	"grappled",
+	"incapacitated",
+	"invisible",
+	"paralyzed",
+	"petrified",
+	"poisoned",
+	"prone",
+	"restrained",
+	"stunned",
+	"unconscious",
+];
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Combatant {
+	pub name: String,
+	pub hp: i64,
+	pub max_hp: i64,
+	pub conditions: Vec<String>,
+}
+
+impl Combatant {
+	// Returns false if nothing has changed, e.g. healing at full HP
+	pub fn apply(&mut self, delta: i64) -> bool {
+		let hp = (self.hp + delta).clamp(0, self.max_hp);
+		let changed = hp != self.hp;
+		self.hp = hp;
+		changed
+	}
+
+	// Sets HP directly, raising the maximum if needed
+	pub fn set_hp(&mut self, hp: i64) {
+		let hp = hp.max(0);
+		self.max_hp = self.max_hp.max(hp);
+		self.hp = hp;
+	}
+
+	// "goblin2" and "Goblin 2" are the same combatant
+	pub fn matches(&self, name: &str) -> bool {
+		combatant_key(&self.name) == combatant_key(name)
+	}
+
+	fn format(&self) -> String {
+		let mut result = format!(
+			"<b>{}</b>: {}/{} HP",
+			escape_name(&self.name),
+			self.hp,
+			self.max_hp
+		);
+		if self.hp == 0 {
+			result.push_str(" 💀");
+		}
+		if !self.conditions.is_empty() {
+			// Tags are turned into buttons with the condition rules
+			let conditions = self
+				.conditions
+				.iter()
+				.map(|condition| format!("{{@condition {condition}}}"))
+				.collect::<Vec<_>>()
+				.join(", ");
+			let _ = write!(result, " ({conditions})");
+		}
+		result
+	}
+}
+
+// Names are chosen by users, the board goes through `replace_string_links`
+// and "{@spell wish}" must not become a link
+pub fn escape_name(name: &str) -> String {
+	name.escape_html().replace('{', "&#123;")
+}
+
+fn combatant_key(name: &str) -> String {
+	name.chars()
+		.filter(|c| !c.is_whitespace())
+		.flat_map(char::to_lowercase)
+		.collect()
+}
+
+impl From<&Combatant> for Document {
+	fn from(combatant: &Combatant) -> Self {
+		let mut doc = Document::new();
+		doc.insert("name", combatant.name.clone());
+		doc.insert("hp", combatant.hp);
+		doc.insert("max_hp", combatant.max_hp);
+		doc.insert(
+			"conditions",
+			combatant
+				.conditions
+				.iter()
+				.cloned()
+				.map(Bson::String)
+				.collect::<Vec<_>>(),
+		);
+		doc
+	}
+}
+
+impl TryFrom<&Document> for Combatant {
+	type Error = bson::document::ValueAccessError;
+
+	fn try_from(doc: &Document) -> Result<Self, Self::Error> {
+		Ok(Self {
+			name: doc.get_str("name")?.to_owned(),
+			hp: doc.get_i64("hp")?,
+			max_hp: doc.get_i64("max_hp")?,
+			conditions: doc
+				.get_array("conditions")
+				.map(|conditions| {
+					conditions
+						.iter()
+						.filter_map(Bson::as_str)
+						.map(str::to_owned)
+						.collect()
+				})
+				.unwrap_or_default(),
+		})
+	}
+}
+
+// Rolls the HP formula once per instance, falling back to the average HP
+pub fn spawn_monsters(monster: &Document, count: usize) -> Option<Vec<Combatant>> {
+	let name = monster.get_name()?;
+	let average = monster.get_hp_average();
+	let formula = monster.get_hp_formula();
+
+	(1..=count)
+		.map(|i| {
+			let hp = formula.and_then(roll_hp).or(average)?.max(1);
+			let name = match count {
+				1 => name.clone(),
+				_ => format!("{name} {i}"),
+			};
+			Some(Combatant {
+				name,
+				hp,
+				max_hp: hp,
+				conditions: Vec::new(),
+			})
+		})
+		.collect()
+}
+
+fn roll_hp(formula: &str) -> Option<i64> {
+	// roll_results would treat a plain number as a number of d20
+	if let Ok(hp) = formula.trim().parse() {
+		return Some(hp);
+	}
+	let rolls = roll_results(formula).ok()?;
+	rolls.first().map(|roll| roll.expression.calc())
+}
+
+pub fn format_tracker(title: &str, combatants: &[Combatant]) -> String {
+	let mut result = format!("<b>{}</b>", title.escape_html());
+	for combatant in combatants {
+		let _ = write!(
+			result,
+			"\n{}: {}/{} HP",
+			combatant.name.escape_html(),
+			combatant.hp,
+			combatant.max_hp
+		);
+		if combatant.hp == 0 {
+			result.push_str(" 💀");
+		}
+	}
+	result
+}
+
+// Status board for the `/combat` command
+pub fn format_board(combatants: &[Combatant], lang: Lang) -> String {
+	if combatants.is_empty() {
+		return tr(lang, Msg::NoCombatants);
+	}
+	let mut result = "<b>Combat</b>".to_owned();
+	for combatant in combatants {
+		let _ = write!(result, "\n{}", combatant.format());
+	}
+	result
+}
+
+// A single line, used as a reply for the `/hp` and `/cond` commands
+pub fn format_combatant(combatant: &Combatant) -> String {
+	combatant.format()
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_tracker() {
+		let mut combatants = vec![
+			Combatant {
+				name: "Goblin 1".to_owned(),
+				hp: 7,
+				max_hp: 7,
+				conditions: Vec::new(),
+			},
+			Combatant {
+				name: "Goblin 2".to_owned(),
+				hp: 3,
+				max_hp: 9,
+				conditions: Vec::new(),
+			},
+		];
+		assert!(combatants[0].apply(-10));
+		assert!(!combatants[0].apply(-1));
+		assert!(combatants[1].apply(100));
+		assert_eq!(combatants[1].hp, 9);
+
+		let text = format_tracker("Goblin 1–2", &combatants);
+		assert_eq!(
+			text,
+			"<b>Goblin 1–2</b>\nGoblin 1: 0/7 HP 💀\nGoblin 2: 9/9 HP"
+		);
+	}
+
+	#[test]
+	fn test_combatant() {
+		let mut combatant = Combatant {
+			name: "Goblin 2".to_owned(),
+			hp: 7,
+			max_hp: 7,
+			conditions: vec!["prone".to_owned()],
+		};
+		assert!(combatant.matches("goblin2"));
+		assert!(!combatant.matches("goblin1"));
+		assert_eq!(
+			format_combatant(&combatant),
+			"<b>Goblin 2</b>: 7/7 HP ({@condition prone})"
+		);
+		assert_eq!(
+			Combatant::try_from(&Document::from(&combatant)).ok(),
+			Some(combatant.clone())
+		);
+		combatant.name = "{@spell wish} <b>".to_owned();
+		assert_eq!(
+			format_combatant(&combatant),
+			"<b>&#123;@spell wish} &lt;b&gt;</b>: 7/7 HP ({@condition prone})"
+		);
+		combatant.set_hp(12);
+		assert_eq!((combatant.hp, combatant.max_hp), (12, 12));
+	}
+
+	#[test]
+	fn test_roll_hp() {
+		assert_eq!(roll_hp("7"), Some(7));
+		let hp = roll_hp("2d6").unwrap();
+		assert!((2..=12).contains(&hp));
+	}
+}
@@ -12,7 +12,12 @@ mod db;
 mod fetch;
 mod format;
 mod metrics;
+mod privacy;
+mod search;
+mod stats;
+mod storage;
 mod telegram;
+mod update;
 
 use std::error::Error;
 use std::{
@@ -20,8 +25,6 @@ use std::{
 	time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use serde_json::Value as JsonValue;
-
 #[allow(unused_imports)]
 use tokio::task;
 use tokio::time;
@@ -34,9 +37,9 @@ pub const DONATION_URL: &str = "https://ko-fi.com/bemyak";
 lazy_static! {
 	static ref DB: DndDatabase = {
 		if env::var("ROLL_BOT_USE_TEST_DB").is_ok() {
-			DndDatabase::new("./test_data/roll_bot.ejdb").unwrap()
+			DndDatabase::new("./test_data/roll_bot.sqlite").unwrap()
 		} else {
-			DndDatabase::new("./roll_bot.ejdb").unwrap()
+			DndDatabase::new("./roll_bot.sqlite").unwrap()
 		}
 	};
 }
@@ -63,52 +66,30 @@ async fn run() -> Result<(), Box<dyn Error>> {
 		let mut interval = time::interval(Duration::from_secs(60 * 60 * 24));
 		loop {
 			interval.tick().await;
-			let fetch_result = fetch().await;
+			let fetch_result = update::update(false).await;
 			if let Err(err) = fetch_result {
 				error!("Error occurred while fetching data: {}", err)
 			}
 		}
 	});
 
-	telegram::start().await;
-
-	Ok(())
-}
-
-async fn fetch() -> Result<(), Box<dyn Error + Send + Sync>> {
-	let changelog = fetch::fetch_changelog().await?;
-	let ver = get_latest_ver(&changelog);
-	let cur_ver = DB.get_version().ok().flatten();
-
-	if !should_update(cur_ver.as_ref(), ver) {
-		info!(
-			"Skipping update, db is running the newest version: {:?}",
-			ver
-		);
-		return Ok(());
-	}
-
-	DB.save_collection(changelog, db::VER_COLLECTION_NAME)?;
-	let data = fetch::fetch().await?;
-	for (collection, items) in data {
-		DB.save_collection(items, &collection)?;
+	if let Some(retention) = privacy::log_retention() {
+		task::spawn(async move {
+			let mut interval = time::interval(Duration::from_secs(60 * 60));
+			loop {
+				interval.tick().await;
+				match DB.purge_log(get_unix_time().saturating_sub(retention)) {
+					Ok(0) => {}
+					Ok(deleted) => info!("Deleted {} expired log entries", deleted),
+					Err(err) => error!("Failed to delete expired log entries: {}", err),
+				}
+			}
+		});
 	}
-	DB.update_cache();
-	Ok(())
-}
 
-fn should_update(cur_ver: Option<&String>, ver: Option<&str>) -> bool {
-	match (cur_ver, ver) {
-		(Some(cur_ver), Some(ver)) => cur_ver != ver,
-		_ => true,
-	}
-}
+	telegram::start().await;
 
-fn get_latest_ver(changelog: &[JsonValue]) -> Option<&str> {
-	let last = changelog.last()?;
-	let doc = last.as_object()?;
-	let ver = doc.get("ver")?;
-	ver.as_str()
+	Ok(())
 }
 
 pub fn get_unix_time() -> u64 {
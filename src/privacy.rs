@@ -0,0 +1,57 @@
+use std::env;
+
+use sha2::{Digest, Sha256};
+
+use crate::stats::DAY;
+
+// Logged user ids are replaced with a salted hash when this is set
+const USER_ID_SALT_VAR: &str = "ROLL_BOT_USER_ID_SALT";
+// Logged messages older than this are deleted, they are kept forever by default
+const LOG_RETENTION_VAR: &str = "ROLL_BOT_LOG_RETENTION_DAYS";
+
+lazy_static! {
+	static ref USER_ID_SALT: Option<String> = env::var(USER_ID_SALT_VAR)
+		.ok()
+		.filter(|salt| !salt.is_empty());
+	static ref LOG_RETENTION: Option<u64> = env::var(LOG_RETENTION_VAR)
+		.ok()
+		.and_then(|days| days.trim().parse::<u64>().ok())
+		.filter(|days| *days > 0)
+		.map(|days| days * DAY);
+}
+
+// Retention period in seconds
+pub fn log_retention() -> Option<u64> {
+	*LOG_RETENTION
+}
+
+// The id the messages of this user are logged under
+pub fn log_user_id(user_id: i64) -> i64 {
+	match USER_ID_SALT.as_deref() {
+		Some(salt) => hash_user_id(salt, user_id),
+		None => user_id,
+	}
+}
+
+// Messages logged before the hashing was enabled still have the raw id
+pub fn logged_user_ids(user_id: i64) -> Vec<i64> {
+	let mut ids = vec![log_user_id(user_id), user_id];
+	ids.dedup();
+	ids
+}
+
+fn hash_user_id(salt: &str, user_id: i64) -> i64 {
+	let digest = Sha256::new()
+		.chain_update(salt.as_bytes())
+		.chain_update(user_id.to_le_bytes())
+		.finalize();
+	i64::from_le_bytes(digest[..8].try_into().unwrap())
+}
+
+#[test]
+fn test_hash_user_id() {
+	assert_eq!(hash_user_id("pepper", 42), hash_user_id("pepper", 42));
+	assert_ne!(hash_user_id("pepper", 42), hash_user_id("pepper", 43));
+	assert_ne!(hash_user_id("pepper", 42), hash_user_id("salt", 42));
+	assert_ne!(hash_user_id("pepper", 42), 42);
+}
@@ -0,0 +1,320 @@
+use std::collections::{HashMap, HashSet};
+
+use bson::{Bson, Document};
+use regex::{Match, Regex};
+
+use crate::collection::CollectionName;
+use crate::format::utils::HtmlEscapable;
+
+// BM25 parameters, the usual defaults
+const K1: f64 = 1.2;
+const B: f64 = 0.75;
+// Words shown around the rarest word of the query
+const SNIPPET_WORDS_BEFORE: usize = 8;
+const SNIPPET_WORDS_AFTER: usize = 16;
+
+// Fields with the text of spells, items, monsters and rules
+const TEXT_FIELDS: &[&str] = &[
+	"entries",
+	"entriesHigherLevel",
+	"additionalEntries",
+	"trait",
+	"action",
+	"bonus",
+	"reaction",
+	"legendary",
+	"mythic",
+];
+// Too common to tell the entries apart, "the spells that deal fire damage" looks for "spells fire damage"
+const STOP_WORDS: &[&str] = &[
+	"an", "and", "are", "as", "at", "be", "by", "can", "do", "for", "from", "has", "have", "in",
+	"is", "it", "its", "of", "on", "or", "that", "the", "their", "them", "there", "these", "this",
+	"to", "what", "when", "which", "who", "will", "with", "you", "your",
+];
+// Markup of the nested entries, not the text
+const SKIPPED_KEYS: &[&str] = &["type", "source", "page", "style", "colStyles", "href"];
+
+lazy_static! {
+	static ref WORD: Regex = Regex::new(r"\w+").unwrap();
+	static ref WHITESPACE: Regex = Regex::new(r"\s+").unwrap();
+	// "{@condition frightened}" or "{@spell fireball|PHB}" → the text
+	static ref TAG: Regex = Regex::new(r"\{@\w+ ?([^|}]*)[^}]*\}").unwrap();
+}
+
+// Full-text index over the entries of every collection, see `/find`.
+// `SimSearch` in `DndDatabase::cache` only knows the names
+#[derive(Default)]
+pub struct TextIndex {
+	docs: Vec<IndexedDoc>,
+	// Word → documents with it
+	postings: HashMap<String, Vec<Posting>>,
+	total_words: u64,
+}
+
+struct IndexedDoc {
+	collection: CollectionName,
+	name: String,
+	words: u32,
+}
+
+struct Posting {
+	doc: u32,
+	count: u32,
+}
+
+#[derive(Debug)]
+pub struct Hit {
+	pub collection: CollectionName,
+	// "Name (SOURCE)", as in the search cache
+	pub name: String,
+}
+
+impl TextIndex {
+	pub fn insert(&mut self, collection: CollectionName, name: String, text: &str) {
+		let mut counts: HashMap<String, u32> = HashMap::new();
+		let mut words = 0;
+		for word in tokenize(text) {
+			*counts.entry(word).or_default() += 1;
+			words += 1;
+		}
+		let doc = self.docs.len() as u32;
+		for (word, count) in counts {
+			self.postings
+				.entry(word)
+				.or_default()
+				.push(Posting { doc, count });
+		}
+		self.docs.push(IndexedDoc {
+			collection,
+			name,
+			words,
+		});
+		self.total_words += words as u64;
+	}
+
+	// Best hits first. Entries don't need every word of the query, but the ones that have more of them go first
+	pub fn search(&self, query: &str) -> Vec<Hit> {
+		let terms = query_terms(query);
+		if terms.is_empty() || self.docs.is_empty() {
+			return Vec::new();
+		}
+		let docs = self.docs.len() as f64;
+		let avg_words = self.total_words as f64 / docs;
+		let mut scores: HashMap<u32, (f64, usize)> = HashMap::new();
+		for term in &terms {
+			let Some(postings) = self.postings.get(term) else {
+				continue;
+			};
+			let idf = self.idf(term);
+			for posting in postings {
+				let words = self.docs[posting.doc as usize].words as f64;
+				let tf = posting.count as f64;
+				let score = idf * tf * (K1 + 1.0) / (tf + K1 * (1.0 - B + B * words / avg_words));
+				let entry = scores.entry(posting.doc).or_default();
+				entry.0 += score;
+				entry.1 += 1;
+			}
+		}
+		let mut hits = scores
+			.into_iter()
+			.map(|(doc, (score, matched))| (score * matched as f64 / terms.len() as f64, doc))
+			.collect::<Vec<_>>();
+		hits.sort_by(|(a_score, a), (b_score, b)| {
+			b_score.total_cmp(a_score).then_with(|| {
+				self.docs[*a as usize]
+					.name
+					.cmp(&self.docs[*b as usize].name)
+			})
+		});
+		hits.into_iter()
+			.map(|(_, doc)| {
+				let doc = &self.docs[doc as usize];
+				Hit {
+					collection: doc.collection,
+					name: doc.name.clone(),
+				}
+			})
+			.collect()
+	}
+
+	// A piece of the text around the rarest query word as HTML, the query words are bold
+	pub fn snippet(&self, text: &str, query: &str) -> Option<String> {
+		let terms = query_terms(query);
+		let words = WORD.find_iter(text).collect::<Vec<_>>();
+		let center = words
+			.iter()
+			.enumerate()
+			.filter_map(|(i, word)| {
+				let word = word.as_str().to_lowercase();
+				terms.contains(&word).then(|| (i, self.idf(&word)))
+			})
+			// The first occurrence of the rarest word
+			.fold(None, |best: Option<(usize, f64)>, (i, idf)| match best {
+				Some((_, best_idf)) if best_idf >= idf => best,
+				_ => Some((i, idf)),
+			})?
+			.0;
+		let start = center.saturating_sub(SNIPPET_WORDS_BEFORE);
+		let end = (center + SNIPPET_WORDS_AFTER).min(words.len());
+
+		let mut result = String::new();
+		if start > 0 {
+			result.push('…');
+		}
+		let mut prev: Option<&Match> = None;
+		for word in &words[start..end] {
+			if let Some(prev) = prev {
+				let gap = WHITESPACE.replace_all(&text[prev.end()..word.start()], " ");
+				result.push_str(&gap.escape_html());
+			}
+			if terms.contains(&word.as_str().to_lowercase()) {
+				result.push_str(&format!("<b>{}</b>", word.as_str().escape_html()));
+			} else {
+				result.push_str(&word.as_str().escape_html());
+			}
+			prev = Some(word);
+		}
+		if end < words.len() {
+			result.push('…');
+		}
+		Some(result)
+	}
+
+	// Rare words weigh more
+	fn idf(&self, term: &str) -> f64 {
+		let docs = self.docs.len() as f64;
+		let df = self.postings.get(term).map_or(0, Vec::len) as f64;
+		((docs - df + 0.5) / (df + 0.5) + 1.0).ln()
+	}
+}
+
+// Plain text of the entry: the name and the text fields without the 5etools tags
+pub fn entry_text(doc: &Document) -> String {
+	let mut parts = Vec::new();
+	if let Ok(name) = doc.get_str("name") {
+		parts.push(name.to_owned());
+	}
+	for field in TEXT_FIELDS {
+		if let Some(value) = doc.get(field) {
+			collect_strings(value, &mut parts);
+		}
+	}
+	TAG.replace_all(&parts.join("\n"), "$1").into_owned()
+}
+
+fn collect_strings(value: &Bson, parts: &mut Vec<String>) {
+	match value {
+		Bson::String(s) => parts.push(s.clone()),
+		Bson::Array(values) => values
+			.iter()
+			.for_each(|value| collect_strings(value, parts)),
+		Bson::Document(doc) => doc
+			.iter()
+			.filter(|(key, _)| !SKIPPED_KEYS.contains(&key.as_str()))
+			.for_each(|(_, value)| collect_strings(value, parts)),
+		_ => {}
+	}
+}
+
+fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+	WORD.find_iter(text)
+		.map(|word| word.as_str().to_lowercase())
+		.filter(|word| word.chars().count() > 1)
+}
+
+fn query_terms(query: &str) -> HashSet<String> {
+	tokenize(query)
+		.filter(|word| !STOP_WORDS.contains(&word.as_str()))
+		.collect()
+}
+
+#[cfg(test)]
+mod test {
+	use bson::doc;
+
+	use super::{entry_text, query_terms, TextIndex};
+
+	#[test]
+	fn test_entry_text() {
+		let spell = doc! {
+			"name": "Cause Fear",
+			"source": "XGE",
+			"entries": [
+				"A creature must succeed on a Wisdom saving throw or become {@condition frightened} of you.",
+				(doc! {"type": "entries", "name": "Undead", "entries": ["Undead are immune."]})
+			]
+		};
+		assert_eq!(
+			entry_text(&spell),
+			"Cause Fear\nA creature must succeed on a Wisdom saving throw or become frightened of you.\nUndead\nUndead are immune."
+		);
+	}
+
+	#[test]
+	fn test_search() {
+		let mut index = TextIndex::default();
+		index.insert(
+			"spell",
+			"Cause Fear (XGE)".to_owned(),
+			"Cause Fear\nThe target becomes frightened of you. A frightened creature can repeat the save.",
+		);
+		index.insert(
+			"spell",
+			"Fireball (PHB)".to_owned(),
+			"Fireball\nA bright streak flashes to a point you choose and blossoms into flame.",
+		);
+		index.insert(
+			"item",
+			"Goggles of Night (DMG)".to_owned(),
+			"Goggles of Night\nWhile wearing these dark lenses, you have darkvision out to a range of 60 feet.",
+		);
+		index.insert(
+			"monster",
+			"Dragon (MM)".to_owned(),
+			"Dragon\nFrightful Presence. Each creature must succeed or become frightened for 1 minute.",
+		);
+
+		let hits = index.search("Frightened");
+		assert_eq!(
+			hits.iter().map(|hit| hit.name.as_str()).collect::<Vec<_>>(),
+			vec!["Cause Fear (XGE)", "Dragon (MM)"]
+		);
+		let hits = index.search("items that grant darkvision");
+		assert_eq!(hits[0].name, "Goggles of Night (DMG)");
+		assert_eq!(hits[0].collection, "item");
+		assert!(index.search("tarrasque").is_empty());
+		assert!(index.search("").is_empty());
+		assert!(index.search("what is the").is_empty());
+	}
+
+	#[test]
+	fn test_query_terms() {
+		let mut terms = query_terms("The items that grant Darkvision")
+			.into_iter()
+			.collect::<Vec<_>>();
+		terms.sort();
+		assert_eq!(terms, vec!["darkvision", "grant", "items"]);
+	}
+
+	#[test]
+	fn test_snippet() {
+		let text = "While wearing these dark lenses, you have darkvision out to a range of 60 feet. If you already have darkvision, wearing the goggles increases its range by 60 feet.";
+		let mut index = TextIndex::default();
+		index.insert("item", "Goggles of Night (DMG)".to_owned(), text);
+		index.insert(
+			"race",
+			"Elf (PHB)".to_owned(),
+			"Accustomed to twilit forests, you have darkvision.",
+		);
+		assert_eq!(
+			index.snippet(text, "darkvision").unwrap(),
+			"While wearing these dark lenses, you have <b>darkvision</b> out to a range of 60 feet. If you already have <b>darkvision</b>, wearing the goggles…"
+		);
+		// Centered on "goggles", "darkvision" is in every entry
+		assert_eq!(
+			index.snippet(text, "darkvision goggles").unwrap(),
+			"…feet. If you already have <b>darkvision</b>, wearing the <b>goggles</b> increases its range by 60 feet"
+		);
+		assert_eq!(index.snippet(text, "fireball"), None);
+	}
+}
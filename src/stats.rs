@@ -0,0 +1,285 @@
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+
+use bson::{doc, document::ValueAccessError, Bson, Document};
+
+pub const DAY: u64 = 60 * 60 * 24;
+pub const MONTH: u64 = 30 * DAY;
+
+// Upper bounds of the latency histogram buckets, ms
+const LATENCY_BUCKETS: [u64; 14] = [
+	10,
+	25,
+	50,
+	100,
+	250,
+	500,
+	1000,
+	2500,
+	5000,
+	10000,
+	25000,
+	50000,
+	100000,
+	u64::MAX,
+];
+
+// Usage aggregates for `/stats`, updated with every logged message instead of scanning the log
+#[derive(Debug, Default)]
+pub struct UsageStats {
+	days: BTreeMap<u64, DayStats>,
+	// Unique users can't be summed up from the daily buckets, so the last message of each user is kept
+	last_seen: HashMap<i64, u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DayStats {
+	// Days since the Unix epoch
+	pub day: u64,
+	pub messages: u64,
+	pub users: u64,
+	pub latency: Latency,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Latency {
+	sum: u64,
+	min: u64,
+	max: u64,
+	buckets: Vec<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Summary {
+	pub messages: u64,
+	pub users: usize,
+	pub latency: Latency,
+}
+
+impl UsageStats {
+	pub fn new(days: Vec<DayStats>, last_seen: HashMap<i64, u64>) -> Self {
+		Self {
+			days: days.into_iter().map(|day| (day.day, day)).collect(),
+			last_seen,
+		}
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.days.is_empty()
+	}
+
+	// Returns the updated bucket, so it can be saved
+	pub fn record(&mut self, user_id: i64, timestamp: u64, latency: u64) -> &DayStats {
+		let day = timestamp / DAY;
+		let stats = self.days.entry(day).or_insert_with(|| DayStats {
+			day,
+			..Default::default()
+		});
+		stats.messages += 1;
+		stats.latency.record(latency);
+		let prev = self.last_seen.get(&user_id).copied();
+		if prev.is_none_or(|prev| prev / DAY < day) {
+			stats.users += 1;
+		}
+		if prev.is_none_or(|prev| prev < timestamp) {
+			self.last_seen.insert(user_id, timestamp);
+		}
+		stats
+	}
+
+	// Daily buckets are anonymous, only the user's last message is forgotten
+	pub fn forget(&mut self, user_id: i64) {
+		self.last_seen.remove(&user_id);
+	}
+
+	pub fn forget_before(&mut self, timestamp: u64) {
+		self.last_seen
+			.retain(|_, last_seen| *last_seen >= timestamp);
+	}
+
+	pub fn last_seen(&self, user_id: i64) -> Option<u64> {
+		self.last_seen.get(&user_id).copied()
+	}
+
+	pub fn days(&self) -> impl Iterator<Item = &DayStats> {
+		self.days.values()
+	}
+
+	pub fn users(&self) -> impl Iterator<Item = (&i64, &u64)> {
+		self.last_seen.iter()
+	}
+
+	// Days are not split, so everything from the day of `timestamp` is counted
+	pub fn since(&self, timestamp: u64) -> Summary {
+		let mut summary = Summary::default();
+		for stats in self.days.range(timestamp / DAY..).map(|(_, stats)| stats) {
+			summary.messages += stats.messages;
+			summary.latency.merge(&stats.latency);
+		}
+		summary.users = self
+			.last_seen
+			.values()
+			.filter(|last_seen| **last_seen >= timestamp)
+			.count();
+		summary
+	}
+}
+
+impl Default for Latency {
+	fn default() -> Self {
+		Self {
+			sum: 0,
+			min: 0,
+			max: 0,
+			buckets: vec![0; LATENCY_BUCKETS.len()],
+		}
+	}
+}
+
+impl Latency {
+	pub fn count(&self) -> u64 {
+		self.buckets.iter().sum()
+	}
+
+	pub fn min(&self) -> u64 {
+		self.min
+	}
+
+	pub fn max(&self) -> u64 {
+		self.max
+	}
+
+	pub fn avg(&self) -> u64 {
+		self.sum.checked_div(self.count()).unwrap_or_default()
+	}
+
+	// Upper bound of the bucket the percentile falls into, but never more than the max latency
+	pub fn percentile(&self, percent: u64) -> u64 {
+		let target = (self.count() * percent).div_ceil(100).max(1);
+		let mut seen = 0;
+		for (bucket, bound) in self.buckets.iter().zip(LATENCY_BUCKETS) {
+			seen += bucket;
+			if seen >= target {
+				return bound.min(self.max);
+			}
+		}
+		self.max
+	}
+
+	fn record(&mut self, latency: u64) {
+		if self.count() == 0 || latency < self.min {
+			self.min = latency;
+		}
+		self.max = self.max.max(latency);
+		self.sum += latency;
+		let bucket = LATENCY_BUCKETS
+			.iter()
+			.position(|bound| latency <= *bound)
+			.unwrap_or(LATENCY_BUCKETS.len() - 1);
+		self.buckets[bucket] += 1;
+	}
+
+	fn merge(&mut self, other: &Latency) {
+		if other.count() == 0 {
+			return;
+		}
+		if self.count() == 0 || other.min < self.min {
+			self.min = other.min;
+		}
+		self.max = self.max.max(other.max);
+		self.sum += other.sum;
+		for (bucket, other) in self.buckets.iter_mut().zip(&other.buckets) {
+			*bucket += other;
+		}
+	}
+}
+
+impl From<&DayStats> for Document {
+	fn from(stats: &DayStats) -> Self {
+		let buckets = stats
+			.latency
+			.buckets
+			.iter()
+			.map(|bucket| Bson::Int64(*bucket as i64))
+			.collect::<Vec<_>>();
+		doc! {
+			"day": stats.day as i64,
+			"messages": stats.messages as i64,
+			"users": stats.users as i64,
+			"latency_sum": stats.latency.sum as i64,
+			"latency_min": stats.latency.min as i64,
+			"latency_max": stats.latency.max as i64,
+			"latency_buckets": buckets
+		}
+	}
+}
+
+impl TryFrom<&Document> for DayStats {
+	type Error = ValueAccessError;
+	fn try_from(doc: &Document) -> Result<Self, Self::Error> {
+		let mut buckets = doc
+			.get_array("latency_buckets")?
+			.iter()
+			.map(|bucket| bucket.as_i64().unwrap_or_default() as u64)
+			.collect::<Vec<_>>();
+		// Buckets might be added later
+		buckets.resize(LATENCY_BUCKETS.len(), 0);
+		Ok(Self {
+			day: doc.get_i64("day")? as u64,
+			messages: doc.get_i64("messages")? as u64,
+			users: doc.get_i64("users")? as u64,
+			latency: Latency {
+				sum: doc.get_i64("latency_sum")? as u64,
+				min: doc.get_i64("latency_min")? as u64,
+				max: doc.get_i64("latency_max")? as u64,
+				buckets,
+			},
+		})
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::convert::TryFrom;
+
+	use bson::Document;
+
+	use super::{DayStats, UsageStats, DAY};
+
+	#[test]
+	fn test_usage_stats() {
+		let mut stats = UsageStats::default();
+		let now = 100 * DAY;
+		stats.record(1, now - 40 * DAY, 3000);
+		stats.record(1, now, 20);
+		stats.record(2, now, 40);
+		stats.record(2, now + 10, 200);
+		let today = stats.record(1, now + 20, 30).clone();
+		assert_eq!(today.messages, 4);
+		assert_eq!(today.users, 2);
+
+		let month = stats.since(now - 30 * DAY);
+		assert_eq!(month.messages, 4);
+		assert_eq!(month.users, 2);
+		assert_eq!(month.latency.min(), 20);
+		assert_eq!(month.latency.max(), 200);
+		assert_eq!(month.latency.avg(), 72);
+		assert_eq!(month.latency.percentile(50), 50);
+		assert_eq!(month.latency.percentile(95), 200);
+
+		let total = stats.since(0);
+		assert_eq!(total.messages, 5);
+		assert_eq!(total.latency.max(), 3000);
+		assert_eq!(total.latency.percentile(99), 3000);
+
+		assert_eq!(DayStats::try_from(&Document::from(&today)).unwrap(), today);
+	}
+
+	#[test]
+	fn test_empty_stats() {
+		let summary = UsageStats::default().since(0);
+		assert_eq!(summary.messages, 0);
+		assert_eq!(summary.latency.avg(), 0);
+		assert_eq!(summary.latency.percentile(50), 0);
+	}
+}
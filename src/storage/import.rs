@@ -0,0 +1,108 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use bson::{Bson, Document};
+use serde_json::Value as JsonValue;
+
+use super::{json_to_bson, Result, Storage, StorageError};
+
+// Written by `tools/ejdb_export` next to the old `roll_bot.ejdb`
+pub const EJDB_DUMP_EXTENSION: &str = "ejdb.jsonl";
+// D&D data is downloaded again, and so is the version, otherwise the update would be skipped
+const SKIPPED_COLLECTIONS: &[&str] = &["_ver", "_active"];
+
+// One-shot import of the chat settings, characters, resources and the log of an EJDB database.
+// Collections that already have documents are left alone, the dump is renamed once it is imported
+pub fn import_ejdb_dump(storage: &dyn Storage, path: &Path) -> Result<usize> {
+	if !path.exists() {
+		return Ok(0);
+	}
+	let mut collections: BTreeMap<String, Vec<Document>> = BTreeMap::new();
+	for line in BufReader::new(File::open(path)?).lines() {
+		let line = line?;
+		if line.trim().is_empty() {
+			continue;
+		}
+		let (collection, doc) = parse_line(&line)?;
+		if collection.starts_with('_') && !SKIPPED_COLLECTIONS.contains(&collection.as_str()) {
+			collections.entry(collection).or_default().push(doc);
+		}
+	}
+
+	let mut imported = 0;
+	for (collection, docs) in collections {
+		if storage.count(&collection)? > 0 {
+			warn!("{collection} is not empty, skipping it");
+			continue;
+		}
+		imported += docs.len();
+		storage.insert_many(&collection, docs)?;
+	}
+	let mut done = path.as_os_str().to_owned();
+	done.push(".imported");
+	fs::rename(path, done)?;
+	info!("Imported {imported} documents from {}", path.display());
+	Ok(imported)
+}
+
+// {"collection": "_settings", "doc": {...}}
+fn parse_line(line: &str) -> Result<(String, Document)> {
+	let mut value: JsonValue = serde_json::from_str(line)?;
+	let collection = value["collection"]
+		.as_str()
+		.ok_or_else(|| StorageError::Corrupted(line.to_owned()))?
+		.to_owned();
+	match json_to_bson(value["doc"].take()) {
+		Bson::Document(mut doc) => {
+			// EJDB ids mean nothing to SQLite
+			doc.remove("_id");
+			Ok((collection, doc))
+		}
+		_ => Err(StorageError::Corrupted(line.to_owned())),
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::{env, fs};
+
+	use bson::doc;
+
+	use super::import_ejdb_dump;
+	use crate::storage::{Filter, MemoryStorage, Storage};
+
+	#[test]
+	fn test_import_ejdb_dump() {
+		let path = env::temp_dir().join("roll_bot_test_import.ejdb.jsonl");
+		fs::write(
+			&path,
+			r#"{"collection": "_settings", "doc": {"_id": {"$oid": "5e8f8f8f8f8f8f8f8f8f8f8f"}, "chat_id": 42, "lang": "ru"}}
+{"collection": "_characters", "doc": {"user_id": 7, "name": "Shmuel"}}
+{"collection": "_ver", "doc": {"ver": "1.0.0"}}
+{"collection": "spell", "doc": {"name": "Fireball"}}
+{"collection": "_combat", "doc": {"chat_id": 42}}
+"#,
+		)
+		.unwrap();
+		let storage = MemoryStorage::default();
+		storage.insert("_combat", doc! {"chat_id": 1i64}).unwrap();
+
+		assert_eq!(import_ejdb_dump(&storage, &path).unwrap(), 2);
+		let settings = storage
+			.find_one("_settings", &Filter::new().eq("chat_id", 42i64))
+			.unwrap()
+			.unwrap();
+		assert_eq!(settings, doc! {"chat_id": 42i64, "lang": "ru"});
+		assert_eq!(storage.count("_characters").unwrap(), 1);
+		assert_eq!(storage.count("_combat").unwrap(), 1);
+		assert_eq!(storage.count("_ver").unwrap(), 0);
+		assert_eq!(storage.count("spell").unwrap(), 0);
+
+		// Renamed, so the next start doesn't import it again
+		assert!(!path.exists());
+		assert_eq!(import_ejdb_dump(&storage, &path).unwrap(), 0);
+		fs::remove_file(path.with_extension("jsonl.imported")).unwrap();
+	}
+}
@@ -0,0 +1,91 @@
+use std::collections::BTreeMap;
+use std::sync::RwLock;
+
+use bson::Document;
+
+use super::{CollectionInfo, Filter, Result, Storage};
+
+// Keeps everything in memory, used by the tests
+#[derive(Default)]
+pub struct MemoryStorage {
+	collections: RwLock<BTreeMap<String, Vec<Document>>>,
+}
+
+impl Storage for MemoryStorage {
+	fn insert_many(&self, collection: &str, docs: Vec<Document>) -> Result<()> {
+		let mut collections = self.collections.write().unwrap();
+		collections
+			.entry(collection.to_owned())
+			.or_default()
+			.extend(docs);
+		Ok(())
+	}
+
+	fn find(&self, collection: &str, filter: &Filter) -> Result<Vec<Document>> {
+		let collections = self.collections.read().unwrap();
+		Ok(collections
+			.get(collection)
+			.map(|docs| {
+				docs.iter()
+					.filter(|doc| filter.matches(doc))
+					.cloned()
+					.collect()
+			})
+			.unwrap_or_default())
+	}
+
+	fn find_one(&self, collection: &str, filter: &Filter) -> Result<Option<Document>> {
+		let collections = self.collections.read().unwrap();
+		Ok(collections
+			.get(collection)
+			.and_then(|docs| docs.iter().find(|doc| filter.matches(doc)))
+			.cloned())
+	}
+
+	fn upsert_many(&self, collection: &str, new_docs: Vec<(Filter, Document)>) -> Result<()> {
+		let mut collections = self.collections.write().unwrap();
+		let docs = collections.entry(collection.to_owned()).or_default();
+		for (filter, doc) in new_docs {
+			match docs.iter_mut().find(|old| filter.matches(old)) {
+				Some(old) => *old = doc,
+				None => docs.push(doc),
+			}
+		}
+		Ok(())
+	}
+
+	fn delete(&self, collection: &str, filter: &Filter) -> Result<usize> {
+		let mut collections = self.collections.write().unwrap();
+		let Some(docs) = collections.get_mut(collection) else {
+			return Ok(0);
+		};
+		let len = docs.len();
+		docs.retain(|doc| !filter.matches(doc));
+		Ok(len - docs.len())
+	}
+
+	fn count(&self, collection: &str) -> Result<usize> {
+		let collections = self.collections.read().unwrap();
+		Ok(collections
+			.get(collection)
+			.map(Vec::len)
+			.unwrap_or_default())
+	}
+
+	fn drop_collection(&self, collection: &str) -> Result<()> {
+		self.collections.write().unwrap().remove(collection);
+		Ok(())
+	}
+
+	fn collections(&self) -> Result<Vec<CollectionInfo>> {
+		let collections = self.collections.read().unwrap();
+		Ok(collections
+			.iter()
+			.filter(|(name, docs)| !name.starts_with('_') && !docs.is_empty())
+			.map(|(name, docs)| CollectionInfo {
+				name: name.clone(),
+				records: docs.len(),
+			})
+			.collect())
+	}
+}
@@ -0,0 +1,329 @@
+mod import;
+#[cfg(test)]
+mod memory;
+mod sqlite;
+
+use bson::{Bson, Document};
+use serde_json::Value as JsonValue;
+use thiserror::Error;
+
+pub use import::{import_ejdb_dump, EJDB_DUMP_EXTENSION};
+#[cfg(test)]
+pub use memory::MemoryStorage;
+pub use sqlite::SqliteStorage;
+
+pub type Result<T> = std::result::Result<T, StorageError>;
+
+#[derive(Error, Debug)]
+pub enum StorageError {
+	#[error("SQLite Error {0}")]
+	Sqlite(#[from] rusqlite::Error),
+	#[error("Malformed document {0}")]
+	Json(#[from] serde_json::Error),
+	#[error("Corrupted document: {0}")]
+	Corrupted(String),
+	#[error("Unsupported query: {0}")]
+	Unsupported(String),
+	#[error("I/O Error {0}")]
+	Io(#[from] std::io::Error),
+}
+
+// A document store where documents are grouped into collections.
+// `DndDatabase` keeps the bot logic, backends only store and filter the documents
+pub trait Storage: Send + Sync {
+	// Appends the documents to the collection, all or none of them are saved
+	fn insert_many(&self, collection: &str, docs: Vec<Document>) -> Result<()>;
+
+	fn insert(&self, collection: &str, doc: Document) -> Result<()> {
+		self.insert_many(collection, vec![doc])
+	}
+
+	// Documents in the insertion order
+	fn find(&self, collection: &str, filter: &Filter) -> Result<Vec<Document>>;
+
+	fn find_one(&self, collection: &str, filter: &Filter) -> Result<Option<Document>>;
+
+	// Replaces the first document matching each filter or inserts a new one,
+	// all or none of them are saved
+	fn upsert_many(&self, collection: &str, docs: Vec<(Filter, Document)>) -> Result<()>;
+
+	fn upsert(&self, collection: &str, filter: &Filter, doc: Document) -> Result<()> {
+		self.upsert_many(collection, vec![(filter.clone(), doc)])
+	}
+
+	// Returns how many documents were deleted
+	fn delete(&self, collection: &str, filter: &Filter) -> Result<usize>;
+
+	fn count(&self, collection: &str) -> Result<usize>;
+
+	fn drop_collection(&self, collection: &str) -> Result<()>;
+
+	// Non-empty collections, sorted by name. The system ones starting with `_` are skipped,
+	// `_log` alone can hold millions of documents
+	fn collections(&self) -> Result<Vec<CollectionInfo>>;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CollectionInfo {
+	pub name: String,
+	pub records: usize,
+}
+
+// Conditions on top level fields, all of them must match
+#[derive(Debug, Clone, Default)]
+pub struct Filter<'a> {
+	conditions: Vec<Condition<'a>>,
+}
+
+#[derive(Debug, Clone)]
+struct Condition<'a> {
+	field: &'a str,
+	value: Bson,
+	op: Op,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+	Eq,
+	EqIgnoreCase,
+	Lt,
+	Ge,
+	Contains,
+}
+
+impl<'a> Filter<'a> {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn eq(self, field: &'a str, value: impl Into<Bson>) -> Self {
+		self.with(field, value.into(), Op::Eq)
+	}
+
+	pub fn eq_ignore_case(self, field: &'a str, value: &str) -> Self {
+		self.with(field, value.into(), Op::EqIgnoreCase)
+	}
+
+	// Numbers or strings that are less than the value
+	pub fn lt(self, field: &'a str, value: impl Into<Bson>) -> Self {
+		self.with(field, value.into(), Op::Lt)
+	}
+
+	// Numbers or strings that are greater than or equal to the value
+	pub fn ge(self, field: &'a str, value: impl Into<Bson>) -> Self {
+		self.with(field, value.into(), Op::Ge)
+	}
+
+	// Arrays with the value
+	pub fn contains(self, field: &'a str, value: impl Into<Bson>) -> Self {
+		self.with(field, value.into(), Op::Contains)
+	}
+
+	fn with(mut self, field: &'a str, value: Bson, op: Op) -> Self {
+		self.conditions.push(Condition { field, value, op });
+		self
+	}
+
+	fn matches(&self, doc: &Document) -> bool {
+		self.conditions.iter().all(|cond| {
+			let Some(value) = doc.get(cond.field) else {
+				return false;
+			};
+			match (value, &cond.value, cond.op) {
+				(Bson::String(a), Bson::String(b), Op::EqIgnoreCase) => {
+					a.to_lowercase() == b.to_lowercase()
+				}
+				(Bson::String(a), Bson::String(b), Op::Lt) => a < b,
+				(a, b, Op::Lt) => match (as_number(a), as_number(b)) {
+					(Some(a), Some(b)) => a < b,
+					_ => false,
+				},
+				(Bson::String(a), Bson::String(b), Op::Ge) => a >= b,
+				(a, b, Op::Ge) => match (as_number(a), as_number(b)) {
+					(Some(a), Some(b)) => a >= b,
+					_ => false,
+				},
+				(Bson::Array(values), b, Op::Contains) => values.contains(b),
+				(_, _, Op::Contains) => false,
+				(a, b, _) => match (as_number(a), as_number(b)) {
+					(Some(a), Some(b)) => a == b,
+					_ => a == b,
+				},
+			}
+		})
+	}
+}
+
+// Plain JSON, unlike `Bson::try_from` it keeps the integers 64-bit like `doc!` does
+// and leaves the "$"-prefixed keys of the extended JSON as they are
+pub fn json_to_bson(json: JsonValue) -> Bson {
+	match json {
+		JsonValue::Null => Bson::Null,
+		JsonValue::Bool(b) => Bson::Boolean(b),
+		JsonValue::Number(n) => match n.as_i64() {
+			Some(n) => Bson::Int64(n),
+			None => Bson::Double(n.as_f64().unwrap_or_default()),
+		},
+		JsonValue::String(s) => Bson::String(s),
+		JsonValue::Array(values) => Bson::Array(values.into_iter().map(json_to_bson).collect()),
+		JsonValue::Object(map) => Bson::Document(
+			map.into_iter()
+				.map(|(key, value)| (key, json_to_bson(value)))
+				.collect(),
+		),
+	}
+}
+
+fn as_number(value: &Bson) -> Option<f64> {
+	match value {
+		Bson::Int32(n) => Some(*n as f64),
+		Bson::Int64(n) => Some(*n as f64),
+		Bson::Double(n) => Some(*n),
+		_ => None,
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use bson::{doc, Bson};
+
+	use super::{CollectionInfo, Filter, MemoryStorage, SqliteStorage, Storage};
+
+	// Both backends must behave the same, `DndDatabase` tests only run against the in-memory one
+	fn check_storage(storage: &dyn Storage) {
+		storage
+			.insert_many(
+				"spell",
+				vec![
+					doc! {"name": "Fireball", "name_source": "Fireball (PHB)", "level": 3i64, "tags": ["fire"]},
+					doc! {"name": "Shield", "name_source": "Shield (PHB)", "level": 1i64},
+					doc! {"name": "Щит", "name_source": "Щит (PHB)", "level": 1i64},
+				],
+			)
+			.unwrap();
+		storage
+			.insert("_settings", doc! {"chat_id": 42i64, "lang": "en"})
+			.unwrap();
+
+		let fireball = storage
+			.find_one(
+				"spell",
+				&Filter::new().eq_ignore_case("name_source", "fireball (phb)"),
+			)
+			.unwrap()
+			.unwrap();
+		assert_eq!(fireball.get_i64("level").unwrap(), 3);
+		assert!(storage
+			.find_one("spell", &Filter::new().eq("name_source", "fireball (phb)"))
+			.unwrap()
+			.is_none());
+		assert_eq!(
+			storage
+				.find("spell", &Filter::new())
+				.unwrap()
+				.iter()
+				.map(|doc| doc.get_str("name").unwrap())
+				.collect::<Vec<_>>(),
+			vec!["Fireball", "Shield", "Щит"]
+		);
+		// SQLite's own `NOCASE` would miss this one
+		let shield = storage
+			.find_one(
+				"spell",
+				&Filter::new().eq_ignore_case("name_source", "щИТ (phb)"),
+			)
+			.unwrap()
+			.unwrap();
+		assert_eq!(shield.get_str("name").unwrap(), "Щит");
+
+		let filter = Filter::new().eq("chat_id", 42i64);
+		storage
+			.upsert("_settings", &filter, doc! {"chat_id": 42i64, "lang": "ru"})
+			.unwrap();
+		storage
+			.upsert(
+				"_settings",
+				&Filter::new().eq("chat_id", 7i64),
+				doc! {"chat_id": 7i64},
+			)
+			.unwrap();
+		let settings = storage.find_one("_settings", &filter).unwrap().unwrap();
+		assert_eq!(settings.get_str("lang").unwrap(), "ru");
+		storage
+			.upsert_many(
+				"_active",
+				vec![
+					(
+						Filter::new().eq("name", "spell"),
+						doc! {"name": "spell", "stored_as": "spell_1"},
+					),
+					(
+						Filter::new().eq("name", "item"),
+						doc! {"name": "item", "stored_as": "item_1"},
+					),
+				],
+			)
+			.unwrap();
+		storage
+			.upsert_many(
+				"_active",
+				vec![(
+					Filter::new().eq("name", "spell"),
+					doc! {"name": "spell", "stored_as": "spell_2"},
+				)],
+			)
+			.unwrap();
+		assert_eq!(
+			storage
+				.find("_active", &Filter::new())
+				.unwrap()
+				.iter()
+				.map(|doc| doc.get_str("stored_as").unwrap())
+				.collect::<Vec<_>>(),
+			vec!["spell_2", "item_1"]
+		);
+		assert_eq!(
+			settings.get("chat_id"),
+			Some(&Bson::Int64(42)),
+			"documents should survive a round trip"
+		);
+
+		assert_eq!(
+			storage.collections().unwrap(),
+			vec![CollectionInfo {
+				name: "spell".to_owned(),
+				records: 3
+			}]
+		);
+		let fire = Filter::new().contains("tags", "fire").ge("level", 3i64);
+		assert_eq!(storage.find("spell", &fire).unwrap().len(), 1);
+		assert!(storage
+			.find("spell", &Filter::new().contains("tags", "cold"))
+			.unwrap()
+			.is_empty());
+		assert!(storage
+			.find("spell", &Filter::new().contains("name", "Shield"))
+			.unwrap()
+			.is_empty());
+		assert_eq!(
+			storage
+				.delete("spell", &Filter::new().lt("level", 2i64))
+				.unwrap(),
+			2
+		);
+		assert_eq!(storage.count("spell").unwrap(), 1);
+		storage.drop_collection("spell").unwrap();
+		assert_eq!(storage.count("spell").unwrap(), 0);
+		assert_eq!(storage.count("_settings").unwrap(), 2);
+	}
+
+	#[test]
+	fn test_memory_storage() {
+		check_storage(&MemoryStorage::default());
+	}
+
+	#[test]
+	fn test_sqlite_storage() {
+		check_storage(&SqliteStorage::open_in_memory().unwrap());
+	}
+}
@@ -0,0 +1,248 @@
+use std::sync::Mutex;
+
+use bson::{Bson, Document};
+use rusqlite::{
+	functions::FunctionFlags,
+	params, params_from_iter,
+	types::{Value, ValueRef},
+	Connection, OptionalExtension,
+};
+use serde_json::Value as JsonValue;
+
+use super::{json_to_bson, CollectionInfo, Filter, Op, Result, Storage, StorageError};
+
+// Documents are kept as JSON, so the filters can use `json_extract`.
+// Expression indexes cover item lookups, the per-chat and per-user system collections
+// and the log retention purge. `NOCASE` only folds ASCII, so the names are compared with `LOWER_FN`
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS documents (
+	id INTEGER PRIMARY KEY,
+	collection TEXT NOT NULL,
+	doc TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS documents_collection ON documents (collection);
+DROP INDEX IF EXISTS documents_name_source;
+CREATE INDEX IF NOT EXISTS documents_name_source_lower
+	ON documents (collection, unicode_lower(json_extract(doc, '$.name_source')));
+CREATE INDEX IF NOT EXISTS documents_chat_id
+	ON documents (collection, json_extract(doc, '$.chat_id'));
+CREATE INDEX IF NOT EXISTS documents_user_id
+	ON documents (collection, json_extract(doc, '$.user_id'));
+CREATE INDEX IF NOT EXISTS documents_timestamp
+	ON documents (collection, json_extract(doc, '$.timestamp'));
+CREATE INDEX IF NOT EXISTS documents_last_seen
+	ON documents (collection, json_extract(doc, '$.last_seen'));
+";
+
+// Same as `str::to_lowercase`, which `MemoryStorage` uses
+const LOWER_FN: &str = "unicode_lower";
+
+pub struct SqliteStorage {
+	// `Connection` is not `Sync`, every query takes the lock
+	conn: Mutex<Connection>,
+}
+
+impl SqliteStorage {
+	pub fn open(path: &str) -> Result<Self> {
+		Self::init(Connection::open(path)?)
+	}
+
+	pub fn open_in_memory() -> Result<Self> {
+		Self::init(Connection::open_in_memory()?)
+	}
+
+	fn init(conn: Connection) -> Result<Self> {
+		// Deterministic, so it can be used by the index
+		conn.create_scalar_function(
+			LOWER_FN,
+			1,
+			FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+			|ctx| {
+				Ok(match ctx.get_raw(0) {
+					ValueRef::Text(text) => Some(String::from_utf8_lossy(text).to_lowercase()),
+					_ => None,
+				})
+			},
+		)?;
+		conn.execute_batch(SCHEMA)?;
+		Ok(Self {
+			conn: Mutex::new(conn),
+		})
+	}
+
+	fn select(&self, collection: &str, filter: &Filter, limit: &str) -> Result<Vec<Document>> {
+		let (conditions, params) = to_sql(collection, filter)?;
+		let conn = self.conn.lock().unwrap();
+		let mut stmt = conn.prepare_cached(&format!(
+			"SELECT doc FROM documents WHERE {conditions} ORDER BY id{limit}"
+		))?;
+		let texts = stmt
+			.query_map(params_from_iter(params), |row| row.get::<_, String>(0))?
+			.collect::<rusqlite::Result<Vec<_>>>()?;
+		texts.into_iter().map(|text| from_json(&text)).collect()
+	}
+}
+
+impl Storage for SqliteStorage {
+	fn insert_many(&self, collection: &str, docs: Vec<Document>) -> Result<()> {
+		let mut conn = self.conn.lock().unwrap();
+		let tx = conn.transaction()?;
+		{
+			let mut stmt =
+				tx.prepare_cached("INSERT INTO documents (collection, doc) VALUES (?, ?)")?;
+			for doc in docs {
+				stmt.execute(params![collection, to_json(doc)?])?;
+			}
+		}
+		tx.commit()?;
+		Ok(())
+	}
+
+	fn find(&self, collection: &str, filter: &Filter) -> Result<Vec<Document>> {
+		self.select(collection, filter, "")
+	}
+
+	fn find_one(&self, collection: &str, filter: &Filter) -> Result<Option<Document>> {
+		Ok(self
+			.select(collection, filter, " LIMIT 1")?
+			.into_iter()
+			.next())
+	}
+
+	fn upsert_many(&self, collection: &str, docs: Vec<(Filter, Document)>) -> Result<()> {
+		let mut conn = self.conn.lock().unwrap();
+		let tx = conn.transaction()?;
+		for (filter, doc) in docs {
+			let (conditions, params) = to_sql(collection, &filter)?;
+			let doc = to_json(doc)?;
+			let id = tx
+				.query_row(
+					&format!("SELECT id FROM documents WHERE {conditions} ORDER BY id LIMIT 1"),
+					params_from_iter(params),
+					|row| row.get::<_, i64>(0),
+				)
+				.optional()?;
+			match id {
+				Some(id) => tx.execute(
+					"UPDATE documents SET doc = ? WHERE id = ?",
+					params![doc, id],
+				)?,
+				None => tx.execute(
+					"INSERT INTO documents (collection, doc) VALUES (?, ?)",
+					params![collection, doc],
+				)?,
+			};
+		}
+		tx.commit()?;
+		Ok(())
+	}
+
+	fn delete(&self, collection: &str, filter: &Filter) -> Result<usize> {
+		let (conditions, params) = to_sql(collection, filter)?;
+		let conn = self.conn.lock().unwrap();
+		let deleted = conn.execute(
+			&format!("DELETE FROM documents WHERE {conditions}"),
+			params_from_iter(params),
+		)?;
+		Ok(deleted)
+	}
+
+	fn count(&self, collection: &str) -> Result<usize> {
+		let conn = self.conn.lock().unwrap();
+		let count = conn.query_row(
+			"SELECT COUNT(*) FROM documents WHERE collection = ?",
+			params![collection],
+			|row| row.get::<_, i64>(0),
+		)?;
+		Ok(count as usize)
+	}
+
+	fn drop_collection(&self, collection: &str) -> Result<()> {
+		let conn = self.conn.lock().unwrap();
+		conn.execute(
+			"DELETE FROM documents WHERE collection = ?",
+			params![collection],
+		)?;
+		Ok(())
+	}
+
+	fn collections(&self) -> Result<Vec<CollectionInfo>> {
+		let conn = self.conn.lock().unwrap();
+		let mut stmt = conn.prepare(
+			// Names starting with `_` sort right before '`', the ranges keep the index usable
+			"SELECT collection, COUNT(*) FROM documents
+			WHERE collection < '_' OR collection >= '`'
+			GROUP BY collection ORDER BY collection",
+		)?;
+		let collections = stmt
+			.query_map([], |row| {
+				Ok(CollectionInfo {
+					name: row.get(0)?,
+					records: row.get::<_, i64>(1)? as usize,
+				})
+			})?
+			.collect::<rusqlite::Result<Vec<_>>>()?;
+		Ok(collections)
+	}
+}
+
+// `WHERE` conditions and their parameters, the collection always goes first
+fn to_sql(collection: &str, filter: &Filter) -> Result<(String, Vec<Value>)> {
+	let mut conditions = "collection = ?".to_owned();
+	let mut params = vec![Value::Text(collection.to_owned())];
+	for cond in &filter.conditions {
+		// Field names are a part of the query text, so the expression indexes can be used
+		if !cond
+			.field
+			.chars()
+			.all(|c| c.is_ascii_alphanumeric() || c == '_')
+		{
+			return Err(StorageError::Unsupported(format!(
+				"field name {}",
+				cond.field
+			)));
+		}
+		let field = cond.field;
+		conditions.push_str(&match cond.op {
+			Op::Eq => format!(" AND json_extract(doc, '$.{field}') = ?"),
+			Op::EqIgnoreCase => format!(" AND {LOWER_FN}(json_extract(doc, '$.{field}')) = ?"),
+			Op::Lt => format!(" AND json_extract(doc, '$.{field}') < ?"),
+			Op::Ge => format!(" AND json_extract(doc, '$.{field}') >= ?"),
+			Op::Contains => format!(
+				" AND json_type(doc, '$.{field}') = 'array'
+				AND EXISTS (SELECT 1 FROM json_each(doc, '$.{field}') WHERE value = ?)"
+			),
+		});
+		params.push(match &cond.value {
+			Bson::String(s) if cond.op == Op::EqIgnoreCase => Value::Text(s.to_lowercase()),
+			Bson::String(s) => Value::Text(s.clone()),
+			Bson::Int32(n) => Value::Integer(*n as i64),
+			Bson::Int64(n) => Value::Integer(*n),
+			// `json_extract` returns booleans as integers
+			Bson::Boolean(b) => Value::Integer(*b as i64),
+			Bson::Double(f) => Value::Real(*f),
+			value => {
+				return Err(StorageError::Unsupported(format!(
+					"{} = {value}",
+					cond.field
+				)))
+			}
+		});
+	}
+	Ok((conditions, params))
+}
+
+fn to_json(doc: Document) -> Result<String> {
+	let json: JsonValue = Bson::Document(doc).into();
+	Ok(serde_json::to_string(&json)?)
+}
+
+fn from_json(text: &str) -> Result<Document> {
+	let json: JsonValue = serde_json::from_str(text)?;
+	match json_to_bson(json) {
+		Bson::Document(doc) => Ok(doc),
+		value => Err(StorageError::Corrupted(format!(
+			"{value} is not a document"
+		))),
+	}
+}
@@ -1,41 +1,73 @@
 use std::{borrow::Cow, env, time::Instant, vec};
 
-use ejdb::bson_crate::{ordered::OrderedDocument, Bson};
+use bson::{Bson, Document};
 use inflector::Inflector;
 use itertools::Itertools;
+use rand::seq::SliceRandom;
 use regex::{Captures, Regex};
 use reqwest::Url;
+use serde_json::Value as JsonValue;
 use thiserror::Error;
 
 use teloxide::{
 	adaptors::{throttle::Limits, CacheMe, Throttle},
+	net::Download,
 	prelude::*,
 	types::{
-		ForceReply, InlineKeyboardButton, InlineKeyboardMarkup, MessageId, MessageKind, ParseMode,
-		ReplyMarkup, Update,
+		BotCommand, Chat, ChatId, ForceReply, InlineKeyboardButton, InlineKeyboardMarkup,
+		InputFile, MessageId, MessageKind, ParseMode, ReplyMarkup, Update, User,
 	},
 	utils::command::{BotCommands, ParseError},
-	RequestError,
+	ApiError, DownloadError, RequestError,
 };
 
 use crate::{
-	collection::{Collection, COMMANDS},
-	commands::{HelpOptions, RollBotCommands},
+	collection::{Collection, COLLECTIONS, COMMANDS},
+	commands::{
+		AdminCommand, CharacterCommand, CombatOptions, CondOptions, HelpOptions, HpChange,
+		HpOptions, RandomOptions, RollBotCommands, SettingsOptions, SlotsOptions,
+	},
+	db::HOMEBREW_COLLECTION_NAME,
 	format::{
 		self,
-		db::{format_collection_metadata, format_message_stats},
+		character::Character,
+		db::{format_ago, format_collection_metadata, format_message_stats, format_update_status},
 		item::Item,
+		locale::{command_description, tr, Lang, Msg, LANGUAGES},
+		loot::generate_loot,
 		monster::Monster,
-		roll::{roll_results, DieFormatError},
-		spell::Spell,
+		resources::{
+			format_resource, format_resources, rest, set_resource, set_spell_slots, spend, Rest,
+		},
+		roll::{roll_attack, roll_dice_with_context, roll_dice_with_die, DieFormatError},
+		rule::Rule,
+		settings::Settings,
+		spell::{split_spell_level, Spell},
+		table::Tables,
 		telegram::chat_type_to_string,
+		tracker::{
+			escape_name, format_board, format_combatant, format_tracker, spawn_monsters, Combatant,
+		},
 		utils::HtmlEscapable,
+		Entry,
 	},
-	DB, DONATION_URL, PROJECT_URL,
+	get_unix_time, privacy, search,
+	stats::{DAY, MONTH},
+	storage::{json_to_bson, StorageError},
+	update, DB, DONATION_URL, PROJECT_URL,
 };
 
 type RollBot = Throttle<CacheMe<Bot>>;
 
+// Telegram rejects buttons with callback data longer than this
+const CALLBACK_DATA_LIMIT: usize = 64;
+// Telegram rejects bigger inline keyboards, the rest of the links and dice are left as text
+const BUTTONS_LIMIT: usize = 100;
+// Hits shown by `/find`, the message gets too long with more snippets
+const FIND_LIMIT: usize = 10;
+// Character exports are small, anything bigger is not a character
+const CHARACTER_FILE_LIMIT: u32 = 1024 * 1024;
+
 pub async fn start() {
 	let token = env::var("ROLL_BOT_TOKEN").unwrap_or_else(|_err| {
 		error!("You must provide <code>ROLL_BOT_TOKEN</code> environment variable!");
@@ -48,6 +80,26 @@ pub async fn start() {
 		.await
 		.log_on_error()
 		.await;
+	// Users with other Telegram languages see translated command descriptions
+	for lang in LANGUAGES
+		.into_iter()
+		.filter(|lang| *lang != Lang::default())
+	{
+		let commands = RollBotCommands::bot_commands()
+			.into_iter()
+			.map(|cmd| {
+				let description = command_description(lang, &cmd.command)
+					.map(str::to_owned)
+					.unwrap_or(cmd.description);
+				BotCommand::new(cmd.command, description)
+			})
+			.collect::<Vec<_>>();
+		bot.set_my_commands(commands)
+			.language_code(lang.code())
+			.await
+			.log_on_error()
+			.await;
+	}
 
 	let handler = dptree::entry()
 		.branch(
@@ -62,6 +114,15 @@ pub async fn start() {
 						msg.text().is_some() && msg.reply_to_message().is_some()
 					})
 					.endpoint(process_message),
+				)
+				.branch(
+					dptree::filter(|msg: Message| {
+						msg.document()
+							.and_then(|doc| doc.file_name.as_ref())
+							.map(|name| name.to_lowercase().ends_with(".json"))
+							.unwrap_or(false) && asks_for_import(&msg)
+					})
+					.endpoint(process_document),
 				),
 		)
 		.branch(Update::filter_callback_query().endpoint(process_callback_query));
@@ -79,7 +140,7 @@ pub enum BotError {
 	Request(#[from] RequestError),
 
 	#[error("Database Error {0}")]
-	Db(#[from] ejdb::Error),
+	Db(#[from] StorageError),
 
 	#[error("Die Format Error {0}")]
 	DieFormat(#[from] DieFormatError),
@@ -98,6 +159,9 @@ pub enum BotError {
 
 	#[error("Bad callback")]
 	BadCallback,
+
+	#[error("Download Error {0}")]
+	Download(#[from] DownloadError),
 }
 
 async fn process_message(msg: Message, bot: RollBot) -> Result<(), BotError> {
@@ -114,6 +178,74 @@ async fn process_message(msg: Message, bot: RollBot) -> Result<(), BotError> {
 	Ok(())
 }
 
+// Groups share JSON files for other reasons too, there the caption has to ask for the import:
+// "/character" or anything with "import"
+fn asks_for_import(msg: &Message) -> bool {
+	msg.chat.is_private()
+		|| msg.caption().is_some_and(|caption| {
+			let caption = caption.to_lowercase();
+			caption.starts_with("/char") || caption.contains("import")
+		})
+}
+
+// Character import, see `Character::import` for the supported formats
+async fn process_document(msg: Message, bot: RollBot) -> Result<(), BotError> {
+	let (Some(document), Some(user)) = (msg.document(), msg.from()) else {
+		return Ok(());
+	};
+	trace!(
+		"Got document from @{}: {:?}",
+		user.username.as_ref().unwrap_or(&user.first_name),
+		document.file_name
+	);
+
+	let character = if document.file.size > CHARACTER_FILE_LIMIT {
+		None
+	} else {
+		let file = bot.get_file(document.file.id.clone()).await?;
+		let mut content = Vec::new();
+		bot.inner()
+			.inner()
+			.download_file(&file.path, &mut content)
+			.await?;
+		serde_json::from_slice::<JsonValue>(&content)
+			.ok()
+			.and_then(|json| match json_to_bson(json) {
+				Bson::Document(doc) => Character::import(&doc),
+				_ => None,
+			})
+	};
+
+	let lang = get_lang(&msg);
+	let text = match character {
+		Some(character) => {
+			DB.save_character(user.id.0 as i64, &character)?;
+			let attack = character
+				.attacks
+				.first()
+				.map(|attack| attack.name.to_lowercase().escape_html())
+				.unwrap_or_else(|| "longsword".to_owned());
+			format!(
+				"{}\n\n{}",
+				character.format_character(),
+				tr(lang, Msg::CharacterImported(attack))
+			)
+		}
+		None => tr(lang, Msg::NoCharacterInFile),
+	};
+
+	let mut m = bot
+		.send_message(msg.chat.id, text)
+		.reply_to_message_id(msg.id)
+		.parse_mode(ParseMode::Html)
+		.disable_web_page_preview(true);
+	if let Some(thread_id) = msg.thread_id {
+		m = m.message_thread_id(thread_id);
+	}
+	m.await?;
+	Ok(())
+}
+
 fn extract_search_data_from_reply(msg: &Message) -> Option<(&'static Collection, &str)> {
 	let item_name = msg.text()?;
 	let reply = msg.reply_to_message()?;
@@ -128,11 +260,65 @@ fn extract_search_data_from_reply(msg: &Message) -> Option<(&'static Collection,
 	Some((collection, item_name))
 }
 
+fn get_lang(msg: &Message) -> Lang {
+	get_user_lang(msg.chat.id, msg.from())
+}
+
+// The chat language wins over the Telegram language of the user
+fn get_user_lang(chat_id: ChatId, user: Option<&User>) -> Lang {
+	DB.get_settings(chat_id.0)
+		.ok()
+		.and_then(|settings| settings.lang)
+		.or_else(|| {
+			user.and_then(|user| user.language_code.as_deref())
+				.and_then(Lang::from_language_code)
+		})
+		.unwrap_or_default()
+}
+
+// The reroll button of a roll reply
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum RerollButton {
+	// Rerolls the command the reply answers
+	Reply,
+	// Rolls started by a button are rerolled with the same callback data
+	Callback(String),
+}
+
+impl RerollButton {
+	fn keyboard(&self, lang: Lang) -> InlineKeyboardMarkup {
+		let data = match self {
+			RerollButton::Reply => "reroll",
+			RerollButton::Callback(data) => data.as_str(),
+		};
+		InlineKeyboardMarkup::new(vec![vec![InlineKeyboardButton::callback(
+			tr(lang, Msg::Reroll),
+			data,
+		)]])
+	}
+}
+
 async fn process_command(msg: Message, bot: RollBot, cmd: RollBotCommands) -> Result<(), BotError> {
+	run_command(msg, bot, cmd, RerollButton::Reply).await
+}
+
+async fn run_command(
+	msg: Message,
+	bot: RollBot,
+	cmd: RollBotCommands,
+	reroll: RerollButton,
+) -> Result<(), BotError> {
 	let start_processing = Instant::now();
 	let chat_id = msg.chat.id;
 	let chat_kind = msg.chat.kind.clone();
 	let msg_text = msg.text().unwrap_or_default().to_string();
+	// Private chats have the same id as the user, group messages are logged for the sender
+	let user_id = msg.from().map_or(chat_id.0, |user| user.id.0 as i64);
+	// Otherwise the request to forget would be the first thing remembered
+	let is_forget_me = match &cmd {
+		RollBotCommands::Multi(commands) => commands.contains(&RollBotCommands::ForgetMe),
+		cmd => *cmd == RollBotCommands::ForgetMe,
+	};
 
 	if msg.via_bot != bot.get_me().await.ok().map(|bot| bot.user) {
 		trace!(
@@ -145,21 +331,74 @@ async fn process_command(msg: Message, bot: RollBot, cmd: RollBotCommands) -> Re
 	}
 
 	let response = match cmd {
+		RollBotCommands::Multi(commands) => execute_commands(msg, bot, commands, &reroll).await,
+		cmd => execute_command(msg, bot, cmd, &reroll).await,
+	}
+	.map(|r| r.text().map(|s| s.to_owned()));
+
+	if !is_forget_me {
+		DB.log_message(
+			user_id,
+			chat_type_to_string(&chat_kind),
+			msg_text,
+			&response,
+			Instant::now()
+				.checked_duration_since(start_processing)
+				.unwrap()
+				.as_millis() as u64,
+		);
+	}
+
+	if let Err(err) = response {
+		error!("Error when sending the message: {err}");
+	}
+
+	Ok(())
+}
+
+// Rolls are combined into a single reply, other commands are answered one by one
+async fn execute_commands(
+	msg: Message,
+	bot: RollBot,
+	commands: Vec<RollBotCommands>,
+	reroll: &RerollButton,
+) -> Result<Message, BotError> {
+	let mut rolls = Vec::new();
+	let mut last_reply = None;
+	for cmd in commands {
+		match cmd {
+			RollBotCommands::Roll(roll) => rolls.push(roll),
+			cmd => {
+				if !rolls.is_empty() {
+					let roll = RollBotCommands::Roll(rolls.join("\n"));
+					rolls.clear();
+					execute_command(msg.clone(), bot.clone(), roll, reroll).await?;
+				}
+				last_reply = Some(execute_command(msg.clone(), bot.clone(), cmd, reroll).await?);
+			}
+		}
+	}
+	if !rolls.is_empty() {
+		let roll = RollBotCommands::Roll(rolls.join("\n"));
+		last_reply = Some(execute_command(msg.clone(), bot, roll, reroll).await?);
+	}
+	last_reply.ok_or_else(|| BotError::NoReplyText(msg.text().unwrap_or_default().to_owned()))
+}
+
+async fn execute_command(
+	msg: Message,
+	bot: RollBot,
+	cmd: RollBotCommands,
+	reroll: &RerollButton,
+) -> Result<Message, BotError> {
+	let lang = get_lang(&msg);
+	match cmd {
 		RollBotCommands::Help(opts) => print_help(msg, bot, opts).await,
 		RollBotCommands::Roll(roll) => {
-			let reply_markup = msg.reply_markup().cloned().unwrap_or_else(|| {
-				InlineKeyboardMarkup::new(vec![vec![InlineKeyboardButton::callback(
-					"Reroll", "reroll",
-				)]])
-			});
+			let reply_markup = reroll.keyboard(lang);
 
 			let reply_id = msg.id;
-
-			let roll = format!(
-				"<b>{} rolls:</b>\n{}",
-				msg.from().unwrap().first_name.escape_html(),
-				roll
-			);
+			let roll = roll_text(&msg, &roll)?;
 
 			split_and_send(
 				msg,
@@ -180,39 +419,81 @@ async fn process_command(msg: Message, bot: RollBot, cmd: RollBotCommands) -> Re
 			}
 			m.await.map_err(BotError::Request)
 		}
+		RollBotCommands::Loot(opts) => {
+			let Some(loot) = generate_loot(opts.cr, opts.kind) else {
+				let text = tr(lang, Msg::LootUsage);
+				let reply_id = msg.id;
+				return split_and_send(msg, bot, &text, None, Some(reply_id)).await;
+			};
+			let mut loot = format!(
+				"{}\n{}",
+				tr(
+					lang,
+					Msg::Finds(
+						msg.from()
+							.map(|user| user.first_name.escape_html())
+							.unwrap_or_default()
+					)
+				),
+				loot
+			);
+
+			let mut keyboard = InlineKeyboardMarkup::default();
+			replace_string_links(&mut loot, None, lang, &mut keyboard);
+			keyboard.inline_keyboard = keyboard.inline_keyboard.into_iter().unique().collect();
+			keyboard = keyboard.append_row(vec![InlineKeyboardButton::callback(
+				tr(lang, Msg::Reroll),
+				"reroll",
+			)]);
+
+			let reply_id = msg.id;
+			split_and_send(
+				msg,
+				bot,
+				&loot,
+				Some(ReplyMarkup::InlineKeyboard(keyboard)),
+				Some(reply_id),
+			)
+			.await
+		}
 		RollBotCommands::Query((collection, item)) => {
 			search_item(msg, bot, collection, &item).await
 		}
-		RollBotCommands::Echo(err) | RollBotCommands::Error(err) => {
-			let mut m = bot
-				.send_message(msg.chat.id, err)
-				.reply_to_message_id(msg.id)
-				.parse_mode(ParseMode::Html)
-				.disable_web_page_preview(true);
-			if let Some(thread_id) = msg.thread_id {
-				m = m.message_thread_id(thread_id);
-			}
-			m.await.map_err(BotError::Request)
+		RollBotCommands::Find(query) => find_text(msg, bot, &query).await,
+		RollBotCommands::Random(opts) => random_item(msg, bot, opts).await,
+		RollBotCommands::Spawn((count, name)) => spawn(msg, bot, count, &name).await,
+		RollBotCommands::Character(cmd) => character_roll(msg, bot, cmd).await,
+		RollBotCommands::Slots(opts) => update_slots(msg, bot, opts).await,
+		RollBotCommands::Rest(opts) => take_rest(msg, bot, opts).await,
+		RollBotCommands::Hp(opts) => update_hp(msg, bot, opts).await,
+		RollBotCommands::Cond(opts) => update_condition(msg, bot, opts).await,
+		RollBotCommands::Combat(opts) => show_combat(msg, bot, opts).await,
+		RollBotCommands::Lang(opts) => set_lang(msg, bot, opts).await,
+		RollBotCommands::Settings(opts) => update_settings(msg, bot, opts).await,
+		RollBotCommands::Admin(cmd) => admin(msg, bot, cmd).await,
+		RollBotCommands::MyData => export_user_data(msg, bot).await,
+		RollBotCommands::ForgetMe => forget_user(msg, bot).await,
+		RollBotCommands::RollTable((collection, index, item)) => {
+			roll_table(msg, bot, collection, index, &item).await
 		}
+		RollBotCommands::Echo(text) => reply_html(msg, bot, text).await,
+		RollBotCommands::Error(err) => reply_html(msg, bot, tr(lang, err)).await,
+		RollBotCommands::Multi(_) => Err(BotError::EntryFormat(
+			"multi: nested commands are not supported".to_owned(),
+		)),
 	}
-	.map(|r| r.text().map(|s| s.to_owned()));
-
-	DB.log_message(
-		chat_id.0,
-		chat_type_to_string(&chat_kind),
-		msg_text,
-		&response,
-		Instant::now()
-			.checked_duration_since(start_processing)
-			.unwrap()
-			.as_millis() as u64,
-	);
+}
 
-	if let Err(err) = response {
-		error!("Error when sending the message: {err}");
+async fn reply_html(msg: Message, bot: RollBot, text: String) -> Result<Message, BotError> {
+	let mut m = bot
+		.send_message(msg.chat.id, text)
+		.reply_to_message_id(msg.id)
+		.parse_mode(ParseMode::Html)
+		.disable_web_page_preview(true);
+	if let Some(thread_id) = msg.thread_id {
+		m = m.message_thread_id(thread_id);
 	}
-
-	Ok(())
+	m.await.map_err(BotError::Request)
 }
 
 async fn process_callback_query(callback_msg: CallbackQuery, bot: RollBot) -> Result<(), BotError> {
@@ -230,10 +511,20 @@ async fn process_callback_query(callback_msg: CallbackQuery, bot: RollBot) -> Re
 		return Err(BotError::BadCallback);
 	};
 
+	// HP tracker buttons edit the tracker instead of sending a new message
+	if let Some(args) = data.strip_prefix("tracker ") {
+		return update_tracker(msg, bot, args).await;
+	}
+	// So do the settings menu buttons
+	if let Some(key) = data.strip_prefix("settings ") {
+		return cycle_setting(msg, bot, &callback_msg.from, key).await;
+	}
+
+	let reroll_edits = data == "reroll" && DB.get_settings(msg.chat.id.0)?.reroll_edits;
+	let (reroll_msg_id, reroll_markup) = (msg.id, msg.reply_markup().cloned());
+	let mut reroll = RerollButton::Reply;
 	// Reroll special message
 	if let MessageKind::Common(ref mut common_msg) = msg.kind {
-		// common_msg.from = Some(callback_msg.from);
-
 		if data == "reroll" {
 			let Some(mut reply) = std::mem::take(&mut common_msg.reply_to_message) else {
 				return Err(BotError::BadCallback);
@@ -243,6 +534,10 @@ async fn process_callback_query(callback_msg: CallbackQuery, bot: RollBot) -> Re
 			}
 			data = reply.text().ok_or(BotError::BadCallback)?.to_owned();
 			msg = *reply;
+		} else {
+			// Whoever pressed the button is the author of the command
+			common_msg.from = Some(callback_msg.from);
+			reroll = RerollButton::Callback(data.clone());
 		}
 	}
 
@@ -260,31 +555,58 @@ async fn process_callback_query(callback_msg: CallbackQuery, bot: RollBot) -> Re
 		.user;
 	let cmd = RollBotCommands::parse(&data, bot_user.username.as_ref().unwrap())?;
 
+	// Rolls can be updated in place, see `/settings`
+	if reroll_edits {
+		let text = match &cmd {
+			RollBotCommands::Roll(roll) => Some(roll_text(&msg, roll)?),
+			RollBotCommands::Character(cmd) => Some(character_roll_text(&msg, cmd)?.0),
+			_ => None,
+		};
+		if let Some(text) = text {
+			let mut m = bot
+				.edit_message_text(msg.chat.id, reroll_msg_id, text)
+				.parse_mode(ParseMode::Html)
+				.disable_web_page_preview(true);
+			if let Some(keyboard) = reroll_markup {
+				m = m.reply_markup(keyboard);
+			}
+			m.await?;
+			return Ok(());
+		}
+	}
+
 	msg.via_bot = Some(bot_user);
-	process_command(msg, bot, cmd).await
+	run_command(msg, bot, cmd, reroll).await
 }
 
 async fn print_help(msg: Message, bot: RollBot, opts: HelpOptions) -> Result<Message, BotError> {
+	let lang = get_lang(&msg);
 	match opts {
 		HelpOptions::None => {
 			let kb = InlineKeyboardMarkup::new(vec![
 				vec![
-					InlineKeyboardButton::url("Source Code", Url::parse(PROJECT_URL).unwrap()),
-					InlineKeyboardButton::url("Buy me a coffee", Url::parse(DONATION_URL).unwrap()),
+					InlineKeyboardButton::url(
+						tr(lang, Msg::SourceCode),
+						Url::parse(PROJECT_URL).unwrap(),
+					),
+					InlineKeyboardButton::url(
+						tr(lang, Msg::BuyMeACoffee),
+						Url::parse(DONATION_URL).unwrap(),
+					),
 				],
 				vec![
 					InlineKeyboardButton::url(
-						"News",
+						tr(lang, Msg::News),
 						Url::parse("https://t.me/roll_bot_news").unwrap(),
 					),
 					InlineKeyboardButton::url(
-						"Chat",
+						tr(lang, Msg::Chat),
 						Url::parse("https://t.me/roll_bot_chat").unwrap(),
 					),
 				],
 			]);
 			let mut m = bot
-				.send_message(msg.chat.id, format::telegram::help_message())
+				.send_message(msg.chat.id, format::telegram::help_message(lang))
 				.parse_mode(ParseMode::Html)
 				.reply_markup(ReplyMarkup::InlineKeyboard(kb))
 				.disable_web_page_preview(true);
@@ -295,7 +617,7 @@ async fn print_help(msg: Message, bot: RollBot, opts: HelpOptions) -> Result<Mes
 		}
 		HelpOptions::Roll => {
 			let mut m = bot
-				.send_message(msg.chat.id, format::telegram::help_roll_message())
+				.send_message(msg.chat.id, format::telegram::help_roll_message(lang))
 				.parse_mode(ParseMode::Html)
 				.disable_web_page_preview(true);
 			if let Some(thread_id) = msg.thread_id {
@@ -312,20 +634,26 @@ fn stats() -> Result<String, BotError> {
 		.unwrap()
 		.as_secs();
 
-	let update_str = match last_update {
-		0..=60 => format!("{last_update}s"),
-		61..=3600 => format!("{}m", last_update / 60),
-		3601..=86400 => format!("{}h", last_update / 60 / 60),
-		86401..=std::u64::MAX => format!("{}d", last_update / 60 / 60 / 24),
-	};
+	let update_str = format_ago(last_update);
 
 	let collection_metadata = DB.get_metadata()?;
-	let messages = DB.get_all_massages()?;
+	let month = DB.get_usage_summary(get_unix_time().saturating_sub(MONTH));
+	let total = DB.get_usage_summary(0);
+	// Users are forgotten with their expired log entries, see `DndDatabase::purge_log`
+	let retention_note = privacy::log_retention()
+		.map(|retention| {
+			format!(
+				"\nThe total only has the users seen in the last {} days",
+				retention / DAY
+			)
+		})
+		.unwrap_or_default();
 
 	let msg = format!(
-        "<b>Table stats</b>\n{}\n\n<b>Usage stats</b> (since last month / total)\n{}\n\nLast database update <code>{}</code> ago",
-        format_collection_metadata(collection_metadata),
-        format_message_stats(messages)?,
+        "<b>Table stats</b>\n{}\n\n<b>Usage stats</b> (since last month / total)\n{}{}\n\nLast database update <code>{}</code> ago",
+        format_collection_metadata(&collection_metadata),
+        format_message_stats(&month, &total),
+        retention_note,
         update_str,
     );
 	Ok(msg)
@@ -337,20 +665,22 @@ async fn search_item(
 	lookup_item: &Collection,
 	arg: &str,
 ) -> Result<Message, BotError> {
+	let lang = get_lang(&msg);
 	if arg.is_empty() {
 		let force_reply = ForceReply::new()
 			.selective(true)
-			.input_field_placeholder(format!(
-				"{} to search",
-				lookup_item.get_default_command().to_title_case()
+			.input_field_placeholder(tr(
+				lang,
+				Msg::SearchPlaceholder(lookup_item.get_default_command().to_title_case()),
 			));
 
+		// The second word must stay the command, see `extract_search_data_from_reply`
 		let mut m = bot
 			.send_message(
 				msg.chat.id,
-				format!(
-					"What {} should I look for? Please, <b>reply</b> to this message with a name:",
-					lookup_item.get_default_command()
+				tr(
+					lang,
+					Msg::SearchPrompt(lookup_item.get_default_command().to_owned()),
 				),
 			)
 			.parse_mode(ParseMode::Html)
@@ -364,37 +694,17 @@ async fn search_item(
 		return m.await.map_err(BotError::Request);
 	}
 
-	let exact_match_result = lookup_item
-		.collections
-		.iter()
-		.filter_map(|collection| {
-			DB.get_item(collection, arg).ok().flatten().or_else(|| {
-				let with_default_source = arg.to_owned() + " (PHB)"; // TODO: Do not hardcode this?
-				DB.get_item(collection, &with_default_source).ok().flatten()
-			})
-		})
-		.next();
+	// "/spell fireball @5" shows the damage for the 5th level slot
+	let (arg, level) = match lookup_item.type_ {
+		crate::collection::CollectionType::Spell => split_spell_level(arg),
+		_ => (arg, None),
+	};
+	let level_suffix = level.map(|level| format!(" @{level}")).unwrap_or_default();
+	let settings = DB.get_settings(msg.chat.id.0)?;
 
-	match exact_match_result {
-		Some(mut item) => {
-			let mut keyboard = InlineKeyboardMarkup::default();
-			replace_links(&mut item, &mut keyboard);
-			// Deduplicate and sort keys
-			keyboard.inline_keyboard = keyboard
-				.inline_keyboard
-				.into_iter()
-				.unique()
-				.sorted_by(|row1, row2| row1[0].text.cmp(&row2[0].text))
-				.collect();
-			let mut reply_msg = match lookup_item.type_ {
-				crate::collection::CollectionType::Item => item.format_item(),
-				crate::collection::CollectionType::Monster => item.format_monster(),
-				crate::collection::CollectionType::Spell => item.format_spell(),
-			}
-			.ok_or_else(|| {
-				BotError::EntryFormat(lookup_item.get_default_command().to_owned() + ": " + arg)
-			})?;
-			replace_string_links(&mut reply_msg, &mut keyboard);
+	match find_exact_item(lookup_item, arg, &settings) {
+		Some(item) => {
+			let (reply_msg, keyboard) = format_found_item(lookup_item, item, level, lang)?;
 			split_and_send(
 				msg,
 				bot,
@@ -410,32 +720,30 @@ async fn search_item(
 				.iter()
 				.cloned()
 				.flat_map(|collection| {
-					let cache = DB.cache.read().unwrap();
-					let engine = cache.get(collection).unwrap();
-					let results = engine.search(arg);
-					results.into_iter().map(|item| {
-						let command = format!("/{} {}", lookup_item.get_default_command(), item);
-						let button = InlineKeyboardButton::callback(item, command);
-						vec![button]
-					})
+					search_names(collection, arg, &settings)
+						.into_iter()
+						.map(|(text, item)| {
+							let command = format!(
+								"/{} {}{level_suffix}",
+								lookup_item.get_default_command(),
+								item
+							);
+							let button = InlineKeyboardButton::callback(text, command);
+							vec![button]
+						})
 				})
 				.collect::<Vec<_>>();
 
 			let mut keyboard = InlineKeyboardMarkup::new(iter);
 
+			let command = lookup_item.get_default_command().to_owned();
 			let mut reply_msg = if keyboard.inline_keyboard.is_empty() {
-				format!(
-					"Can't find any {} with this name, sorry :(",
-					lookup_item.get_default_command()
-				)
+				tr(lang, Msg::NotFound(command))
 			} else {
-				format!(
-					"I've found these {} names:",
-					lookup_item.get_default_command()
-				)
+				tr(lang, Msg::FoundNames(command))
 			};
 
-			replace_string_links(&mut reply_msg, &mut keyboard);
+			replace_string_links(&mut reply_msg, None, lang, &mut keyboard);
 			split_and_send(
 				msg,
 				bot,
@@ -448,32 +756,1009 @@ async fn search_item(
 	}
 }
 
-fn replace_links(doc: &mut OrderedDocument, keyboard: &mut InlineKeyboardMarkup) {
+// Names without a source are looked up in the preferred sources of the chat
+fn find_exact_item(lookup_item: &Collection, arg: &str, settings: &Settings) -> Option<Document> {
+	lookup_item
+		.collections
+		.iter()
+		.filter_map(|collection| {
+			DB.get_item(collection, arg).ok().flatten().or_else(|| {
+				settings.sources.iter().find_map(|source| {
+					let with_source = format!("{arg} ({source})");
+					DB.get_item(collection, &with_source).ok().flatten()
+				})
+			})
+		})
+		.find(|item| !is_hidden_homebrew(item, settings))
+}
+
+// Fuzzy search, returns (button text, name) pairs.
+// Homebrew is hidden unless the chat enabled it, the preferred sources go first
+fn search_names(collection: &str, query: &str, settings: &Settings) -> Vec<(String, String)> {
+	let (homebrew, hidden) = homebrew_sources(settings);
+
+	let cache = DB.cache.read().unwrap();
+	let engine = cache.get(collection).unwrap();
+	let mut results = engine.search(query);
+	results.retain(|item| !has_source(item, &hidden));
+	results.sort_by_key(|item| !has_source(item, &settings.sources));
+	results
+		.into_iter()
+		.map(|item| {
+			let text = if has_source(&item, &homebrew) {
+				format!("🍺 {item}")
+			} else {
+				item.clone()
+			};
+			(text, item)
+		})
+		.collect()
+}
+
+// Full-text search, the best hits with a piece of the text around the match
+async fn find_text(msg: Message, bot: RollBot, query: &str) -> Result<Message, BotError> {
+	let lang = get_lang(&msg);
+	let settings = DB.get_settings(msg.chat.id.0)?;
+	let (homebrew, hidden) = homebrew_sources(&settings);
+	let hits = DB
+		.text_index
+		.read()
+		.unwrap()
+		.search(query)
+		.into_iter()
+		.filter(|hit| !has_source(&hit.name, &hidden))
+		.collect::<Vec<_>>();
+
+	if hits.is_empty() {
+		let reply_msg = tr(lang, Msg::NothingFound);
+		let reply_id = msg.id;
+		return split_and_send(msg, bot, &reply_msg, None, Some(reply_id)).await;
+	}
+
+	let reply_id = msg.id;
+	let mut reply_msg = tr(lang, Msg::EntriesFound(hits.len()));
+	let mut keyboard = InlineKeyboardMarkup::default();
+	for (i, hit) in hits.iter().take(FIND_LIMIT).enumerate() {
+		let Some(lookup_item) = COLLECTIONS
+			.iter()
+			.find(|lookup_item| lookup_item.collections.contains(&hit.collection))
+		else {
+			continue;
+		};
+		let command = lookup_item.get_default_command();
+		reply_msg.push_str(&format!(
+			"\n\n{}. <b>{}</b>, {command}",
+			i + 1,
+			hit.name.escape_html()
+		));
+		let snippet = DB
+			.get_item(hit.collection, &hit.name)
+			.ok()
+			.flatten()
+			.and_then(|item| {
+				DB.text_index
+					.read()
+					.unwrap()
+					.snippet(&search::entry_text(&item), query)
+			});
+		if let Some(snippet) = snippet {
+			reply_msg.push_str(&format!("\n{snippet}"));
+		}
+
+		let data = format!("/{command} {}", hit.name);
+		if data.len() <= CALLBACK_DATA_LIMIT {
+			let text = if has_source(&hit.name, &homebrew) {
+				format!("🍺 {}", hit.name)
+			} else {
+				hit.name.clone()
+			};
+			keyboard
+				.inline_keyboard
+				.push(vec![InlineKeyboardButton::callback(text, data)]);
+		}
+	}
+
+	split_and_send(
+		msg,
+		bot,
+		&reply_msg,
+		Some(ReplyMarkup::InlineKeyboard(keyboard)),
+		Some(reply_id),
+	)
+	.await
+}
+
+// Search results are "Name (SOURCE)"
+fn has_source(name_source: &str, sources: &[String]) -> bool {
+	sources
+		.iter()
+		.any(|source| name_source.ends_with(&format!(" ({source})")))
+}
+
+fn is_hidden_homebrew(item: &Document, settings: &Settings) -> bool {
+	item.get_str("homebrew").is_ok()
+		&& !item
+			.get_str("source")
+			.is_ok_and(|source| settings.homebrew.iter().any(|s| s == source))
+}
+
+// Sources of all homebrew packs and of the ones the chat didn't enable
+fn homebrew_sources(settings: &Settings) -> (Vec<String>, Vec<String>) {
+	let homebrew = homebrew_packs()
+		.into_iter()
+		.map(|(source, _)| source)
+		.collect::<Vec<_>>();
+	let hidden = homebrew
+		.iter()
+		.filter(|source| !settings.homebrew.contains(source))
+		.cloned()
+		.collect::<Vec<_>>();
+	(homebrew, hidden)
+}
+
+// (source, pack name) of the loaded homebrew packs
+fn homebrew_packs() -> Vec<(String, String)> {
+	DB.get_all(HOMEBREW_COLLECTION_NAME)
+		.unwrap_or_default()
+		.iter()
+		.filter_map(|pack| {
+			let source = pack.get_str("source").ok()?;
+			let name = pack.get_str("name").ok()?;
+			Some((source.to_owned(), name.to_owned()))
+		})
+		.collect()
+}
+
+fn format_found_item(
+	lookup_item: &Collection,
+	mut item: Document,
+	level: Option<i64>,
+	lang: Lang,
+) -> Result<(String, InlineKeyboardMarkup), BotError> {
+	let mut keyboard = InlineKeyboardMarkup::default();
+	append_table_buttons(&item, lookup_item, lang, &mut keyboard);
+	// Leveled spells can be cast right away, spending a slot
+	if lookup_item.type_ == crate::collection::CollectionType::Spell {
+		if let Ok(spell_level @ 1..) = item.get_i64("level") {
+			let cast_level = level.unwrap_or(spell_level).clamp(spell_level, 9);
+			keyboard
+				.inline_keyboard
+				.push(vec![InlineKeyboardButton::callback(
+					tr(lang, Msg::CastAtLevel(cast_level)),
+					format!("/slots use {cast_level}"),
+				)]);
+		}
+	}
+	// Should be done before the links are replaced, they hold the scaling data
+	let scaling = level.and_then(|level| {
+		append_scaling_buttons(&item, level, &mut keyboard);
+		item.format_scaling(level)
+	});
+	replace_links(&mut item, None, lang, &mut keyboard);
+	// Deduplicate and sort keys
+	keyboard.inline_keyboard = keyboard
+		.inline_keyboard
+		.into_iter()
+		.unique()
+		.sorted_by(|row1, row2| row1[0].text.cmp(&row2[0].text))
+		.collect();
+	let mut reply_msg = match lookup_item.type_ {
+		crate::collection::CollectionType::Item => item.format_item(),
+		crate::collection::CollectionType::Monster => item.format_monster(),
+		crate::collection::CollectionType::Spell => item.format_spell(),
+		crate::collection::CollectionType::Rule => item.format_rule(),
+	}
+	.ok_or_else(|| {
+		BotError::EntryFormat(
+			lookup_item.get_default_command().to_owned()
+				+ ": " + &item.get_name().unwrap_or_default(),
+		)
+	})?;
+	if let Some(scaling) = scaling {
+		reply_msg = reply_msg + "\n\n" + &scaling;
+	}
+	replace_string_links(&mut reply_msg, None, lang, &mut keyboard);
+	Ok((reply_msg, keyboard))
+}
+
+async fn random_item(msg: Message, bot: RollBot, opts: RandomOptions) -> Result<Message, BotError> {
+	let settings = DB.get_settings(msg.chat.id.0)?;
+	let filter = opts.filter();
+	let candidates = opts
+		.collection
+		.collections
+		.iter()
+		.filter_map(|collection| DB.find_all(collection, &filter).ok())
+		.flatten()
+		.filter(|item| !is_hidden_homebrew(item, &settings))
+		.collect::<Vec<_>>();
+
+	let lang = get_lang(&msg);
+	let Some(item) = candidates.choose(&mut rand::thread_rng()) else {
+		let mut m = bot
+			.send_message(
+				msg.chat.id,
+				tr(
+					lang,
+					Msg::NothingLikeThis(opts.collection.get_default_command().to_owned()),
+				),
+			)
+			.reply_to_message_id(msg.id)
+			.parse_mode(ParseMode::Html)
+			.disable_web_page_preview(true);
+		if let Some(thread_id) = msg.thread_id {
+			m = m.message_thread_id(thread_id);
+		}
+		return m.await.map_err(BotError::Request);
+	};
+
+	let (reply_msg, keyboard) = format_found_item(opts.collection, item.clone(), None, lang)?;
+	// Reroll works only when we reply to the original command
+	let keyboard = keyboard.append_row(vec![InlineKeyboardButton::callback(
+		tr(lang, Msg::Reroll),
+		"reroll",
+	)]);
+	let reply_id = msg.id;
+	split_and_send(
+		msg,
+		bot,
+		&reply_msg,
+		Some(ReplyMarkup::InlineKeyboard(keyboard)),
+		Some(reply_id),
+	)
+	.await
+}
+
+async fn spawn(msg: Message, bot: RollBot, count: usize, name: &str) -> Result<Message, BotError> {
+	let lookup_item = COMMANDS
+		.get("monster")
+		.ok_or_else(|| BotError::EntryFormat("spawn: no monster collection".to_owned()))?;
+
+	let settings = DB.get_settings(msg.chat.id.0)?;
+
+	let Some(monster) = find_exact_item(lookup_item, name, &settings) else {
+		// Same as in `search_item`, but the buttons spawn the monsters
+		let buttons = lookup_item
+			.collections
+			.iter()
+			.flat_map(|collection| {
+				search_names(collection, name, &settings)
+					.into_iter()
+					.map(|(text, item)| {
+						let command = format!("/spawn {count} {item}");
+						vec![InlineKeyboardButton::callback(text, command)]
+					})
+					.collect::<Vec<_>>()
+			})
+			.collect::<Vec<_>>();
+		let command = lookup_item.get_default_command().to_owned();
+		let reply_msg = if buttons.is_empty() {
+			tr(get_lang(&msg), Msg::NotFound(command))
+		} else {
+			tr(get_lang(&msg), Msg::FoundNames(command))
+		};
+		return split_and_send(
+			msg,
+			bot,
+			&reply_msg,
+			Some(ReplyMarkup::InlineKeyboard(InlineKeyboardMarkup::new(
+				buttons,
+			))),
+			None,
+		)
+		.await;
+	};
+
+	let monster_name = monster.get_name().unwrap_or_default();
+	// Summoned creatures have "special" HP that depends on the caster
+	let Some(combatants) = spawn_monsters(&monster, count) else {
+		let text = tr(
+			get_lang(&msg),
+			Msg::SpawnNoHp(format!("<b>{}</b>", monster_name.escape_html())),
+		);
+		let reply_id = msg.id;
+		return split_and_send(msg, bot, &text, None, Some(reply_id)).await;
+	};
+
+	// Spawned monsters join the chat combat, replacing the ones with the same names
+	DB.update_combatants(msg.chat.id.0, |state| {
+		state.retain(|old| !combatants.iter().any(|new| new.matches(&old.name)));
+		state.extend(combatants.iter().cloned());
+	})?;
+	let title = match count {
+		1 => monster_name,
+		_ => format!("{monster_name} 1–{count}"),
+	};
+
+	let keyboard = InlineKeyboardMarkup::new((1..=count).map(|i| {
+		["-5", "-1", "+1", "+5"]
+			.into_iter()
+			.map(|delta| {
+				InlineKeyboardButton::callback(
+					format!("#{i} {delta}"),
+					format!("tracker {i} {delta}"),
+				)
+			})
+			.collect::<Vec<_>>()
+	}));
+
+	let chat_id = msg.chat.id.0;
+	let reply_id = msg.id;
+	let sent = split_and_send(
+		msg,
+		bot,
+		&format_tracker(&title, &combatants),
+		Some(ReplyMarkup::InlineKeyboard(keyboard)),
+		Some(reply_id),
+	)
+	.await?;
+	// The buttons are on the last part, the one that is returned
+	let names = combatants
+		.into_iter()
+		.map(|combatant| combatant.name)
+		.collect::<Vec<_>>();
+	DB.save_tracker(chat_id, sent.id.0, &title, &names)?;
+	Ok(sent)
+}
+
+// Callback data is "tracker <instance number> <HP delta>", the HP comes from the chat combat
+async fn update_tracker(msg: Message, bot: RollBot, args: &str) -> Result<(), BotError> {
+	let (index, delta) = args.split_once(' ').ok_or(BotError::BadCallback)?;
+	let index = index.parse::<usize>().map_err(|_| BotError::BadCallback)?;
+	let delta = delta.parse::<i64>().map_err(|_| BotError::BadCallback)?;
+
+	let (title, names) = DB
+		.get_tracker(msg.chat.id.0, msg.id.0)?
+		.ok_or(BotError::BadCallback)?;
+	let name = index
+		.checked_sub(1)
+		.and_then(|i| names.get(i))
+		.ok_or(BotError::BadCallback)?;
+
+	// Combatants removed by `/combat end` or a later `/spawn` are not shown
+	let combatants = DB.update_combatants(msg.chat.id.0, |state| {
+		if let Some(combatant) = state.iter_mut().find(|c| c.matches(name)) {
+			combatant.apply(delta);
+		}
+		names
+			.iter()
+			.filter_map(|name| state.iter().find(|c| c.matches(name)).cloned())
+			.collect::<Vec<_>>()
+	})?;
+
+	let mut m = bot
+		.edit_message_text(msg.chat.id, msg.id, format_tracker(&title, &combatants))
+		.parse_mode(ParseMode::Html);
+	if let Some(keyboard) = msg.reply_markup() {
+		m = m.reply_markup(keyboard.clone());
+	}
+	match m.await {
+		// E.g. healing at full HP, the message is up to date
+		Err(RequestError::Api(ApiError::MessageNotModified)) => Ok(()),
+		result => result.map(|_| ()).map_err(BotError::Request),
+	}
+}
+
+// "/r" and "/r 2" use the chat default die
+fn roll_text(msg: &Message, roll: &str) -> Result<String, BotError> {
+	let lang = get_lang(msg);
+	let die = DB.get_settings(msg.chat.id.0)?.die;
+	let result = roll
+		.split('\n')
+		.map(|line| roll_dice_with_die(line, die))
+		.collect::<Result<Vec<_>, _>>()?
+		.join("\n");
+	let name = msg
+		.from()
+		.map(|user| user.first_name.escape_html())
+		.unwrap_or_default();
+	// Only the heading is translated, the comments are written by the user
+	Ok(format!("{}\n{result}", tr(lang, Msg::Rolls(name))))
+}
+
+async fn character_roll(
+	msg: Message,
+	bot: RollBot,
+	cmd: CharacterCommand,
+) -> Result<Message, BotError> {
+	let (text, rerollable) = character_roll_text(&msg, &cmd)?;
+	send_character_reply(msg, bot, &text, rerollable).await
+}
+
+// Returns the reply and whether it can be rerolled
+fn character_roll_text(msg: &Message, cmd: &CharacterCommand) -> Result<(String, bool), BotError> {
+	let user = msg
+		.from()
+		.ok_or_else(|| BotError::EntryFormat("character: no user".to_owned()))?;
+	let user_name = user.first_name.escape_html();
+	let lang = get_lang(msg);
+
+	let Some(character) = DB.get_character(user.id.0 as i64)? else {
+		if let CharacterCommand::Roll {
+			fallback: Some(fallback),
+			..
+		} = cmd
+		{
+			return Ok((roll_text(msg, fallback)?, true));
+		}
+		return Ok((tr(lang, Msg::NoCharacter), false));
+	};
+
+	// Names are used as quoted roll comments
+	let comment = |name: &str| name.replace('"', "");
+	// "@stealth"-like modifiers are replaced with the character bonuses
+	let context = character.get_roll_context();
+	let roll = |roll: String| roll_dice_with_context(&roll, &context).map_err(Msg::Roll);
+	let result = match cmd {
+		CharacterCommand::Show => return Ok((character.format_character(), false)),
+		CharacterCommand::Check(name) => character
+			.get_check_bonus(name)
+			.map(|bonus| format!("d20{bonus:+} \"{} check\"", comment(name)))
+			.ok_or(Msg::UnknownSkill)
+			.and_then(roll),
+		CharacterCommand::Save(ability) => character
+			.get_save_bonus(ability)
+			.map(|bonus| format!("d20{bonus:+} \"{} save\"", comment(ability)))
+			.ok_or(Msg::UnknownAbility)
+			.and_then(roll),
+		CharacterCommand::Attack(name) => match character.find_attack(name) {
+			Some(attack) => {
+				let name = comment(&attack.name);
+				let damage_comment = tr(lang, Msg::Damage(name.clone()));
+				let crit = DB.get_settings(msg.chat.id.0)?.crit;
+				roll_attack(
+					&format!("d20{:+} \"{}\"", attack.to_hit, tr(lang, Msg::ToHit(name))),
+					attack
+						.damage
+						.as_deref()
+						.map(|damage| (damage, damage_comment.as_str())),
+					crit,
+				)
+				.map(|(result, critical)| match critical {
+					true => format!("{result}\n{}", tr(lang, Msg::CriticalHit)),
+					false => result,
+				})
+				.map_err(Msg::Roll)
+			}
+			None => Err(Msg::UnknownAttack),
+		},
+		CharacterCommand::Roll {
+			roll: expression, ..
+		} => roll(expression.clone()),
+	};
+
+	Ok(match result {
+		Ok(result) => {
+			let name = format!("{user_name} ({})", character.name.escape_html());
+			(format!("{}\n{result}", tr(lang, Msg::Rolls(name))), true)
+		}
+		Err(err) => (tr(lang, err), false),
+	})
+}
+
+async fn send_character_reply(
+	msg: Message,
+	bot: RollBot,
+	text: &str,
+	rerollable: bool,
+) -> Result<Message, BotError> {
+	let lang = get_lang(&msg);
+	let keyboard = rerollable.then(|| {
+		ReplyMarkup::InlineKeyboard(InlineKeyboardMarkup::new(vec![vec![
+			InlineKeyboardButton::callback(tr(lang, Msg::Reroll), "reroll"),
+		]]))
+	});
+	let reply_id = msg.id;
+	split_and_send(msg, bot, text, keyboard, Some(reply_id)).await
+}
+
+async fn update_slots(msg: Message, bot: RollBot, opts: SlotsOptions) -> Result<Message, BotError> {
+	let user = msg
+		.from()
+		.ok_or_else(|| BotError::EntryFormat("slots: no user".to_owned()))?;
+	let (chat_id, user_id) = (msg.chat.id.0, user.id.0 as i64);
+	let user_name = user.first_name.escape_html();
+	let lang = get_lang(&msg);
+
+	let mut resources = DB.get_resources(chat_id, user_id)?;
+	let text = match opts {
+		SlotsOptions::Show => format_resources(&resources, lang),
+		SlotsOptions::Clear => {
+			resources.clear();
+			tr(lang, Msg::ResourcesCleared)
+		}
+		SlotsOptions::SpellSlots(slots) => {
+			set_spell_slots(&mut resources, &slots);
+			format_resources(&resources, lang)
+		}
+		SlotsOptions::Set { name, max, rest } => {
+			set_resource(&mut resources, &name, max, rest);
+			format_resources(&resources, lang)
+		}
+		SlotsOptions::Use { name, amount } => match spend(&mut resources, &name, amount) {
+			Ok(resource) => format!(
+				"{}\n{}",
+				tr(lang, Msg::Used(amount)),
+				format_resource(resource)
+			),
+			Err(err) => tr(lang, err),
+		},
+	};
+	DB.save_resources(chat_id, user_id, &resources)?;
+
+	let reply_id = msg.id;
+	split_and_send(
+		msg,
+		bot,
+		&format!("<b>{user_name}</b>\n{text}"),
+		None,
+		Some(reply_id),
+	)
+	.await
+}
+
+async fn take_rest(msg: Message, bot: RollBot, rest_kind: Rest) -> Result<Message, BotError> {
+	let user = msg
+		.from()
+		.ok_or_else(|| BotError::EntryFormat("rest: no user".to_owned()))?;
+	let (chat_id, user_id) = (msg.chat.id.0, user.id.0 as i64);
+	let user_name = user.first_name.escape_html();
+	let lang = get_lang(&msg);
+
+	let mut resources = DB.get_resources(chat_id, user_id)?;
+	rest(&mut resources, rest_kind);
+	DB.save_resources(chat_id, user_id, &resources)?;
+
+	let heading = match rest_kind {
+		Rest::Short => Msg::ShortRest(user_name),
+		Rest::Long => Msg::LongRest(user_name),
+	};
+	let text = format!(
+		"{}\n{}",
+		tr(lang, heading),
+		format_resources(&resources, lang)
+	);
+	let reply_id = msg.id;
+	split_and_send(msg, bot, &text, None, Some(reply_id)).await
+}
+
+async fn update_hp(msg: Message, bot: RollBot, opts: HpOptions) -> Result<Message, BotError> {
+	let chat_id = msg.chat.id.0;
+	let text = DB.update_combatants(chat_id, |combatants| {
+		let index = match combatants.iter().position(|c| c.matches(&opts.name)) {
+			Some(index) => index,
+			// Setting HP of an unknown combatant adds it, e.g. a player character
+			None => match opts.change {
+				HpChange::Set(hp) => {
+					combatants.push(Combatant {
+						name: opts.name.clone(),
+						hp,
+						max_hp: hp,
+						conditions: Vec::new(),
+					});
+					combatants.len() - 1
+				}
+				HpChange::Delta(_) => return None,
+			},
+		};
+
+		let combatant = &mut combatants[index];
+		match opts.change {
+			HpChange::Delta(delta) => {
+				combatant.apply(delta);
+			}
+			HpChange::Set(hp) => combatant.set_hp(hp),
+		}
+		Some(format_combatant(combatant))
+	})?;
+	match text {
+		Some(text) => send_combat_reply(msg, bot, text).await,
+		None => send_not_in_combat(msg, bot, &opts.name).await,
+	}
+}
+
+async fn update_condition(
+	msg: Message,
+	bot: RollBot,
+	opts: CondOptions,
+) -> Result<Message, BotError> {
+	let chat_id = msg.chat.id.0;
+	let text = DB.update_combatants(chat_id, |combatants| {
+		let combatant = combatants.iter_mut().find(|c| c.matches(&opts.name))?;
+		if opts.add {
+			if !combatant.conditions.contains(&opts.condition) {
+				combatant.conditions.push(opts.condition.clone());
+			}
+		} else {
+			combatant.conditions.retain(|c| *c != opts.condition);
+		}
+		Some(format_combatant(combatant))
+	})?;
+	match text {
+		Some(text) => send_combat_reply(msg, bot, text).await,
+		None => send_not_in_combat(msg, bot, &opts.name).await,
+	}
+}
+
+async fn show_combat(msg: Message, bot: RollBot, opts: CombatOptions) -> Result<Message, BotError> {
+	let chat_id = msg.chat.id.0;
+	let lang = get_lang(&msg);
+	let text = match opts {
+		CombatOptions::Show => format_board(&DB.get_combatants(chat_id)?, lang),
+		CombatOptions::End => {
+			DB.end_combat(chat_id)?;
+			tr(lang, Msg::CombatOver)
+		}
+	};
+	send_combat_reply(msg, bot, text).await
+}
+
+async fn set_lang(msg: Message, bot: RollBot, lang: Option<Lang>) -> Result<Message, BotError> {
+	let allowed = lang.is_none() || can_change_settings(&bot, &msg.chat, msg.from()).await?;
+	let text = match lang {
+		Some(_) if !allowed => tr(get_lang(&msg), Msg::OnlyChatAdmins),
+		Some(lang) => {
+			let mut settings = DB.get_settings(msg.chat.id.0)?;
+			settings.lang = Some(lang);
+			DB.save_settings(msg.chat.id.0, &settings)?;
+			tr(lang, Msg::NowISpeak(lang))
+		}
+		None => {
+			let lang = get_lang(&msg);
+			tr(lang, Msg::ISpeak(lang))
+		}
+	};
+	let reply_id = msg.id;
+	split_and_send(msg, bot, &text, None, Some(reply_id)).await
+}
+
+// Data management for the bot operators
+async fn admin(msg: Message, bot: RollBot, cmd: AdminCommand) -> Result<Message, BotError> {
+	lazy_static! {
+		// Comma-separated Telegram user ids
+		static ref ADMINS: Vec<u64> = env::var("ROLL_BOT_ADMINS")
+			.unwrap_or_default()
+			.split(',')
+			.filter_map(|id| id.trim().parse().ok())
+			.collect();
+	}
+
+	let reply_id = msg.id;
+	if !msg.from().is_some_and(|user| ADMINS.contains(&user.id.0)) {
+		let text = tr(get_lang(&msg), Msg::OnlyBotAdmins);
+		return split_and_send(msg, bot, &text, None, Some(reply_id)).await;
+	}
+
+	// Only the bot admins see these, so they stay in English like the update logs
+	let text = match cmd {
+		AdminCommand::Update => match update::spawn_update(true) {
+			Ok(()) => "Updating, check <code>/admin status</code> later".to_owned(),
+			Err(_) => "Another update is already running, check <code>/admin status</code> later"
+				.to_owned(),
+		},
+		AdminCommand::Status => format_update_status(update::last_status()),
+		AdminCommand::Rollback => match update::rollback() {
+			Ok(0) => "There is nothing to roll back".to_owned(),
+			Ok(swapped) => format!(
+				"Rolled back {swapped} collections. The next daily update will fetch the newest data again"
+			),
+			Err(err) => format!("Rollback failed: {}", err.to_string().escape_html()),
+		},
+	};
+	split_and_send(msg, bot, &text, None, Some(reply_id)).await
+}
+
+// Sends the messages logged for the user as a JSON file, see `/mydata`
+async fn export_user_data(msg: Message, bot: RollBot) -> Result<Message, BotError> {
+	let reply_id = msg.id;
+	let lang = get_lang(&msg);
+	if !msg.chat.is_private() {
+		let text = tr(lang, Msg::MyDataInPrivate);
+		return split_and_send(msg, bot, &text, None, Some(reply_id)).await;
+	}
+
+	let messages = match msg.from() {
+		Some(user) => DB.get_user_log(user.id.0 as i64)?,
+		None => Vec::new(),
+	};
+	if messages.is_empty() {
+		let text = tr(lang, Msg::NothingLogged);
+		return split_and_send(msg, bot, &text, None, Some(reply_id)).await;
+	}
+
+	let caption = tr(lang, Msg::LoggedMessages(messages.len()));
+	let export = messages
+		.into_iter()
+		.map(|mut doc| {
+			// It's either the user's own id or a meaningless hash
+			doc.remove("user_id");
+			Bson::Document(doc).into()
+		})
+		.collect::<Vec<JsonValue>>();
+	let file = InputFile::memory(format!("{:#}", JsonValue::Array(export)).into_bytes())
+		.file_name("mydata.json");
+	let mut m = bot
+		.send_document(msg.chat.id, file)
+		.caption(caption)
+		.reply_to_message_id(reply_id);
+	if let Some(thread_id) = msg.thread_id {
+		m = m.message_thread_id(thread_id);
+	}
+	Ok(m.await?)
+}
+
+// Deletes the messages logged for the user, see `/forgetme`
+async fn forget_user(msg: Message, bot: RollBot) -> Result<Message, BotError> {
+	let reply_id = msg.id;
+	let lang = get_lang(&msg);
+	let deleted = match msg.from() {
+		Some(user) => DB.forget_user(user.id.0 as i64)?,
+		None => 0,
+	};
+	let text = match deleted {
+		0 => tr(lang, Msg::NothingLogged),
+		deleted => tr(lang, Msg::DeletedMessages(deleted)),
+	};
+	split_and_send(msg, bot, &text, None, Some(reply_id)).await
+}
+
+async fn update_settings(
+	msg: Message,
+	bot: RollBot,
+	opts: SettingsOptions,
+) -> Result<Message, BotError> {
+	let chat_id = msg.chat.id.0;
+	let mut settings = DB.get_settings(chat_id)?;
+	if let SettingsOptions::Set { key, value } = opts {
+		if !can_change_settings(&bot, &msg.chat, msg.from()).await? {
+			let text = tr(get_lang(&msg), Msg::OnlyChatAdmins);
+			let reply_id = msg.id;
+			return split_and_send(msg, bot, &text, None, Some(reply_id)).await;
+		}
+		// The value is checked by the parser
+		let _ = settings.set(&key, &value);
+		// Only the loaded packs, their list is not known to the parser.
+		// Packs removed from the configuration don't block the other settings
+		let packs = homebrew_packs();
+		let unknown = settings
+			.homebrew
+			.iter()
+			.filter(|source| key == "homebrew" && !packs.iter().any(|(pack, _)| pack == *source))
+			.map(|source| source.escape_html())
+			.collect::<Vec<_>>();
+		if !unknown.is_empty() {
+			let text = tr(get_lang(&msg), Msg::UnknownHomebrew(unknown.join(", ")));
+			let reply_id = msg.id;
+			return split_and_send(msg, bot, &text, None, Some(reply_id)).await;
+		}
+		DB.save_settings(chat_id, &settings)?;
+	}
+
+	let lang = get_lang(&msg);
+	let reply_id = msg.id;
+	split_and_send(
+		msg,
+		bot,
+		&settings.format(lang, &homebrew_packs()),
+		Some(ReplyMarkup::InlineKeyboard(settings_keyboard(
+			&settings, lang,
+		))),
+		Some(reply_id),
+	)
+	.await
+}
+
+// Callback data is "settings <key>", the menu is edited in place
+async fn cycle_setting(msg: Message, bot: RollBot, user: &User, key: &str) -> Result<(), BotError> {
+	if !can_change_settings(&bot, &msg.chat, Some(user)).await? {
+		return Ok(());
+	}
+	let chat_id = msg.chat.id.0;
+	let mut settings = DB.get_settings(chat_id)?;
+	settings.cycle(key).map_err(|_| BotError::BadCallback)?;
+	DB.save_settings(chat_id, &settings)?;
+
+	let lang = get_user_lang(msg.chat.id, Some(user));
+	bot.edit_message_text(
+		msg.chat.id,
+		msg.id,
+		settings.format(lang, &homebrew_packs()),
+	)
+	.parse_mode(ParseMode::Html)
+	.reply_markup(settings_keyboard(&settings, lang))
+	.await?;
+	Ok(())
+}
+
+fn settings_keyboard(settings: &Settings, lang: Lang) -> InlineKeyboardMarkup {
+	InlineKeyboardMarkup::new(settings.buttons(lang).into_iter().map(|(text, key)| {
+		vec![InlineKeyboardButton::callback(
+			text,
+			format!("settings {key}"),
+		)]
+	}))
+}
+
+// Anyone can change the settings of a private chat, only admins can do it in groups
+async fn can_change_settings(
+	bot: &RollBot,
+	chat: &Chat,
+	user: Option<&User>,
+) -> Result<bool, BotError> {
+	let Some(user) = user else {
+		return Ok(false);
+	};
+	if chat.is_private() {
+		return Ok(true);
+	}
+	let member = bot.get_chat_member(chat.id, user.id).await?;
+	Ok(member.is_privileged())
+}
+
+async fn send_not_in_combat(msg: Message, bot: RollBot, name: &str) -> Result<Message, BotError> {
+	let text = tr(
+		get_lang(&msg),
+		Msg::NotInCombat(format!("<b>{}</b>", escape_name(name))),
+	);
+	send_combat_reply(msg, bot, text).await
+}
+
+// Conditions are turned into buttons with their rules, the board is split by `split_and_send`
+async fn send_combat_reply(
+	msg: Message,
+	bot: RollBot,
+	mut text: String,
+) -> Result<Message, BotError> {
+	let mut keyboard = InlineKeyboardMarkup::default();
+	replace_string_links(&mut text, None, get_lang(&msg), &mut keyboard);
+	keyboard.inline_keyboard = keyboard.inline_keyboard.into_iter().unique().collect();
+
+	let reply_id = msg.id;
+	split_and_send(
+		msg,
+		bot,
+		&text,
+		Some(ReplyMarkup::InlineKeyboard(keyboard)),
+		Some(reply_id),
+	)
+	.await
+}
+
+async fn roll_table(
+	msg: Message,
+	bot: RollBot,
+	lookup_item: &Collection,
+	index: usize,
+	arg: &str,
+) -> Result<Message, BotError> {
+	let table = lookup_item
+		.collections
+		.iter()
+		.find_map(|collection| DB.get_item(collection, arg).ok().flatten())
+		.and_then(|item| item.get_rollable_tables().into_iter().nth(index))
+		.ok_or_else(|| {
+			BotError::EntryFormat(format!(
+				"{} table #{index}: {arg}",
+				lookup_item.get_default_command()
+			))
+		})?;
+
+	let lang = get_lang(&msg);
+	let rolls_on = match table.caption.as_deref() {
+		Some(caption) => tr(lang, Msg::RollsOn(caption.to_owned())),
+		None => tr(lang, Msg::RollsOnTheTable),
+	};
+	let mut reply_msg = format!(
+		"<b>{} {rolls_on}:</b>\n{}",
+		msg.from()
+			.map(|user| user.first_name.escape_html())
+			.unwrap_or_default(),
+		table.roll()?
+	);
+
+	let mut keyboard = InlineKeyboardMarkup::default();
+	replace_string_links(&mut reply_msg, None, lang, &mut keyboard);
+	keyboard = keyboard.append_row(vec![InlineKeyboardButton::callback(
+		tr(lang, Msg::RollAgain),
+		format!(
+			"/rolltable {} {index} {arg}",
+			lookup_item.get_default_command()
+		),
+	)]);
+
+	let reply_id = msg.id;
+	split_and_send(
+		msg,
+		bot,
+		&reply_msg,
+		Some(ReplyMarkup::InlineKeyboard(keyboard)),
+		Some(reply_id),
+	)
+	.await
+}
+
+fn append_table_buttons(
+	item: &Document,
+	lookup_item: &Collection,
+	lang: Lang,
+	keyboard: &mut InlineKeyboardMarkup,
+) {
+	let Ok(name_source) = item.get_str("name_source") else {
+		return;
+	};
+	for (index, table) in item.get_rollable_tables().into_iter().enumerate() {
+		let data = format!(
+			"/rolltable {} {index} {name_source}",
+			lookup_item.get_default_command()
+		);
+		if data.len() > CALLBACK_DATA_LIMIT {
+			warn!("Table button data is too long: {data}");
+			continue;
+		}
+		let text = match table.caption {
+			Some(caption) => tr(lang, Msg::RollOn(caption)),
+			None => tr(lang, Msg::RollOnThisTable),
+		};
+		push_button(keyboard, InlineKeyboardButton::callback(text, data));
+	}
+}
+
+// The scaled dice of `/spell <name> @<level>`, the text only has them in bold
+fn append_scaling_buttons(item: &Document, level: i64, keyboard: &mut InlineKeyboardMarkup) {
+	let name = item.get_name().unwrap_or_default();
+	for scaled in item.get_scaled_dice(level) {
+		let text = match scaled.label {
+			Some(label) => format!("{name}: {} {label}", scaled.dice),
+			None => format!("{name}: {}", scaled.dice),
+		};
+		append_roll_button(keyboard, &text, &scaled.dice, &name);
+	}
+}
+
+// `attack` is the name of the closest entry, like "Bite" for the dice of a monster action
+fn replace_links(
+	doc: &mut Document,
+	attack: Option<&str>,
+	lang: Lang,
+	keyboard: &mut InlineKeyboardMarkup,
+) {
+	let name = doc.get_str("name").ok().map(str::to_owned);
+	let attack = name.as_deref().or(attack);
 	let keys: Vec<String> = doc.keys().cloned().collect();
 	for key in keys {
 		let entry = doc.entry(key).or_insert(Bson::String("".to_string()));
-		replace_bson_links(entry, keyboard);
+		replace_bson_links(entry, attack, lang, keyboard);
 	}
 }
 
-fn replace_bson_links(b: &mut Bson, keyboard: &mut InlineKeyboardMarkup) {
+fn replace_bson_links(
+	b: &mut Bson,
+	attack: Option<&str>,
+	lang: Lang,
+	keyboard: &mut InlineKeyboardMarkup,
+) {
 	match b {
 		Bson::String(val) => {
-			replace_string_links(val, keyboard);
+			replace_string_links(val, attack, lang, keyboard);
 		}
 		Bson::Array(arr) => {
 			for val in arr {
-				replace_bson_links(val, keyboard);
+				replace_bson_links(val, attack, lang, keyboard);
 			}
 		}
 		Bson::Document(doc) => {
-			replace_links(doc, keyboard);
+			replace_links(doc, attack, lang, keyboard);
 		}
 		_ => {}
 	}
 }
 
-fn replace_string_links(text: &mut String, keyboard: &mut InlineKeyboardMarkup) {
+fn replace_string_links(
+	text: &mut String,
+	attack: Option<&str>,
+	lang: Lang,
+	keyboard: &mut InlineKeyboardMarkup,
+) {
 	lazy_static! {
 		static ref LINK_REGEX: Regex =
 			Regex::new(r"\{@(?P<cmd>\w+)(?:\s+(?P<arg1>.+?))?(?:\|(?P<arg2>.*?))?(?:\|(?P<arg3>.*?))?(?:\|(?P<arg4>.*?))?\}(?P<bonus>\d+)?")
@@ -534,17 +1819,43 @@ fn replace_string_links(text: &mut String, keyboard: &mut InlineKeyboardMarkup)
 
 		match cmd {
 			"i" => format!("• {nice_str}"),
-			"hit" => format!("+{name}"),
+			"hit" => {
+				let bonus = if name.starts_with('-') {
+					name.to_owned()
+				} else {
+					format!("+{name}")
+				};
+				append_roll_button(
+					keyboard,
+					&attack_label(attack, &tr(lang, Msg::ToHit(bonus.clone()))),
+					&format!("d20{bonus}"),
+					tr(lang, Msg::ToHit(attack.unwrap_or_default().to_owned())).trim(),
+				);
+				bonus
+			}
 			"h" => match caps.name("bonus") {
 				Some(bonus) => format!("<b>{}</b>", bonus.as_str()),
 				None => "".to_owned(),
 			},
 			"atk" => "".to_owned(),
 			"scaledamage" => format!("<b>{nice_str}</b>"),
-			"dice" | "damage" => {
-				let roll_results = roll_results(name).unwrap();
-				let roll = roll_results.get(0).unwrap();
-				format!("<b>{}</b> <code>[{}]</code> ", name, roll.expression.calc())
+			"dice" => {
+				append_roll_button(
+					keyboard,
+					&attack_label(attack, name),
+					name,
+					attack.unwrap_or_default(),
+				);
+				format!("<b>{name}</b>")
+			}
+			"damage" => {
+				append_roll_button(
+					keyboard,
+					&attack_label(attack, &tr(lang, Msg::Damage(name.to_owned()))),
+					name,
+					tr(lang, Msg::Damage(attack.unwrap_or_default().to_owned())).trim(),
+				);
+				format!("<b>{name}</b>")
 			}
 			"recharge" => {
 				if name.is_empty() {
@@ -556,26 +1867,66 @@ fn replace_string_links(text: &mut String, keyboard: &mut InlineKeyboardMarkup)
 			_ => {
 				let nice_str = other.or(display_text).unwrap_or(name);
 				if let Some(item) = COMMANDS.get(cmd) {
-					let mut kb = keyboard.clone();
-					kb = kb.append_row(vec![InlineKeyboardButton::callback(
-						format!("{}: {}", item.get_default_command(), nice_str),
-						format!(
-							"{} {}{}",
-							item.get_default_command(),
-							name,
-							source
-								.map(|source| format!(" ({source})"))
-								.unwrap_or_default()
+					push_button(
+						keyboard,
+						InlineKeyboardButton::callback(
+							format!("{}: {}", item.get_default_command(), nice_str),
+							format!(
+								"{} {}{}",
+								item.get_default_command(),
+								name,
+								source
+									.map(|source| format!(" ({source})"))
+									.unwrap_or_default()
+							),
 						),
-					)]);
-					*keyboard = kb;
+					);
 				}
 				format!("<i>{nice_str}</i>")
 			}
 		}
 	});
 
-	std::mem::swap(text, &mut result.into_owned());
+	*text = result.into_owned();
+}
+
+// Dice in entries are rolled on demand by the clicker, see `process_callback_query`
+fn append_roll_button(
+	keyboard: &mut InlineKeyboardMarkup,
+	text: &str,
+	expression: &str,
+	comment: &str,
+) {
+	let data = format!("/roll {expression} {comment}");
+	let data = if data.len() > CALLBACK_DATA_LIMIT {
+		format!("/roll {expression}")
+	} else {
+		data
+	};
+	if data.len() > CALLBACK_DATA_LIMIT {
+		warn!("Roll button data is too long: {data}");
+		return;
+	}
+	push_button(
+		keyboard,
+		InlineKeyboardButton::callback(format!("🎲 {text}"), data.trim_end().to_owned()),
+	);
+}
+
+// "Bite: +5 to hit", so the rolls of different attacks can be told apart
+fn attack_label(attack: Option<&str>, text: &str) -> String {
+	match attack {
+		Some(attack) => format!("{attack}: {text}"),
+		None => text.to_owned(),
+	}
+}
+
+// One button per row, repeated links get one button
+fn push_button(keyboard: &mut InlineKeyboardMarkup, button: InlineKeyboardButton) {
+	let row = vec![button];
+	if keyboard.inline_keyboard.len() < BUTTONS_LIMIT && !keyboard.inline_keyboard.contains(&row) {
+		keyboard.inline_keyboard.push(row);
+	}
 }
 
 // Splitting messages that are too long
@@ -742,6 +2093,44 @@ fn split2(text: &str, max_len: usize) -> Vec<String> {
 	result
 }
 
+#[test]
+fn test_replace_links() {
+	let mut monster = bson::doc! {
+		"name": "Owlbear",
+		"action": [(bson::doc! {
+			"name": "Beak",
+			"entries": ["{@atk mw} {@hit 7} to hit, reach 5 ft. {@h}10 ({@damage 1d10 + 5}) piercing damage."]
+		})]
+	};
+	let mut keyboard = InlineKeyboardMarkup::default();
+	replace_links(&mut monster, None, Lang::En, &mut keyboard);
+	let buttons = keyboard
+		.inline_keyboard
+		.iter()
+		.map(|row| row[0].text.as_str())
+		.collect::<Vec<_>>();
+	assert_eq!(
+		buttons,
+		vec!["🎲 Beak: +7 to hit", "🎲 Beak: 1d10 + 5 damage"]
+	);
+	assert_eq!(
+		keyboard.inline_keyboard[0][0].kind,
+		teloxide::types::InlineKeyboardButtonKind::CallbackData(
+			"/roll d20+7 Beak to hit".to_owned()
+		)
+	);
+
+	let mut text = "{@dice 1d6} ".repeat(BUTTONS_LIMIT + 10)
+		+ &(0..BUTTONS_LIMIT + 10)
+			.map(|i| format!("{{@dice {i}d6}}"))
+			.collect::<String>();
+	let mut keyboard = InlineKeyboardMarkup::default();
+	replace_string_links(&mut text, None, Lang::En, &mut keyboard);
+	assert_eq!(keyboard.inline_keyboard.len(), BUTTONS_LIMIT);
+	assert_eq!(keyboard.inline_keyboard[0][0].text, "🎲 1d6");
+	assert_eq!(keyboard.inline_keyboard[1][0].text, "🎲 0d6");
+}
+
 #[test]
 fn test_split2_simple0() {
 	let parts = split2("123", 3);
@@ -0,0 +1,244 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+
+use serde_json::Value as JsonValue;
+use tokio::task;
+
+use crate::{
+	collection::COLLECTIONS,
+	db,
+	fetch::{self, FetchReport},
+	get_unix_time, DB,
+};
+
+lazy_static! {
+	static ref LAST_UPDATE: RwLock<Option<UpdateStatus>> = RwLock::new(None);
+}
+
+// Daily and `/admin` updates must not overlap, they stage and swap the same collections
+static UPDATING: AtomicBool = AtomicBool::new(false);
+
+// Outcome of the last update or rollback, see `/admin status`
+#[derive(Debug, Clone)]
+pub struct UpdateStatus {
+	pub timestamp: u64,
+	pub forced: bool,
+	pub outcome: String,
+	pub mirror: Option<String>,
+	pub ver: Option<String>,
+	pub report: Option<FetchReport>,
+}
+
+impl UpdateStatus {
+	fn new(forced: bool) -> Self {
+		Self {
+			timestamp: get_unix_time(),
+			forced,
+			outcome: String::new(),
+			mirror: None,
+			ver: None,
+			report: None,
+		}
+	}
+}
+
+// Releases `UPDATING` even if the update panics
+struct UpdateGuard;
+
+impl UpdateGuard {
+	fn acquire() -> Result<Self, Box<dyn Error + Send + Sync>> {
+		if UPDATING.swap(true, Ordering::SeqCst) {
+			return Err("Another update is already running".into());
+		}
+		Ok(Self)
+	}
+}
+
+impl Drop for UpdateGuard {
+	fn drop(&mut self) {
+		UPDATING.store(false, Ordering::SeqCst);
+	}
+}
+
+pub fn last_status() -> Option<UpdateStatus> {
+	LAST_UPDATE.read().unwrap().clone()
+}
+
+// `force` updates even if the database already has the newest version
+pub async fn update(force: bool) -> Result<(), Box<dyn Error + Send + Sync>> {
+	let guard = UpdateGuard::acquire()?;
+	run_update(guard, force).await
+}
+
+// Same as `update`, but returns right away. The outcome ends up in `last_status`
+pub fn spawn_update(force: bool) -> Result<(), Box<dyn Error + Send + Sync>> {
+	// Acquired here, so two updates in a row don't both start
+	let guard = UpdateGuard::acquire()?;
+	task::spawn(async move {
+		if let Err(err) = run_update(guard, force).await {
+			warn!("Update failed: {}", err);
+		}
+	});
+	Ok(())
+}
+
+async fn run_update(_guard: UpdateGuard, force: bool) -> Result<(), Box<dyn Error + Send + Sync>> {
+	let mut status = UpdateStatus::new(force);
+	let result = try_update(force, &mut status).await;
+	if let Err(err) = &result {
+		status.outcome = format!("Failed: {err}");
+	}
+	status.timestamp = get_unix_time();
+	*LAST_UPDATE.write().unwrap() = Some(status);
+	result
+}
+
+// Brings back the data that was replaced by the last update
+pub fn rollback() -> Result<usize, Box<dyn Error + Send + Sync>> {
+	let _guard = UpdateGuard::acquire()?;
+	let swapped = DB.rollback()?;
+	let mut status = UpdateStatus::new(true);
+	status.outcome = format!("Rolled back {swapped} collections");
+	status.ver = DB.get_version().ok().flatten();
+	*LAST_UPDATE.write().unwrap() = Some(status);
+	Ok(swapped)
+}
+
+async fn try_update(
+	force: bool,
+	status: &mut UpdateStatus,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+	let cur_ver = DB.get_version().ok().flatten();
+	let mirrors = fetch::check_mirrors(fetch::DataSource::from_env(), cur_ver.as_deref()).await;
+	let Some(ver) = mirrors.first().and_then(fetch::Mirror::ver) else {
+		return Err("No healthy data mirrors left".into());
+	};
+	status.ver = Some(ver.to_owned());
+
+	let homebrew = fetch::homebrew_from_env();
+	if !force && !should_update(cur_ver.as_ref(), Some(ver)) && !homebrew_changed(&homebrew) {
+		info!(
+			"Skipping update, db is running the newest version: {:?}",
+			ver
+		);
+		status.outcome = "Skipped, the data is up to date".to_owned();
+		return Ok(());
+	}
+
+	let mut report = FetchReport::default();
+	let mut homebrew = fetch::fetch_homebrew(&homebrew, &mut report).await;
+	// Users keep searching the old data until everything is saved.
+	// `/admin update` may replace a collection with a much smaller one
+	let mut staging = db::Staging::new().allow_shrink(force);
+	// Collections that failed on a mirror are tried again on the next one
+	let mut pending = COLLECTIONS.iter().collect::<Vec<_>>();
+	let mut failed = Vec::new();
+	let mut used = Vec::new();
+	let mut official = Vec::new();
+	for mirror in &mirrors {
+		if pending.is_empty() {
+			break;
+		}
+		info!("Updating from {}", mirror.source);
+		let (data, fetched) = match fetch::fetch(&mirror.source, &pending).await {
+			Ok(data) => data,
+			Err(err) => {
+				warn!("Mirror {} failed: {}", mirror.source, err);
+				continue;
+			}
+		};
+		info!("{}", fetched);
+		used.push(mirror.source.to_string());
+		pending.retain(|collection| {
+			fetched
+				.failed
+				.iter()
+				.any(|(command, _)| command == collection.get_default_command())
+		});
+		failed = fetched.failed;
+		official.extend(data);
+	}
+	if used.is_empty() {
+		return Err("Every data mirror failed".into());
+	}
+	// Homebrew is checked against every official source before anything is staged
+	let sources = official
+		.iter()
+		.flat_map(|(_, items)| items)
+		.filter_map(|item| item.get("source").and_then(JsonValue::as_str))
+		.collect();
+	fetch::drop_official_sources(&mut homebrew, &sources, &mut report);
+	for (collection, mut items) in official {
+		items.extend(homebrew.remove(&collection).unwrap_or_default());
+		stage(&mut staging, &mut report, collection, items);
+	}
+	status.mirror = Some(used.join(", "));
+	report.failed.extend(failed);
+
+	// Collections that only homebrew has. A failed official collection is not replaced with its homebrew part
+	for (collection, items) in homebrew {
+		if pending.is_empty() || collection.starts_with('_') {
+			stage(&mut staging, &mut report, collection, items);
+		}
+	}
+	// Collections that failed on every mirror keep the old data until the next version,
+	// `/admin update` tries them again. The version is committed with the data,
+	// so a rollback brings back the old version too
+	let changelog = mirrors
+		.into_iter()
+		.next()
+		.map(|mirror| mirror.changelog)
+		.unwrap_or_default();
+	stage(
+		&mut staging,
+		&mut report,
+		db::VER_COLLECTION_NAME.to_owned(),
+		changelog,
+	);
+	DB.commit(staging)?;
+	report.loaded.sort();
+	info!("{}", report);
+	status.outcome = if report.failed.is_empty() {
+		"Updated".to_owned()
+	} else {
+		"Partially updated, the failed collections keep the old data".to_owned()
+	};
+	status.report = Some(report);
+	Ok(())
+}
+
+fn stage(
+	staging: &mut db::Staging,
+	report: &mut FetchReport,
+	collection: String,
+	items: Vec<JsonValue>,
+) {
+	let count = items.len();
+	match DB.stage_collection(staging, items, &collection) {
+		Ok(()) => report.loaded.push((collection, count)),
+		Err(err) => {
+			error!("Failed to save {}: {}", collection, err);
+			report.failed.push((collection, err.to_string()));
+		}
+	}
+}
+
+// Homebrew packs are not versioned, they are reloaded when the configuration changes
+fn homebrew_changed(homebrew: &[String]) -> bool {
+	let loaded = DB
+		.get_all(db::HOMEBREW_COLLECTION_NAME)
+		.unwrap_or_default()
+		.iter()
+		.filter_map(|pack| pack.get_str("location").ok().map(str::to_owned))
+		.collect::<HashSet<_>>();
+	loaded != homebrew.iter().cloned().collect()
+}
+
+fn should_update(cur_ver: Option<&String>, ver: Option<&str>) -> bool {
+	match (cur_ver, ver) {
+		(Some(cur_ver), Some(ver)) => cur_ver != ver,
+		_ => true,
+	}
+}
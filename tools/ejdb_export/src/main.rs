@@ -0,0 +1,40 @@
+use std::env;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use ejdb::bson::Bson;
+use ejdb::query::{Q, QH};
+use ejdb::Database;
+use serde_json::{json, Value as JsonValue};
+
+// Dumps the system collections of an old `roll_bot.ejdb` to `roll_bot.ejdb.jsonl`,
+// the bot imports it on the next start. D&D data is not dumped, it is downloaded again
+fn main() -> Result<(), Box<dyn Error>> {
+	let path = env::args()
+		.nth(1)
+		.unwrap_or_else(|| "roll_bot.ejdb".to_owned());
+	let out = format!("{path}.jsonl");
+	let db = Database::open(path.as_str())?;
+	let collections = db
+		.get_metadata()?
+		.collections()
+		.map(|coll| coll.name().to_owned())
+		.filter(|coll| coll.starts_with('_'))
+		.collect::<Vec<_>>();
+
+	let mut writer = BufWriter::new(File::create(&out)?);
+	for collection in collections {
+		let coll = db.collection(&collection)?;
+		let mut count = 0;
+		for doc in coll.query(Q.empty(), QH.empty()).find()? {
+			let doc: JsonValue = Bson::Document(doc?).into();
+			writeln!(writer, "{}", json!({"collection": collection, "doc": doc}))?;
+			count += 1;
+		}
+		println!("{collection}: {count}");
+	}
+	writer.flush()?;
+	println!("Saved to {out}");
+	Ok(())
+}